@@ -1,32 +1,39 @@
 extern crate minion;
-use minion::Cancellable;
+use minion::{net::CancellableListener, Cancellable};
 use std::{
     io::{self, prelude::*}, net, thread, time,
 };
 
-struct Service(net::TcpListener);
+struct Service(CancellableListener);
 
 impl minion::Cancellable for Service {
     type Error = io::Error;
     fn for_each(&mut self) -> Result<minion::LoopState, Self::Error> {
-        let mut stream = self.0.accept()?.0;
+        let mut stream = match self.0.accept() {
+            Ok((stream, _)) => stream,
+            // cancelled while waiting for the next connection
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {
+                return Ok(minion::LoopState::Break)
+            }
+            Err(e) => return Err(e),
+        };
         write!(stream, "hello!")?;
         Ok(minion::LoopState::Continue)
     }
 }
 
 impl Service {
-    fn new() -> Self {
+    fn new(exit: &minion::Canceller) -> Self {
         let listener = net::TcpListener::bind("127.0.0.1:6556").unwrap();
-        Service(listener)
+        Service(CancellableListener::new(listener, exit).unwrap())
     }
 }
 
 fn main() {
-    let s = Service::new();
+    let exit = minion::Canceller::new();
+    let s = Service::new(&exit);
     eprintln!("server running");
-    let h = s.spawn();
-    let exit = h.canceller();
+    let h = s.builder().canceller(exit.clone()).spawn();
     thread::spawn(move || {
         thread::sleep(time::Duration::from_secs(10));
         eprintln!("server terminating");
@@ -0,0 +1,49 @@
+//! Measures how long it takes for a cancellation to reach an idle service loop.
+//!
+//! Compare the default condition-variable path against the `parker` feature with:
+//!
+//! ```text
+//! cargo run --release --example wake_latency
+//! cargo run --release --example wake_latency --features parker
+//! ```
+use minion::{Cancellable, LoopState};
+use std::time::{Duration, Instant};
+
+struct Idle;
+
+impl Cancellable for Idle {
+    type Error = ();
+    fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+        Ok(LoopState::Continue)
+    }
+}
+
+const ROUNDS: usize = 1000;
+
+fn main() {
+    let mut latencies = Vec::with_capacity(ROUNDS);
+    for _ in 0..ROUNDS {
+        // after its first iteration, the loop sits idle waiting out the throttle interval
+        let h = Idle.builder().throttle(Duration::from_secs(60)).spawn();
+        assert!(h.wait_ready(Duration::from_secs(1)));
+        std::thread::sleep(Duration::from_millis(1));
+
+        let (jh, canceller) = h.into_parts();
+        let jh = jh.expect("spawned loops run on a thread of their own");
+        let start = Instant::now();
+        canceller.cancel();
+        jh.join().unwrap().unwrap();
+        latencies.push(start.elapsed());
+    }
+
+    latencies.sort();
+    let pct = |p: usize| latencies[(ROUNDS - 1) * p / 100];
+    println!(
+        "cancel -> exit over {} rounds: p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+        ROUNDS,
+        pct(50),
+        pct(90),
+        pct(99),
+        latencies[ROUNDS - 1]
+    );
+}
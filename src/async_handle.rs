@@ -0,0 +1,132 @@
+use super::{propagate, Canceller, Handle};
+use std::future::Future;
+use std::ops::Deref;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::thread;
+
+/// A handle to a thread-spawned service loop for use from async code.
+///
+/// An `AsyncHandle` is itself a [`Future`] that resolves to the service loop's result once the
+/// loop exits, so you can `.await` it directly. Like [`Handle`], it dereferences to a
+/// [`Canceller`] for cancelling the loop.
+///
+/// Obtain one with [`Handle::into_async`] from within a Tokio runtime, or by spawning an
+/// [`AsyncCancellable`](crate::AsyncCancellable) with
+/// [`AsyncCancellable::spawn_on`](crate::AsyncCancellable::spawn_on).
+///
+/// ```
+/// # use minion::*;
+/// # struct Service;
+/// # impl Cancellable for Service {
+/// #     type Error = ();
+/// #     fn for_each(&mut self) -> Result<LoopState, ()> { Ok(LoopState::Continue) }
+/// # }
+/// let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+/// rt.block_on(async {
+///     let h = Service.spawn().into_async();
+///     // cancel the service when the application shuts down
+///     h.cancel_on(async { /* e.g., tokio::signal::ctrl_c().await */ });
+///     h.await.unwrap();
+/// });
+/// ```
+pub struct AsyncHandle<E, T = ()> {
+    canceller: Canceller,
+    join: tokio::task::JoinHandle<thread::Result<Result<T, E>>>,
+}
+
+impl<E: Send + 'static, T: Send + 'static> Handle<E, T> {
+    /// Turn this handle into one that can be awaited from async code.
+    ///
+    /// The blocking wait for the service loop is moved onto Tokio's blocking thread pool.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from outside of a Tokio runtime.
+    pub fn into_async(self) -> AsyncHandle<E, T> {
+        let executor = self.executor;
+        AsyncHandle {
+            canceller: self.canceller,
+            join: tokio::task::spawn_blocking(move || executor.join()),
+        }
+    }
+}
+
+impl<E, T> AsyncHandle<E, T> {
+    pub(crate) fn new(
+        canceller: Canceller,
+        join: tokio::task::JoinHandle<thread::Result<Result<T, E>>>,
+    ) -> Self {
+        AsyncHandle { canceller, join }
+    }
+
+    /// Get another handle for cancelling the service loop.
+    pub fn canceller(&self) -> Canceller {
+        self.canceller.clone()
+    }
+
+    /// Cancel the service loop once `signal` resolves.
+    ///
+    /// This is handy for tying the service's lifetime to an application-wide shutdown signal such
+    /// as `tokio::signal::ctrl_c()`. If `signal` never resolves, the service is not cancelled.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from outside of a Tokio runtime.
+    pub fn cancel_on<F>(&self, signal: F)
+    where
+        F: Future + Send + 'static,
+    {
+        let canceller = self.canceller.clone();
+        tokio::spawn(async move {
+            signal.await;
+            canceller.cancel();
+        });
+    }
+}
+
+impl<E, T> Future for AsyncHandle<E, T> {
+    type Output = Result<T, E>;
+
+    /// If the service loop returns an error, the future resolves to it in the `Err` value.
+    /// If the service loop panics, polling the future will also panic with the same error.
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.join)
+            .poll(cx)
+            .map(|r| propagate(r.expect("service loop waiter was cancelled")))
+    }
+}
+
+impl<E, T> Deref for AsyncHandle<E, T> {
+    type Target = Canceller;
+    fn deref(&self) -> &Self::Target {
+        &self.canceller
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    struct Spin;
+
+    impl Cancellable for Spin {
+        type Error = ();
+        fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+            thread::yield_now();
+            Ok(LoopState::Continue)
+        }
+    }
+
+    #[test]
+    fn it_cancels_on_signal() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let h = Spin.spawn().into_async();
+            h.cancel_on(async {});
+            h.await.unwrap();
+        });
+    }
+}
@@ -0,0 +1,271 @@
+use super::{AsyncHandle, CancelListener, Canceller, ErrorDisposition, ExitReason, LoopState};
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::task::Poll;
+use std::time::Duration;
+
+/// The future returned by [`AsyncCancellable::for_each`].
+pub type IterationFuture<'a, E> = Pin<Box<dyn Future<Output = Result<LoopState, E>> + Send + 'a>>;
+
+/// A service loop whose iterations are asynchronous.
+///
+/// This is the async counterpart to [`Cancellable`](crate::Cancellable), for services that live
+/// inside a Tokio runtime. Rather than occupying a thread of its own, the loop is driven as a
+/// task with [`AsyncCancellable::spawn_on`]:
+///
+/// ```
+/// # use minion::*;
+/// struct Ticker(usize);
+/// impl AsyncCancellable for Ticker {
+///     type Error = ();
+///     fn for_each(&mut self) -> IterationFuture<'_, ()> {
+///         Box::pin(async move {
+///             self.0 += 1;
+///             tokio::task::yield_now().await;
+///             Ok(LoopState::Continue)
+///         })
+///     }
+/// }
+///
+/// let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+/// let h = Ticker(0).spawn_on(rt.handle());
+/// rt.block_on(async {
+///     h.cancel();
+///     h.await.unwrap();
+/// });
+/// ```
+///
+/// As with threaded service loops, cancellation does not interrupt a running
+/// [`AsyncCancellable::for_each`]; the loop checks for it between iterations. Errors go through
+/// [`AsyncCancellable::on_error`], and the loop calls [`AsyncCancellable::on_start`] and
+/// [`AsyncCancellable::on_exit`] around its iterations, all just like for a threaded loop.
+/// Services that return [`LoopState::ContinueAfter`] or [`ErrorDisposition::RetryAfter`] must be
+/// spawned on a runtime with its timer enabled.
+pub trait AsyncCancellable {
+    /// Error type for [`AsyncCancellable::for_each`].
+    type Error;
+
+    /// Do one iteration of the service loop.
+    ///
+    /// See [`Cancellable::for_each`](crate::Cancellable::for_each).
+    fn for_each(&mut self) -> IterationFuture<'_, Self::Error>;
+
+    /// Reset the service after [`AsyncCancellable::for_each`] returned [`LoopState::Reset`].
+    ///
+    /// The default implementation does nothing.
+    fn reset(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Decide what the loop does about an error returned by [`AsyncCancellable::for_each`].
+    ///
+    /// See [`Cancellable::on_error`](crate::Cancellable::on_error). The default implementation
+    /// returns [`ErrorDisposition::Abort`], so that every error ends the loop.
+    fn on_error(&mut self, err: Self::Error) -> ErrorDisposition<Self::Error> {
+        ErrorDisposition::Abort(err)
+    }
+
+    /// This method is called by the task before the loop's first iteration.
+    ///
+    /// See [`Cancellable::on_start`](crate::Cancellable::on_start). The default implementation
+    /// does nothing.
+    fn on_start(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// This method is called by the task once the loop has run its last iteration.
+    ///
+    /// See [`Cancellable::on_exit`](crate::Cancellable::on_exit). The default implementation
+    /// does nothing.
+    fn on_exit(&mut self, reason: ExitReason) {
+        let _ = reason;
+    }
+
+    /// Drive the service loop as a task on the runtime behind `rt`, and return a handle to it.
+    ///
+    /// The returned [`AsyncHandle`] can be awaited for the loop's result, and dereferences to a
+    /// [`Canceller`] for stopping it.
+    fn spawn_on(mut self, rt: &tokio::runtime::Handle) -> AsyncHandle<Self::Error>
+    where
+        Self: Sized + Send + 'static,
+        Self::Error: Send + 'static,
+    {
+        let canceller = Canceller::new();
+        let cancelled = canceller.clone();
+        let task = rt.spawn(async move {
+            self.on_start()?;
+            let r = {
+                let mut body = std::pin::pin!(drive(&mut self, &cancelled));
+                // a panicking iteration still gets the service's on_exit called
+                std::future::poll_fn(|cx| {
+                    match panic::catch_unwind(AssertUnwindSafe(|| body.as_mut().poll(cx))) {
+                        Ok(poll) => poll.map(Ok),
+                        Err(panic) => Poll::Ready(Err(panic)),
+                    }
+                })
+                .await
+            };
+            self.on_exit(match r {
+                Ok(Ok(true)) => ExitReason::Finished,
+                Ok(Ok(false)) => ExitReason::Cancelled,
+                Ok(Err(_)) => ExitReason::Failed,
+                Err(_) => ExitReason::Panicked,
+            });
+            crate::propagate(r).map(|_| ())
+        });
+        // resolve the same way a service thread would, including if the loop panics
+        let join = rt.spawn(async move {
+            task.await.map_err(|e| {
+                e.try_into_panic()
+                    .unwrap_or_else(|e| Box::new(e) as Box<dyn std::any::Any + Send>)
+            })
+        });
+        AsyncHandle::new(canceller, join)
+    }
+}
+
+/// Run the service loop until it is cancelled or breaks, and return whether it broke.
+async fn drive<S>(service: &mut S, cancelled: &Canceller) -> Result<bool, S::Error>
+where
+    S: AsyncCancellable + ?Sized,
+{
+    let mut cancel = cancelled.listener();
+    while cancelled.keep_running() {
+        let wait = match service.for_each().await {
+            Ok(LoopState::Continue) | Ok(LoopState::Idle) => continue,
+            Ok(LoopState::ContinueAfter(dur)) => dur,
+            Ok(LoopState::Break) => return Ok(true),
+            Ok(LoopState::Reset) => {
+                service.reset()?;
+                continue;
+            }
+            Err(e) => match service.on_error(e) {
+                ErrorDisposition::Continue => continue,
+                ErrorDisposition::RetryAfter(dur) => dur,
+                ErrorDisposition::Abort(e) => return Err(e),
+            },
+        };
+        sleep(wait, &mut cancel).await;
+    }
+    Ok(false)
+}
+
+/// Wait out `dur`, unless the loop is cancelled first.
+async fn sleep(dur: Duration, cancel: &mut CancelListener) {
+    let mut sleep = std::pin::pin!(tokio::time::sleep(dur));
+    std::future::poll_fn(|cx| {
+        if sleep.as_mut().poll(cx).is_ready() || Pin::new(&mut *cancel).poll(cx).is_ready() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    struct Countdown(usize);
+    impl AsyncCancellable for Countdown {
+        type Error = usize;
+        fn for_each(&mut self) -> IterationFuture<'_, usize> {
+            Box::pin(async move {
+                tokio::task::yield_now().await;
+                match self.0 {
+                    0 => Err(42),
+                    usize::MAX => Ok(LoopState::Continue),
+                    _ => {
+                        self.0 -= 1;
+                        Ok(LoopState::Continue)
+                    }
+                }
+            })
+        }
+    }
+
+    #[test]
+    fn it_runs_as_a_task() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let h = Countdown(3).spawn_on(rt.handle());
+        assert_eq!(rt.block_on(h), Err(42));
+
+        let h = Countdown(usize::MAX).spawn_on(rt.handle());
+        rt.block_on(async {
+            tokio::task::yield_now().await;
+            h.cancel();
+            h.await.unwrap();
+        });
+    }
+
+    struct Sleepy;
+    impl AsyncCancellable for Sleepy {
+        type Error = ();
+        fn for_each(&mut self) -> IterationFuture<'_, ()> {
+            Box::pin(async { Ok(LoopState::ContinueAfter(Duration::from_secs(60))) })
+        }
+    }
+
+    #[test]
+    fn cancel_cuts_delays_short() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let h = Sleepy.spawn_on(rt.handle());
+        rt.block_on(async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            let start = std::time::Instant::now();
+            h.cancel();
+            h.await.unwrap();
+            assert!(start.elapsed() < Duration::from_secs(1));
+        });
+    }
+
+    struct Hooked(Vec<String>, std::sync::mpsc::Sender<Vec<String>>);
+    impl AsyncCancellable for Hooked {
+        type Error = &'static str;
+        fn for_each(&mut self) -> IterationFuture<'_, Self::Error> {
+            Box::pin(async move {
+                match self.0.len() {
+                    1 => Err("transient"),
+                    2 => Err("slow down"),
+                    _ => Err("fatal"),
+                }
+            })
+        }
+        fn on_error(&mut self, e: Self::Error) -> ErrorDisposition<Self::Error> {
+            self.0.push(e.to_string());
+            match e {
+                "transient" => ErrorDisposition::Continue,
+                "slow down" => ErrorDisposition::RetryAfter(Duration::from_millis(1)),
+                _ => ErrorDisposition::Abort(e),
+            }
+        }
+        fn on_start(&mut self) -> Result<(), Self::Error> {
+            self.0.push(String::from("start"));
+            Ok(())
+        }
+        fn on_exit(&mut self, reason: ExitReason) {
+            self.0.push(format!("{:?}", reason));
+            self.1.send(self.0.clone()).unwrap();
+        }
+    }
+
+    #[test]
+    fn it_runs_the_hooks() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let h = Hooked(Vec::new(), tx).spawn_on(rt.handle());
+        assert_eq!(rt.block_on(h), Err("fatal"));
+        let log = rx.recv().unwrap();
+        assert_eq!(log, ["start", "transient", "slow down", "fatal", "Failed"]);
+    }
+}
@@ -0,0 +1,314 @@
+use super::group::Factory;
+use super::{Cancellable, Handle, LoopState, MemberStatus, Stats};
+
+/// Bounds and thresholds that decide when an [`Autoscaler`] adds or removes workers.
+#[derive(Debug, Clone)]
+pub struct ScalePolicy {
+    min: usize,
+    max: usize,
+    queue_per_worker: Option<usize>,
+    scale_up_busy: f64,
+    scale_down_idle: f64,
+}
+
+impl ScalePolicy {
+    /// Keep between `min` and `max` workers running.
+    ///
+    /// By default, the pool grows when its workers are busy in at least 90% of their
+    /// iterations, and shrinks when they are idle in at least 50% of them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is zero or less than `min`.
+    pub fn new(min: usize, max: usize) -> Self {
+        assert!(max > 0 && max >= min, "invalid worker bounds");
+        ScalePolicy {
+            min,
+            max,
+            queue_per_worker: None,
+            scale_up_busy: 0.9,
+            scale_down_idle: 0.5,
+        }
+    }
+
+    /// Grow the pool whenever the queue holds more than `n` items per worker, and only shrink
+    /// it if the remaining workers would still have at most `n` items each.
+    ///
+    /// This only has an effect if the autoscaler is told about the
+    /// [queue depth](Autoscaler::queue_depth).
+    pub fn queue_per_worker(mut self, n: usize) -> Self {
+        self.queue_per_worker = Some(n);
+        self
+    }
+
+    /// Grow the pool when at least `ratio` of the workers' recent iterations were busy.
+    pub fn scale_up_when_busy(mut self, ratio: f64) -> Self {
+        self.scale_up_busy = ratio;
+        self
+    }
+
+    /// Shrink the pool when at least `ratio` of the workers' recent iterations were idle.
+    pub fn scale_down_when_idle(mut self, ratio: f64) -> Self {
+        self.scale_down_idle = ratio;
+        self
+    }
+}
+
+/// A service that grows and shrinks a pool of identical workers to match their load.
+///
+/// Every iteration of the autoscaler looks at how busy the workers have been since the previous
+/// iteration (see [`Activity`](crate::Activity)) and, optionally, at how much work is queued up
+/// for them, and then adds or retires at most one worker within the bounds of its
+/// [`ScalePolicy`]. Spawn it with a [throttle](crate::ServiceBuilder::throttle) to control how
+/// often it re-evaluates:
+///
+/// ```
+/// # use minion::*;
+/// # use std::time::Duration;
+/// # struct Consumer;
+/// # impl Cancellable for Consumer {
+/// #     type Error = ();
+/// #     fn for_each(&mut self) -> Result<LoopState, ()> { Ok(LoopState::Idle) }
+/// # }
+/// let pool = Autoscaler::new(ScalePolicy::new(1, 8), || Consumer)
+///     .builder()
+///     .throttle(Duration::from_millis(500))
+///     .spawn();
+/// pool.cancel();
+/// pool.wait().unwrap();
+/// ```
+///
+/// Workers are named `worker-0`, `worker-1`, and so on. If a worker fails, the autoscaler
+/// returns its name and exit status as its error. Once the autoscaler exits, all remaining
+/// workers are cancelled and waited for.
+pub struct Autoscaler<E> {
+    policy: ScalePolicy,
+    factory: Factory<E>,
+    queue_depth: Option<Box<dyn FnMut() -> usize + Send>>,
+    workers: Vec<Worker<E>>,
+    retiring: Vec<(String, Handle<E>)>,
+    next_id: usize,
+}
+
+struct Worker<E> {
+    name: String,
+    handle: Handle<E>,
+    last: Stats,
+}
+
+impl<E> Autoscaler<E> {
+    /// Create an autoscaler that spawns workers produced by `factory`, according to `policy`.
+    pub fn new<F, S>(policy: ScalePolicy, mut factory: F) -> Self
+    where
+        F: FnMut() -> S + Send + 'static,
+        S: Cancellable<Error = E> + Send + 'static,
+        E: Send + 'static,
+    {
+        Autoscaler {
+            policy,
+            factory: Box::new(move |name| factory().builder().name(name).spawn()),
+            queue_depth: None,
+            workers: Vec::new(),
+            retiring: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Take the number of queued work items into account, as reported by `depth`.
+    ///
+    /// See [`ScalePolicy::queue_per_worker`].
+    pub fn queue_depth<F>(mut self, depth: F) -> Self
+    where
+        F: FnMut() -> usize + Send + 'static,
+    {
+        self.queue_depth = Some(Box::new(depth));
+        self
+    }
+
+    /// The number of workers currently in the pool, not counting ones that are shutting down.
+    pub fn workers(&self) -> usize {
+        self.workers.len()
+    }
+
+    fn grow(&mut self) {
+        let name = format!("worker-{}", self.next_id);
+        self.next_id += 1;
+        let handle = (self.factory)(&name);
+        self.workers.push(Worker {
+            name,
+            handle,
+            last: Stats::default(),
+        });
+    }
+
+    fn shrink(&mut self) {
+        if let Some(w) = self.workers.pop() {
+            w.handle.cancel();
+            self.retiring.push((w.name, w.handle));
+        }
+    }
+
+    /// Collect workers that have exited, returning the first one that failed.
+    fn reap(&mut self) -> Result<(), MemberStatus<E>> {
+        let mut exited = Vec::new();
+        let mut i = 0;
+        while i < self.workers.len() {
+            if self.workers[i].handle.status.has_exited() {
+                let w = self.workers.remove(i);
+                exited.push((w.name, w.handle));
+            } else {
+                i += 1;
+            }
+        }
+        let (done, retiring) = self
+            .retiring
+            .drain(..)
+            .partition(|(_, h)| h.status.has_exited());
+        self.retiring = retiring;
+        exited.extend::<Vec<_>>(done);
+
+        for (name, handle) in exited {
+            let status = handle.join();
+            if !status.is_success() {
+                return Err((name, status));
+            }
+        }
+        Ok(())
+    }
+
+    /// The fraction of the workers' iterations since the last call that were busy, if any
+    /// iterations completed at all.
+    fn busy_ratio(&mut self) -> Option<f64> {
+        let (mut iterations, mut idle) = (0, 0);
+        for w in &mut self.workers {
+            let now = w.handle.stats();
+            iterations += now.iterations - w.last.iterations;
+            idle += now.idle_iterations - w.last.idle_iterations;
+            w.last = now;
+        }
+        if iterations == 0 {
+            None
+        } else {
+            Some((iterations - idle) as f64 / iterations as f64)
+        }
+    }
+}
+
+impl<E> Cancellable for Autoscaler<E> {
+    type Error = MemberStatus<E>;
+
+    fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+        self.reap()?;
+        if self.workers.len() < self.policy.min {
+            while self.workers.len() < self.policy.min {
+                self.grow();
+            }
+            return Ok(LoopState::Continue);
+        }
+
+        let n = self.workers.len();
+        let busy = self.busy_ratio();
+        let depth = self.queue_depth.as_mut().map(|f| f());
+        let per_worker = self.policy.queue_per_worker;
+        let backlog = |workers: usize| match (depth, per_worker) {
+            (Some(depth), Some(per)) => Some(depth > per * workers),
+            _ => None,
+        };
+
+        let overloaded = backlog(n) == Some(true)
+            || busy.map(|b| b >= self.policy.scale_up_busy).unwrap_or(false);
+        let underloaded = backlog(n.saturating_sub(1)) != Some(true)
+            && busy
+                .map(|b| 1.0 - b >= self.policy.scale_down_idle)
+                .unwrap_or(false);
+
+        if overloaded && n < self.policy.max {
+            self.grow();
+        } else if underloaded && n > self.policy.min {
+            self.shrink();
+        }
+        Ok(LoopState::Continue)
+    }
+}
+
+impl<E> Drop for Autoscaler<E> {
+    fn drop(&mut self) {
+        for w in &self.workers {
+            w.handle.cancel();
+        }
+        for w in self.workers.drain(..) {
+            let _ = w.handle.join();
+        }
+        for (_, h) in self.retiring.drain(..) {
+            let _ = h.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    struct Consumer(Arc<AtomicUsize>);
+    impl Cancellable for Consumer {
+        type Error = ();
+        fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+            thread::sleep(Duration::from_millis(1));
+            let queued = self.0.load(Ordering::SeqCst);
+            if queued == 0 {
+                Ok(LoopState::Idle)
+            } else {
+                Ok(LoopState::Continue)
+            }
+        }
+    }
+
+    #[test]
+    fn it_follows_the_queue() {
+        let queue = Arc::new(AtomicUsize::new(100));
+        let q = queue.clone();
+        let depth = queue.clone();
+        let mut scaler = Autoscaler::new(ScalePolicy::new(1, 3).queue_per_worker(10), move || {
+            Consumer(q.clone())
+        })
+        .queue_depth(move || depth.load(Ordering::SeqCst));
+
+        // starts at the minimum, then grows one worker at a time up to the maximum
+        for expected in &[1, 2, 3, 3] {
+            scaler.for_each().unwrap();
+            assert_eq!(scaler.workers(), *expected);
+        }
+
+        // once the queue empties and the workers go idle, it shrinks back down
+        queue.store(0, Ordering::SeqCst);
+        for expected in &[2, 1, 1] {
+            thread::sleep(Duration::from_millis(20));
+            scaler.for_each().unwrap();
+            assert_eq!(scaler.workers(), *expected);
+        }
+    }
+
+    #[test]
+    fn it_reports_failed_workers() {
+        struct Failing;
+        impl Cancellable for Failing {
+            type Error = &'static str;
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                Err("failed")
+            }
+        }
+
+        let h = Autoscaler::new(ScalePolicy::new(1, 1), || Failing)
+            .builder()
+            .throttle(Duration::from_millis(1))
+            .spawn();
+        match h.wait() {
+            Err((name, ExitStatus::Failed("failed"))) => assert_eq!(name, "worker-0"),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+}
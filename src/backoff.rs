@@ -0,0 +1,277 @@
+use super::{Canceller, Cancelled};
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// How to randomize a delay, so that many services waiting out the same delay do not all wake up
+/// at the same instant.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Jitter {
+    /// Use the delay as-is.
+    #[default]
+    None,
+    /// Pick a delay uniformly between zero and the full delay.
+    ///
+    /// This spreads services out the most, and is usually the best choice for retries.
+    Full,
+    /// Keep half the delay, and pick the other half uniformly at random.
+    Equal,
+    /// Pick a delay uniformly within the given fraction of the delay either way, so `0.1` yields
+    /// delays between 90% and 110% of the original.
+    Ratio(f64),
+}
+
+impl Jitter {
+    /// Randomize `delay` according to this distribution.
+    pub fn apply(&self, delay: Duration) -> Duration {
+        match *self {
+            Jitter::None => delay,
+            Jitter::Full => delay.mul_f64(random()),
+            Jitter::Equal => delay / 2 + (delay / 2).mul_f64(random()),
+            Jitter::Ratio(r) => delay.mul_f64((1.0 + r * (2.0 * random() - 1.0)).max(0.0)),
+        }
+    }
+}
+
+/// A number uniformly distributed in `[0, 1)`.
+///
+/// Good enough to spread out delays, and nothing more.
+fn random() -> f64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut h = RandomState::new().build_hasher();
+    h.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    (h.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// A policy for how long to wait between retries of a failing operation.
+///
+/// The delay starts out at a base value, and is multiplied by a factor (two, by default) after
+/// every consecutive failure, up to a maximum. Each delay is then randomized according to the
+/// policy's [`Jitter`], so that many workers that fail at the same time (say, because of a
+/// downstream outage) do not all retry at the same time once it recovers. Pass the policy to
+/// [`ServiceBuilder::retry`](crate::ServiceBuilder::retry) to have a service loop retry
+/// iterations that fail:
+///
+/// ```
+/// # use minion::*;
+/// # use std::time::Duration;
+/// let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(10))
+///     .jitter(Jitter::Full)
+///     .max_retries(5);
+/// while let Some(delay) = backoff.next_delay() {
+///     assert!(delay <= Duration::from_secs(10));
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    factor: f64,
+    jitter: Jitter,
+    max_retries: Option<u32>,
+    attempt: u32,
+}
+
+impl Backoff {
+    /// Start retrying after `base`, and never wait longer than `max` between attempts.
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Backoff {
+            base,
+            max,
+            factor: 2.0,
+            jitter: Jitter::None,
+            max_retries: None,
+            attempt: 0,
+        }
+    }
+
+    /// Multiply the delay by `factor` after every consecutive failure.
+    pub fn factor(mut self, factor: f64) -> Self {
+        self.factor = factor;
+        self
+    }
+
+    /// Randomize every delay according to `jitter`.
+    pub fn jitter(mut self, jitter: Jitter) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Give up after `n` consecutive retries.
+    pub fn max_retries(mut self, n: u32) -> Self {
+        self.max_retries = Some(n);
+        self
+    }
+
+    /// How long to wait before the next retry, or `None` if it is time to give up.
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        if self.max_retries.map(|n| self.attempt >= n).unwrap_or(false) {
+            return None;
+        }
+        let scale = self.factor.powi(self.attempt.min(i32::MAX as u32) as i32);
+        let delay = Duration::try_from_secs_f64(self.base.as_secs_f64() * scale)
+            .unwrap_or(self.max)
+            .min(self.max);
+        self.attempt = self.attempt.saturating_add(1);
+        Some(self.jitter.apply(delay))
+    }
+
+    /// Start over from the base delay, as after a success.
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+
+    /// The longest the policy ever waits, before jitter.
+    pub(crate) fn max(&self) -> Duration {
+        self.max
+    }
+}
+
+/// The error returned by [`retry`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RetryError<E> {
+    /// The service loop was cancelled before the operation succeeded.
+    Cancelled,
+    /// The backoff policy gave up; this is the error from the last attempt.
+    Exhausted(E),
+}
+
+impl<E: fmt::Display> fmt::Display for RetryError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            RetryError::Cancelled => fmt::Display::fmt(&Cancelled, f),
+            RetryError::Exhausted(ref e) => write!(f, "gave up retrying: {}", e),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for RetryError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            RetryError::Cancelled => Some(&Cancelled),
+            RetryError::Exhausted(ref e) => Some(e),
+        }
+    }
+}
+
+impl From<RetryError<io::Error>> for io::Error {
+    fn from(e: RetryError<io::Error>) -> Self {
+        match e {
+            RetryError::Cancelled => Cancelled.into(),
+            RetryError::Exhausted(e) => e,
+        }
+    }
+}
+
+/// Run `op` until it succeeds, waiting between attempts according to `policy`, but give up as
+/// soon as `canceller` is cancelled.
+///
+/// This is meant for outbound requests made from inside
+/// [`Cancellable::for_each`](crate::Cancellable::for_each): a plain retry loop would keep the
+/// service busy (and shutdown waiting) for as long as the remote end stays down, whereas this
+/// returns [`RetryError::Cancelled`] the moment the loop is cancelled, even in the middle of a
+/// delay. No new attempt is started once the loop has been cancelled, but an attempt that is
+/// already under way is not interrupted. Draining the loop does not stop the retries.
+///
+/// ```no_run
+/// # use minion::*;
+/// # use std::{io, time::Duration};
+/// # fn fetch() -> io::Result<Vec<u8>> { Ok(Vec::new()) }
+/// struct Poller(Canceller);
+/// impl Cancellable for Poller {
+///     type Error = io::Error;
+///     fn for_each(&mut self) -> Result<LoopState, io::Error> {
+///         let policy = Backoff::new(Duration::from_millis(100), Duration::from_secs(5))
+///             .jitter(Jitter::Full)
+///             .max_retries(10);
+///         let body = retry(&self.0, policy, fetch)?;
+///         // ...
+/// #       drop(body);
+///         Ok(LoopState::Continue)
+///     }
+/// }
+/// ```
+pub fn retry<T, E, F>(
+    canceller: &Canceller,
+    mut policy: Backoff,
+    mut op: F,
+) -> Result<T, RetryError<E>>
+where
+    F: FnMut() -> Result<T, E>,
+{
+    loop {
+        if canceller.is_cancelled() {
+            return Err(RetryError::Cancelled);
+        }
+        let e = match op() {
+            Ok(t) => return Ok(t),
+            Err(e) => e,
+        };
+        match policy.next_delay() {
+            Some(delay) if canceller.wait_cancelled(Some(delay)) => {
+                return Err(RetryError::Cancelled)
+            }
+            Some(_) => {}
+            None => return Err(RetryError::Exhausted(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_backs_off_exponentially() {
+        let ms = Duration::from_millis;
+        let mut b = Backoff::new(ms(10), ms(50)).max_retries(4);
+        let delays: Vec<_> = std::iter::from_fn(|| b.next_delay()).collect();
+        assert_eq!(delays, [ms(10), ms(20), ms(40), ms(50)]);
+        b.reset();
+        assert_eq!(b.next_delay(), Some(ms(10)));
+    }
+
+    #[test]
+    fn jitter_stays_in_range() {
+        let d = Duration::from_secs(1);
+        for _ in 0..100 {
+            assert!(Jitter::Full.apply(d) <= d);
+            assert!(Jitter::Equal.apply(d) >= d / 2 && Jitter::Equal.apply(d) <= d);
+            let r = Jitter::Ratio(0.1).apply(d);
+            assert!(r >= d.mul_f64(0.9) && r <= d.mul_f64(1.1));
+        }
+        // and it actually spreads things out
+        assert_ne!(Jitter::Full.apply(d), Jitter::Full.apply(d));
+    }
+
+    #[test]
+    fn retry_stops_on_cancel() {
+        let ms = Duration::from_millis;
+        let exit = Canceller::new();
+        let mut attempts = 0;
+        let r = retry(&exit, Backoff::new(ms(1), ms(1)).max_retries(3), || {
+            attempts += 1;
+            if attempts < 3 {
+                Err("down")
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(r, Ok(3));
+        let r = retry(&exit, Backoff::new(ms(1), ms(1)).max_retries(2), || Err::<(), _>("down"));
+        assert_eq!(r, Err(RetryError::Exhausted("down")));
+
+        let c = exit.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(ms(50));
+            c.cancel();
+        });
+        let start = std::time::Instant::now();
+        let r = retry(&exit, Backoff::new(ms(10_000), ms(10_000)), || Err::<(), _>("down"));
+        assert_eq!(r, Err(RetryError::Cancelled));
+        assert!(start.elapsed() < ms(5_000));
+    }
+}
@@ -0,0 +1,2015 @@
+use super::{
+    probe::Prober, Activity, Backoff, Cancellable, Canceller, Context, ErrorDisposition, ExitBus,
+    ExitReason, Finish, Handle, Jitter, KillSwitch, LivenessProbe, LoopState, MaintenanceWindow,
+    ProbeSchedule, ReadinessProbe, Registry, Resources, Sample, Status, WaitError,
+};
+use std::any::Any;
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// A builder for configuring how a [`Cancellable`] service is spawned.
+///
+/// Obtain one through [`Cancellable::builder`], chain the options you need, and then call
+/// [`ServiceBuilder::spawn`] to start the service loop:
+///
+/// ```
+/// # use minion::*;
+/// # use std::time::Duration;
+/// # struct Service;
+/// # impl Cancellable for Service {
+/// #     type Error = ();
+/// #     fn for_each(&mut self) -> Result<LoopState, ()> { Ok(LoopState::Break) }
+/// # }
+/// let h = Service
+///     .builder()
+///     .name("poller")
+///     .throttle(Duration::from_millis(100))
+///     .run_for(Duration::from_secs(60))
+///     .spawn();
+/// h.wait().unwrap();
+/// ```
+pub struct ServiceBuilder<S> {
+    service: S,
+    options: SpawnOptions,
+    canceller: Option<Canceller>,
+    throttle: Option<Duration>,
+    registry: Option<Registry>,
+    resources: Option<Resources>,
+    max_items: Option<usize>,
+    idle_timeout: Option<Duration>,
+    max_extension: Duration,
+    warmup: u64,
+    rate_half_life: Option<Duration>,
+    sampler: Option<(u64, Sampler)>,
+    budget: Option<Duration>,
+    over_budget: Option<(u64, Sampler)>,
+    retry: Option<Backoff>,
+    kill_switch: Option<Box<dyn KillSwitch>>,
+    wait_for: Option<(Vec<String>, Duration)>,
+    readiness: Option<Prober<S>>,
+    liveness: Option<Prober<S>>,
+    windows: Vec<MaintenanceWindow>,
+    errors: Option<(usize, Describe)>,
+    exits: Option<(ExitBus, Describe)>,
+    on_panic: Option<OnPanic<S>>,
+}
+
+/// How often a service that [waits for](ServiceBuilder::wait_for) others checks whether they are
+/// ready.
+const DEPENDENCY_POLL: Duration = Duration::from_millis(10);
+
+type Sampler = Box<dyn FnMut(&Sample<'_>) + Send>;
+
+/// Formats an error of the service's error type; see [`crate::errors::describe`].
+type Describe = fn(&dyn Any) -> String;
+
+/// Told about a panic in the service, and optionally gives back a fresh service to carry on with.
+type OnPanic<S> = Box<dyn FnMut(Box<dyn Any + Send>) -> Option<S> + Send>;
+
+/// Thread-level options for spawning a service loop.
+///
+/// These can be passed to [`Cancellable::spawn_cfg`] directly, or to
+/// [`ServiceBuilder::options`] alongside the builder's other settings.
+///
+/// ```
+/// # use minion::*;
+/// # use std::time::Duration;
+/// # struct Service;
+/// # impl Cancellable for Service {
+/// #     type Error = ();
+/// #     fn for_each(&mut self) -> Result<LoopState, ()> { Ok(LoopState::Break) }
+/// # }
+/// let h = Service.spawn_cfg(SpawnOptions::new().name("poller").stack_size(64 * 1024));
+/// h.wait().unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SpawnOptions {
+    name: Option<String>,
+    stack_size: Option<usize>,
+    deadline: Option<Instant>,
+    max_iterations: Option<u64>,
+    start_after: Option<Duration>,
+    start_jitter: Jitter,
+}
+
+impl SpawnOptions {
+    /// Create a set of options where everything is left at its default.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Name the service.
+    ///
+    /// The name is also given to the thread that runs the service loop, so it shows up in panic
+    /// messages and in debuggers.
+    pub fn name<N: Into<String>>(mut self, name: N) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Set the stack size (in bytes) of the thread that runs the service loop.
+    pub fn stack_size(mut self, size: usize) -> Self {
+        self.stack_size = Some(size);
+        self
+    }
+
+    /// Stop accepting new work once `deadline` has passed.
+    ///
+    /// Like [`Canceller::cancel`], this does not interrupt a currently executing
+    /// [`Cancellable::for_each`]; the loop simply returns instead of starting the next iteration.
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Stop accepting new work once `dur` has passed since the service was spawned.
+    ///
+    /// See [`SpawnOptions::deadline`].
+    pub fn run_for(self, dur: Duration) -> Self {
+        self.deadline(Instant::now() + dur)
+    }
+
+    /// Run at most `n` iterations of the service loop, and then return as though
+    /// [`Cancellable::for_each`] had returned [`LoopState::Break`](crate::LoopState::Break).
+    ///
+    /// Every call to [`Cancellable::for_each`] counts, including ones that fail.
+    pub fn max_iterations(mut self, n: u64) -> Self {
+        self.max_iterations = Some(n);
+        self
+    }
+
+    /// Wait for `delay` before starting the first iteration of the service loop.
+    ///
+    /// This spreads out services that would otherwise all start (and hit their upstreams) at the
+    /// same instant. The delay is cut short if the service is cancelled or asked to drain, in
+    /// which case the loop exits without running at all. The service is not
+    /// [ready](crate::Handle::is_ready) until the delay has passed.
+    pub fn start_after(mut self, delay: Duration) -> Self {
+        self.start_after = Some(delay);
+        self
+    }
+
+    /// Randomize the [start delay](SpawnOptions::start_after) according to `jitter`.
+    ///
+    /// Without jitter, services that are spawned together with the same delay still all start
+    /// at the same instant, just a little later.
+    pub fn start_jitter(mut self, jitter: Jitter) -> Self {
+        self.start_jitter = jitter;
+        self
+    }
+
+    fn thread_builder(&self) -> thread::Builder {
+        let mut tb = thread::Builder::new();
+        if let Some(ref name) = self.name {
+            tb = tb.name(name.clone());
+        }
+        if let Some(size) = self.stack_size {
+            tb = tb.stack_size(size);
+        }
+        tb
+    }
+}
+
+impl<S> ServiceBuilder<S> {
+    pub(crate) fn new(service: S) -> Self {
+        ServiceBuilder {
+            service,
+            options: SpawnOptions::new(),
+            canceller: None,
+            throttle: None,
+            registry: None,
+            resources: None,
+            max_items: None,
+            idle_timeout: None,
+            max_extension: Duration::from_secs(5),
+            warmup: 0,
+            rate_half_life: None,
+            sampler: None,
+            budget: None,
+            over_budget: None,
+            retry: None,
+            kill_switch: None,
+            wait_for: None,
+            readiness: None,
+            liveness: None,
+            windows: Vec::new(),
+            errors: None,
+            exits: None,
+            on_panic: None,
+        }
+    }
+
+    /// Replace this builder's thread-level options with `options`.
+    pub fn options(mut self, options: SpawnOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Name the service.
+    ///
+    /// See [`SpawnOptions::name`].
+    pub fn name<N: Into<String>>(mut self, name: N) -> Self {
+        self.options = self.options.name(name);
+        self
+    }
+
+    /// Set the stack size (in bytes) of the thread that runs the service loop.
+    pub fn stack_size(mut self, size: usize) -> Self {
+        self.options = self.options.stack_size(size);
+        self
+    }
+
+    /// Stop accepting new work once `deadline` has passed.
+    ///
+    /// See [`SpawnOptions::deadline`].
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.options = self.options.deadline(deadline);
+        self
+    }
+
+    /// Run at most `n` iterations of the service loop.
+    ///
+    /// See [`SpawnOptions::max_iterations`].
+    pub fn max_iterations(mut self, n: u64) -> Self {
+        self.options = self.options.max_iterations(n);
+        self
+    }
+
+    /// Wait for `delay` before starting the first iteration of the service loop.
+    ///
+    /// See [`SpawnOptions::start_after`].
+    pub fn start_after(mut self, delay: Duration) -> Self {
+        self.options = self.options.start_after(delay);
+        self
+    }
+
+    /// Randomize the start delay according to `jitter`.
+    ///
+    /// See [`SpawnOptions::start_jitter`].
+    pub fn start_jitter(mut self, jitter: Jitter) -> Self {
+        self.options = self.options.start_jitter(jitter);
+        self
+    }
+
+    /// Retry iterations that fail, waiting between attempts according to `backoff`.
+    ///
+    /// When [`Cancellable::for_each`] returns an error, the loop waits for the policy's next
+    /// delay and then carries on with the next iteration, rather than exiting. The wait is cut
+    /// short if the loop is cancelled. Once the policy gives up, the loop exits with the error as
+    /// usual, and any successful iteration starts the policy over. Errors from
+    /// [`Cancellable::reset`], [`Cancellable::reload`], and [`Cancellable::drain`] are never
+    /// retried, and neither are errors that [`Cancellable::on_error`] does not
+    /// [abort](ErrorDisposition::Abort) on.
+    pub fn retry(mut self, backoff: Backoff) -> Self {
+        self.retry = Some(backoff);
+        self
+    }
+
+    /// Stop accepting new work once `dur` has passed since the service was spawned.
+    ///
+    /// See [`SpawnOptions::deadline`].
+    pub fn run_for(mut self, dur: Duration) -> Self {
+        self.options = self.options.run_for(dur);
+        self
+    }
+
+    /// Apply the settings in `spec`.
+    ///
+    /// Settings that `spec` leaves unset are left as they are. The spec's restart policy only
+    /// matters to a [`Supervisor`](crate::Supervisor), and is ignored here.
+    #[cfg(feature = "serde")]
+    pub fn spec(mut self, spec: &crate::ServiceSpec) -> Self {
+        if let Some(ref name) = spec.name {
+            self = self.name(name.clone());
+        }
+        if let Some(throttle) = spec.throttle {
+            self = self.throttle(throttle);
+        }
+        if let Some(deadline) = spec.deadline {
+            self = self.run_for(deadline);
+        }
+        if let Some(delay) = spec.start_after {
+            self = self.start_after(delay);
+        }
+        if let Some(size) = spec.stack_size {
+            self = self.stack_size(size);
+        }
+        self
+    }
+
+    /// Make the service loop stop when `canceller` is cancelled.
+    ///
+    /// By default, every spawned service gets its own [`Canceller`]. Passing the same canceller
+    /// to several builders lets all of their loops be cancelled with a single call.
+    pub fn canceller(mut self, canceller: Canceller) -> Self {
+        self.canceller = Some(canceller);
+        self
+    }
+
+    /// Start at most one iteration every `interval`.
+    ///
+    /// If an iteration finishes early, the loop waits out the remainder of the interval before
+    /// calling [`Cancellable::for_each`] again. The wait ends early if the loop is cancelled.
+    pub fn throttle(mut self, interval: Duration) -> Self {
+        self.throttle = Some(interval);
+        self
+    }
+
+    /// Process at most `n` work items per iteration.
+    ///
+    /// This limit is made available to the service through [`Context::max_items`], and is
+    /// respected by adapters such as [`Consumer`](crate::Consumer). It keeps a single iteration
+    /// from monopolizing the thread when a deep backlog has built up, so that cancellation is
+    /// still observed promptly.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is zero.
+    pub fn max_items_per_iteration(mut self, n: usize) -> Self {
+        assert!(n > 0, "an iteration must be allowed to process some items");
+        self.max_items = Some(n);
+        self
+    }
+
+    /// Cancel the service loop once it has been idle for `timeout` without a break.
+    ///
+    /// An iteration is idle if it returns [`LoopState::Idle`] or reports itself
+    /// [idle](Context::set_activity); any busy iteration starts the clock over. This lets loops
+    /// that only exist to serve one client, such as per-connection handlers, expire on their own
+    /// once the client goes quiet. The timeout is checked after each iteration, so it is only as
+    /// precise as the iterations are short. Time spent [paused](Canceller::pause) does not count.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Treat the first `n` iterations as warmup.
+    ///
+    /// Warmup iterations are left out of the service's [`Stats`](crate::Stats) (and so out of
+    /// anything that acts on them, like an [`Autoscaler`](crate::Autoscaler)), since cold caches
+    /// and connection setup tend to make them unrepresentative. The service can tell which
+    /// iterations are warmup through [`Context::is_warmup`].
+    ///
+    /// Warmup iterations that fail are still [retried](ServiceBuilder::retry), but always after
+    /// the policy's first delay, and without counting toward its retry limit, so a service that is
+    /// slow to start does not use up its retries before it is up and running.
+    pub fn warmup(mut self, n: u64) -> Self {
+        self.warmup = n;
+        self
+    }
+
+    /// Smooth the service's [iteration rate](crate::Stats::iteration_rate) and
+    /// [error rate](crate::Stats::error_rate) with the given half-life.
+    ///
+    /// Iterations and errors count for half as much in the rates once `half_life` has passed, so
+    /// a longer half-life gives steadier rates that are slower to follow changes. The default is
+    /// ten seconds.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `half_life` is zero.
+    pub fn rate_half_life(mut self, half_life: Duration) -> Self {
+        assert!(half_life > Duration::from_secs(0), "the half-life must be positive");
+        self.rate_half_life = Some(half_life);
+        self
+    }
+
+    /// Call `f` with the timing of one in every `n` iterations.
+    ///
+    /// This is meant for cheap, continuous profiling of loops that iterate too often for every
+    /// iteration to be instrumented. `f` runs on the service thread between iterations, so it
+    /// should be quick. [Warmup](ServiceBuilder::warmup) iterations are never sampled.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is zero.
+    pub fn sample<F>(mut self, n: u64, f: F) -> Self
+    where
+        F: FnMut(&Sample<'_>) + Send + 'static,
+    {
+        assert!(n > 0, "cannot sample one in zero iterations");
+        self.sampler = Some((n, Box::new(f)));
+        self
+    }
+
+    /// Aim for `per_second` iterations every second.
+    ///
+    /// This [throttles](ServiceBuilder::throttle) the loop to that rate, and gives every iteration
+    /// a time budget of `1 / per_second`. The budget is made available to the service through
+    /// [`Context::budget`] and [`Context::deadline`], so that it can size its work to fit, and
+    /// iterations that exceed it are counted in
+    /// [`Stats::over_budget_iterations`](crate::Stats::over_budget_iterations). Use
+    /// [`ServiceBuilder::on_over_budget`] to be warned when that keeps happening.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `per_second` is not a positive, finite number.
+    pub fn target_rate(mut self, per_second: f64) -> Self {
+        assert!(
+            per_second > 0.0 && per_second.is_finite(),
+            "target rate must be positive"
+        );
+        let budget = Duration::from_secs_f64(1.0 / per_second);
+        self.budget = Some(budget);
+        self.throttle(budget)
+    }
+
+    /// Call `f` whenever `n` iterations in a row have exceeded their
+    /// [time budget](ServiceBuilder::target_rate).
+    ///
+    /// `f` is given the iteration that completed the streak. If the streak goes on, `f` is called
+    /// again every `n` iterations, so a loop that is persistently too slow is reported
+    /// periodically rather than on every iteration. Like the [sampler](ServiceBuilder::sample),
+    /// `f` runs on the service thread, and [warmup](ServiceBuilder::warmup) iterations are never
+    /// counted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is zero.
+    pub fn on_over_budget<F>(mut self, n: u64, f: F) -> Self
+    where
+        F: FnMut(&Sample<'_>) + Send + 'static,
+    {
+        assert!(n > 0, "cannot warn after zero iterations");
+        self.over_budget = Some((n, Box::new(f)));
+        self
+    }
+
+    /// Check `switch` before starting the service, and exit right away if it has been disabled.
+    ///
+    /// The switch is checked under the service's [name](ServiceBuilder::name), so the service
+    /// must have one. See [`KillSwitch`].
+    ///
+    /// # Panics
+    ///
+    /// The service panics on spawn if it has not been given a name.
+    pub fn kill_switch<K: KillSwitch + 'static>(mut self, switch: K) -> Self {
+        self.kill_switch = Some(Box::new(switch));
+        self
+    }
+
+    /// Hold off on starting the service loop until the services registered under `names` are
+    /// all [ready](crate::Handle::is_ready).
+    ///
+    /// This encodes startup ordering (say, waiting for a database migration to finish) alongside
+    /// the service itself, rather than in the order things are spawned. The services are looked
+    /// up in the registry this service is [registered](ServiceBuilder::register) with, or in the
+    /// [global registry](Registry::global) if it is not registered, and need not have been
+    /// spawned yet. The wait is cut short if this service is cancelled, in which case it exits
+    /// without running at all.
+    ///
+    /// # Panics
+    ///
+    /// The service loop panics, naming the missing dependency, if the services are not all ready
+    /// within `timeout`. Use [`Handle::join`] to observe this without propagating the panic.
+    pub fn wait_for<I, N>(mut self, names: I, timeout: Duration) -> Self
+    where
+        I: IntoIterator<Item = N>,
+        N: Into<String>,
+    {
+        self.wait_for = Some((names.into_iter().map(Into::into).collect(), timeout));
+        self
+    }
+
+    /// Keep the service from starting iterations while `window` is open.
+    ///
+    /// This can be called several times to add more windows. See [`MaintenanceWindow`].
+    pub fn maintenance_window(mut self, window: MaintenanceWindow) -> Self {
+        self.windows.push(window);
+        self
+    }
+
+    /// Cap the extension that the service's [`Cancellable::pre_cancel`] hook can ask for.
+    ///
+    /// The default is five seconds.
+    pub fn max_cancel_extension(mut self, max: Duration) -> Self {
+        self.max_extension = max;
+        self
+    }
+
+    /// Add the service to `registry` under its [name](ServiceBuilder::name) once spawned.
+    ///
+    /// Services that have not been given a name are registered under the empty string.
+    pub fn register(mut self, registry: &Registry) -> Self {
+        self.registry = Some(registry.clone());
+        self
+    }
+
+    /// Give the service access to the shared `resources`.
+    ///
+    /// The service can look them up with [`Context::resource`].
+    pub fn resources(mut self, resources: &Resources) -> Self {
+        self.resources = Some(resources.clone());
+        self
+    }
+}
+
+impl<S: ReadinessProbe> ServiceBuilder<S> {
+    /// Run the service's [`ReadinessProbe`] according to `schedule`, and have the service be
+    /// [ready](crate::Handle::is_ready) only while the probe is passing.
+    ///
+    /// The service does not become ready until the probe first passes. The probe runs on the
+    /// service thread between iterations, so it is never run more often than the loop iterates,
+    /// and should be quick.
+    pub fn readiness_probe(mut self, schedule: ProbeSchedule) -> Self {
+        self.readiness = Some(Prober::new(S::ready, schedule, false));
+        self
+    }
+}
+
+impl<S: LivenessProbe> ServiceBuilder<S> {
+    /// Run the service's [`LivenessProbe`] according to `schedule`, and take the service down
+    /// once the probe is failing.
+    ///
+    /// Like the readiness probe, the probe runs on the service thread between iterations.
+    ///
+    /// # Panics
+    ///
+    /// The service loop panics once the probe has failed too many times in a row, so that a
+    /// supervisor sees the service as having failed. Use [`Handle::join`] to observe this without
+    /// propagating the panic.
+    pub fn liveness_probe(mut self, schedule: ProbeSchedule) -> Self {
+        self.liveness = Some(Prober::new(S::alive, schedule, true));
+        self
+    }
+}
+
+impl<S> ServiceBuilder<S>
+where
+    S: Cancellable,
+    S::Error: fmt::Debug + 'static,
+{
+    /// Keep the `n` most recent errors returned by the service's iterations, so that they can be
+    /// inspected after the fact.
+    ///
+    /// Errors are kept along with when they happened and in which iteration, including ones that
+    /// a [retry policy](ServiceBuilder::retry) absorbed. See [`Handle::recent_errors`] and
+    /// [`Registry::recent_errors`].
+    pub fn keep_errors(mut self, n: usize) -> Self {
+        self.errors = Some((n, crate::errors::describe::<S::Error>));
+        self
+    }
+
+    /// Publish the loop's exit on `bus`, so that subscribers can react to it without a
+    /// [`Handle`].
+    ///
+    /// The exit is published on the service thread once the loop has exited (and
+    /// [`Cancellable::on_exit`] has been called), before the handle's waiters are woken. An exit
+    /// with an error carries the error formatted with [`Debug`](fmt::Debug), and one with a panic
+    /// carries the panic message. See [`ExitBus`].
+    pub fn publish_exits(mut self, bus: &ExitBus) -> Self {
+        self.exits = Some((bus.clone(), crate::errors::describe::<S::Error>));
+        self
+    }
+}
+
+impl<S> ServiceBuilder<S>
+where
+    S: Cancellable + Send + 'static,
+    S::Error: Send + 'static,
+{
+    /// Continuously execute [`Cancellable::for_each`] in a new thread with the configured
+    /// options, and return a [`Handle`] to that loop so that it can be cancelled or waited for.
+    pub fn spawn(self) -> Handle<S::Error> {
+        self.spawn_map_err(|e| e)
+    }
+
+    /// Like [`ServiceBuilder::spawn`], but convert the service's error using `f` before it is
+    /// returned from the loop.
+    ///
+    /// The conversion runs on the service thread. This is useful for keeping handles to services
+    /// with different error types in one collection without boxing the errors.
+    pub fn spawn_map_err<F, E>(self, f: F) -> Handle<E>
+    where
+        F: FnOnce(S::Error) -> E + Send + 'static,
+        E: Send + 'static,
+    {
+        self.spawn_with(f, |_| ())
+    }
+
+    /// Like [`ServiceBuilder::spawn`], but turn a panic in the service into an error, rather
+    /// than letting it unwind out of the service thread.
+    ///
+    /// If any of the service's methods panic, the loop exits with [`WaitError::Panicked`], so
+    /// waiting on the handle always gives a `Result`, and never re-raises the panic on the
+    /// waiting thread. Errors returned by the service come back as [`WaitError::Failed`]. The
+    /// service's [`Cancellable::on_exit`] is still told that it
+    /// [panicked](ExitReason::Panicked). This is like calling [`Handle::wait_catch`], but leaves
+    /// the choice with whoever spawns the service rather than whoever waits for it.
+    pub fn spawn_catch(self) -> Handle<WaitError<S::Error>> {
+        self.spawn_loop(|builder, cancelled, status| {
+            let r = std::panic::AssertUnwindSafe(|| builder.run(cancelled, status));
+            crate::catch(std::panic::catch_unwind(r)).map(|_| ())
+        })
+    }
+
+    /// Like [`ServiceBuilder::spawn`], but keep the loop going when an iteration panics.
+    ///
+    /// A panic in [`Cancellable::for_each`] is caught, and its payload is handed to `on_panic`,
+    /// after which the loop carries on with the next iteration. If `on_panic` returns `None`,
+    /// the loop keeps using the same service instance; since the panic may have left it in an
+    /// inconsistent state, `on_panic` can instead return a fresh instance to replace it with. The
+    /// replaced instance is told that it [panicked](ExitReason::Panicked), and the replacement is
+    /// [started](Cancellable::on_start) before its first iteration. A caught panic counts as a
+    /// failed iteration in the loop's [statistics](Handle::stats), and is kept among its
+    /// [recent errors](ServiceBuilder::keep_errors).
+    ///
+    /// Panics anywhere else, such as in [`Cancellable::reset`] or a failed
+    /// [liveness probe](ServiceBuilder::liveness_probe), still end the loop.
+    ///
+    /// ```
+    /// # use minion::*;
+    /// # struct Scraper;
+    /// # impl Cancellable for Scraper {
+    /// #     type Error = ();
+    /// #     fn for_each(&mut self) -> Result<LoopState, ()> { Ok(LoopState::Break) }
+    /// # }
+    /// let h = Scraper.builder().spawn_resilient(|panic| {
+    ///     eprintln!("scraper panicked: {:?}", WaitError::<()>::Panicked(panic));
+    ///     Some(Scraper)
+    /// });
+    /// # h.wait().unwrap();
+    /// ```
+    pub fn spawn_resilient<F>(mut self, on_panic: F) -> Handle<S::Error>
+    where
+        F: FnMut(Box<dyn Any + Send>) -> Option<S> + Send + 'static,
+    {
+        self.on_panic = Some(Box::new(on_panic));
+        self.spawn()
+    }
+
+    /// Like [`ServiceBuilder::spawn`], but have the loop return the service's
+    /// [output](Finish::finish) once it exits cleanly.
+    pub fn spawn_finish(self) -> Handle<S::Error, S::Output>
+    where
+        S: Finish,
+        S::Output: Send + 'static,
+    {
+        self.spawn_with(|e| e, S::finish)
+    }
+
+    fn spawn_with<F, E, O, T>(self, f: F, output: O) -> Handle<E, T>
+    where
+        F: FnOnce(S::Error) -> E + Send + 'static,
+        E: Send + 'static,
+        O: FnOnce(S) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.spawn_loop(|builder, cancelled, status| {
+            builder.run(cancelled, status).map(output).map_err(f)
+        })
+    }
+
+    /// Spawn a thread that executes the service loop through `run`.
+    fn spawn_loop<R, E, T>(mut self, run: R) -> Handle<E, T>
+    where
+        R: FnOnce(Self, &Canceller, &Status) -> Result<T, E> + Send + 'static,
+        E: Send + 'static,
+        T: Send + 'static,
+    {
+        assert!(
+            self.kill_switch.is_none() || self.options.name.is_some(),
+            "services with a kill switch must be named"
+        );
+        let tb = self.options.thread_builder();
+        let canceller = self.canceller.take().unwrap_or_default();
+        if let Some(release) = self.service.release_on_cancel() {
+            canceller.on_cancel(release);
+        }
+        if let Some(hook) = self.service.pre_cancel() {
+            let max = self.max_extension;
+            canceller.pre_cancel(move || hook().map(|e| e.min(max)));
+        }
+        let cancelled = canceller.clone();
+        let registration = self
+            .registry
+            .clone()
+            .map(|r| (r, self.options.name.clone().unwrap_or_default()));
+        let handle = Handle::spawn(tb, canceller, move |status| {
+            run(self, &cancelled, status)
+        });
+        *handle.status.service.lock().unwrap() = Some(std::any::TypeId::of::<S>());
+        if let Some((registry, name)) = registration {
+            registry.add(name, &handle);
+        }
+        handle
+    }
+
+    /// Execute the service loop on the current thread, and hand back the service once it exits
+    /// cleanly.
+    fn run(mut self, cancelled: &Canceller, status: &Status) -> Result<S, S::Error> {
+        let (bus, describe) = match self.exits.take() {
+            Some(exits) => exits,
+            None => return self.run_loop(cancelled, status),
+        };
+        let name = self.options.name.clone().unwrap_or_default();
+        let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            self.run_loop(cancelled, status)
+        }));
+        let (reason, message) = match r {
+            Ok(Ok(_)) if status.has_finished() => (ExitReason::Finished, None),
+            Ok(Ok(_)) => (ExitReason::Cancelled, None),
+            Ok(Err(ref e)) => (ExitReason::Failed, Some(describe(e))),
+            Err(ref panic) => (
+                ExitReason::Panicked,
+                crate::panic_message(&**panic).map(String::from),
+            ),
+        };
+        bus.publish::<S>(name, reason, message);
+        crate::propagate(r)
+    }
+
+    fn run_loop(self, cancelled: &Canceller, status: &Status) -> Result<S, S::Error> {
+        let ServiceBuilder {
+            mut service,
+            options,
+            throttle,
+            max_items,
+            idle_timeout,
+            mut warmup,
+            rate_half_life,
+            mut sampler,
+            budget,
+            mut over_budget,
+            mut retry,
+            kill_switch,
+            wait_for,
+            registry,
+            resources,
+            mut readiness,
+            mut liveness,
+            windows,
+            errors,
+            mut on_panic,
+            ..
+        } = self;
+        let deadline = options.deadline;
+        let max_iterations = options.max_iterations;
+
+        if let (Some(switch), Some(name)) = (kill_switch, &options.name) {
+            if switch.is_disabled(name) {
+                return Ok(service);
+            }
+        }
+
+        if let Some((names, timeout)) = wait_for {
+            let registry = registry.unwrap_or_else(|| Registry::global().clone());
+            let give_up = Instant::now() + timeout;
+            for name in names {
+                while !registry.is_ready(&name) {
+                    let now = Instant::now();
+                    if now >= give_up {
+                        panic!("dependency {:?} was not ready within {:?}", name, timeout);
+                    }
+                    if !cancelled.sleep(DEPENDENCY_POLL.min(give_up - now)) {
+                        return Ok(service);
+                    }
+                }
+            }
+        }
+
+        if let Some(delay) = options.start_after {
+            let delay = options.start_jitter.apply(delay);
+            let delay = match deadline {
+                Some(deadline) => delay.min(deadline.saturating_duration_since(Instant::now())),
+                None => delay,
+            };
+            if !cancelled.sleep(delay) {
+                // shut down before we ever got going
+                return Ok(service);
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        status.config.lock().unwrap().publish(service.config());
+        if readiness.is_none() {
+            status.set_ready();
+        }
+        if let Some((n, _)) = errors {
+            status.errors.lock().unwrap().set_capacity(n);
+        }
+        if let Some(half_life) = rate_half_life {
+            status.counters.set_half_life(half_life);
+        }
+        let mut ctx = Context::new();
+        ctx.set_canceller(cancelled.clone());
+        if let Some(resources) = resources {
+            ctx.set_resources(resources);
+        }
+        ctx.set_max_items(max_items);
+        ctx.set_budget(budget);
+        let mut last_start = None;
+        let mut iterations = 0;
+        let mut started_iterations = 0;
+        let mut over_budget_streak = 0;
+        let mut idle_since = None;
+        crate::with_hooks(&mut service, |service| {
+            while cancelled.keep_running() {
+                if let Some(deadline) = cancelled.drain_deadline() {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        // out of time, so switch to hard cancellation
+                        cancelled.cancel();
+                        break;
+                    }
+                    let r = service.drain(deadline - now);
+                    if let (Ok(_), Some(ref mut backoff)) = (&r, &mut retry) {
+                        backoff.reset();
+                    }
+                    let wait = match r {
+                        Ok(LoopState::Continue) | Ok(LoopState::Idle) => continue,
+                        Ok(LoopState::ContinueAfter(dur)) => dur,
+                        // the loop was still cancelled, it just got to wrap up first
+                        Ok(LoopState::Break) => break,
+                        Ok(LoopState::Reset) => {
+                            service.reset()?;
+                            continue;
+                        }
+                        Err(e) => {
+                            if let Some((_, describe)) = errors {
+                                let mut log = status.errors.lock().unwrap();
+                                log.record(ctx.iteration(), describe(&e));
+                            }
+                            match dispose(service, e, &mut retry)? {
+                                Some(delay) => delay,
+                                None => continue,
+                            }
+                        }
+                    };
+                    // only a hard cancellation (or the deadline) cuts this short
+                    cancelled.wait_cancelled(Some(wait.min(deadline - now)));
+                    continue;
+                }
+
+                if max_iterations.is_some_and(|n| started_iterations >= n) {
+                    status.set_finished();
+                    break;
+                }
+                if let (Some(interval), Some(last)) = (throttle, last_start) {
+                    let next = last + interval;
+                    let next = match deadline {
+                        Some(deadline) if deadline < next => deadline,
+                        _ => next,
+                    };
+                    let now = Instant::now();
+                    if next > now && !cancelled.sleep(next - now) {
+                        // cancelled or asked to drain while waiting
+                        continue;
+                    }
+                }
+                let now = Instant::now();
+                if deadline.map(|d| now >= d).unwrap_or(false) {
+                    break;
+                }
+                if cancelled.check_lease() {
+                    // cancelled (or possibly drained) in response
+                    continue;
+                }
+                if cancelled.is_paused() {
+                    // time spent paused does not count as time spent idle
+                    idle_since = None;
+                    if !cancelled.wait_resumed(deadline) {
+                        // cancelled, drained, or out of time while paused
+                        continue;
+                    }
+                }
+                let closes = windows.iter().filter_map(|w| w.remaining(SystemTime::now())).max();
+                if let Some(closes) = closes {
+                    let left = deadline.map(|d| d.saturating_duration_since(now));
+                    // look again once the window closes, in case another one has opened since
+                    cancelled.sleep(left.map_or(closes, |left| left.min(closes)));
+                    continue;
+                }
+                last_start = Some(now);
+
+                if let Some(factory) = status.take_restart() {
+                    service.on_exit(ExitReason::Restarted);
+                    *service = factory();
+                    service.on_start()?;
+                }
+                if status.take_reload() {
+                    service.reload()?;
+                    #[cfg(feature = "serde")]
+                    status.config.lock().unwrap().reloaded(service.config());
+                }
+
+                if let Some(ref mut probe) = readiness {
+                    match probe.poll(service) {
+                        Some(true) => status.set_ready(),
+                        Some(false) => status.set_unready(),
+                        None => {}
+                    }
+                }
+                if let Some(ref mut probe) = liveness {
+                    if probe.poll(service) == Some(false) {
+                        panic!("service failed its liveness probe");
+                    }
+                }
+
+                ctx.start_iteration();
+                started_iterations += 1;
+                ctx.set_warmup(warmup > 0);
+                warmup = warmup.saturating_sub(1);
+                let started = Instant::now();
+                let mut panicked = None;
+                let r = match on_panic {
+                    Some(_) => {
+                        let iteration = || service.for_each_ctx(&mut ctx);
+                        match std::panic::catch_unwind(std::panic::AssertUnwindSafe(iteration)) {
+                            Ok(r) => r.map_err(Some),
+                            Err(panic) => {
+                                // accounted for like any other failed iteration
+                                panicked = Some(panic);
+                                Err(None)
+                            }
+                        }
+                    }
+                    None => service.for_each_ctx(&mut ctx).map_err(Some),
+                };
+                ctx.end_iteration();
+                if let Ok(LoopState::Idle) = r {
+                    ctx.set_activity(Activity::Idle);
+                }
+                let took = started.elapsed();
+                status.counters.record(&ctx, took, r.is_err());
+                if let Some(timeout) = idle_timeout {
+                    if ctx.activity() == Activity::Idle {
+                        if idle_since.get_or_insert(started).elapsed() >= timeout {
+                            cancelled.cancel();
+                        }
+                    } else {
+                        idle_since = None;
+                    }
+                }
+                if !ctx.is_warmup() {
+                    iterations += 1;
+                    let sample = Sample {
+                        iteration: iterations,
+                        took,
+                        activity: ctx.activity(),
+                        tag: ctx.tag(),
+                    };
+                    if let Some((n, ref mut f)) = sampler {
+                        if iterations % n == 0 {
+                            f(&sample);
+                        }
+                    }
+                    if budget.map(|b| took > b).unwrap_or(false) {
+                        over_budget_streak += 1;
+                        if let Some((n, ref mut f)) = over_budget {
+                            if over_budget_streak % n == 0 {
+                                f(&sample);
+                            }
+                        }
+                    } else {
+                        over_budget_streak = 0;
+                    }
+                }
+                if let (Ok(_), Some(ref mut backoff)) = (&r, &mut retry) {
+                    backoff.reset();
+                }
+                match r {
+                    Ok(LoopState::Continue) | Ok(LoopState::Idle) => {}
+                    Ok(LoopState::ContinueAfter(dur)) => {
+                        let left = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+                        // the loop condition takes care of cancellation during the wait
+                        cancelled.sleep(left.map_or(dur, |left| left.min(dur)));
+                    }
+                    Ok(LoopState::Break) => {
+                        status.set_finished();
+                        break;
+                    }
+                    Ok(LoopState::Reset) => service.reset()?,
+                    Err(None) => {
+                        let panic = panicked.expect("only panics fail without an error");
+                        if errors.is_some() {
+                            let message = match crate::panic_message(&*panic) {
+                                Some(msg) => format!("Panicked({:?})", msg),
+                                None => String::from("Panicked(..)"),
+                            };
+                            status.errors.lock().unwrap().record(ctx.iteration(), message);
+                        }
+                        let on_panic = on_panic.as_mut().expect("only caught with on_panic");
+                        if let Some(fresh) = on_panic(panic) {
+                            service.on_exit(ExitReason::Panicked);
+                            *service = fresh;
+                            service.on_start()?;
+                        }
+                    }
+                    Err(Some(e)) => {
+                        if let Some((_, describe)) = errors {
+                            let mut log = status.errors.lock().unwrap();
+                            log.record(ctx.iteration(), describe(&e));
+                        }
+                        let delay = dispose(service, e, &mut retry)?;
+                        if ctx.is_warmup() {
+                            if let Some(ref mut backoff) = retry {
+                                backoff.reset();
+                            }
+                        }
+                        let delay = match delay {
+                            Some(delay) => delay,
+                            None => continue,
+                        };
+                        let left = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+                        // the loop condition takes care of cancellation during the wait
+                        cancelled.sleep(left.map_or(delay, |left| left.min(delay)));
+                    }
+                }
+            }
+            Ok(status.has_finished())
+        })?;
+        Ok(service)
+    }
+}
+
+/// Decide what the loop does about an error returned by the service, first by asking
+/// [`Cancellable::on_error`], and then by consulting the loop's retry policy (if any).
+///
+/// Returns how long to wait before carrying on, `None` to carry on right away, or the error if
+/// the loop should exit with it.
+fn dispose<S: Cancellable>(
+    service: &mut S,
+    e: S::Error,
+    retry: &mut Option<Backoff>,
+) -> Result<Option<Duration>, S::Error> {
+    match service.on_error(e) {
+        ErrorDisposition::Continue => Ok(None),
+        ErrorDisposition::RetryAfter(delay) => Ok(Some(delay)),
+        ErrorDisposition::Abort(e) => match retry.as_mut().and_then(Backoff::next_delay) {
+            Some(delay) => Ok(Some(delay)),
+            None => Err(e),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use std::time::{Duration, Instant};
+
+    struct Counter(usize);
+
+    impl Cancellable for Counter {
+        type Error = ();
+        fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+            self.0 += 1;
+            Ok(LoopState::Continue)
+        }
+    }
+
+    #[test]
+    fn it_names_the_thread() {
+        struct Named;
+        impl Cancellable for Named {
+            type Error = String;
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                Err(thread::current().name().unwrap_or("").to_string())
+            }
+        }
+
+        let h = Named.builder().name("named-service").spawn();
+        assert_eq!(h.wait().unwrap_err(), "named-service");
+    }
+
+    #[test]
+    fn into_parts_exposes_the_thread() {
+        let h = Counter(0).builder().name("parts").spawn();
+        let (jh, canceller) = h.into_parts();
+        let jh = jh.unwrap();
+        assert_eq!(jh.thread().name(), Some("parts"));
+        canceller.cancel();
+        jh.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn spawn_cfg_applies_options() {
+        let start = Instant::now();
+        let h = Counter(0).spawn_cfg(SpawnOptions::new().run_for(Duration::from_millis(20)));
+        h.wait().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn it_maps_errors() {
+        struct Failing;
+        impl Cancellable for Failing {
+            type Error = u8;
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                Err(42)
+            }
+        }
+
+        let h: Handle<String> = Failing.spawn_map_err(|e| e.to_string());
+        assert_eq!(h.wait().unwrap_err(), "42");
+    }
+
+    #[test]
+    fn it_resets() {
+        struct Flaky {
+            resets: usize,
+            broken: bool,
+        }
+        impl Cancellable for Flaky {
+            type Error = usize;
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                if self.broken {
+                    return Err(self.resets);
+                }
+                self.broken = true;
+                if self.resets == 3 {
+                    return Err(self.resets);
+                }
+                Ok(LoopState::Reset)
+            }
+            fn reset(&mut self) -> Result<(), Self::Error> {
+                self.resets += 1;
+                self.broken = false;
+                Ok(())
+            }
+        }
+
+        let h = Flaky {
+            resets: 0,
+            broken: false,
+        }
+        .spawn();
+        assert_eq!(h.wait().unwrap_err(), 3);
+    }
+
+    #[test]
+    fn stats_smooth_rates() {
+        struct Flaky(bool);
+        impl Cancellable for Flaky {
+            type Error = ();
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                self.0 = !self.0;
+                if self.0 {
+                    Err(())
+                } else {
+                    Ok(LoopState::Continue)
+                }
+            }
+            fn on_error(&mut self, _: ()) -> ErrorDisposition<()> {
+                ErrorDisposition::Continue
+            }
+        }
+
+        let ms = Duration::from_millis;
+        let h = Flaky(false).builder().throttle(ms(5)).rate_half_life(ms(50)).spawn();
+        thread::sleep(ms(300));
+        let stats = h.stats();
+        // about 200 iterations per second, half of which fail
+        assert!(stats.iteration_rate > 50.0 && stats.iteration_rate < 250.0, "{:?}", stats);
+        let ratio = stats.error_rate / stats.iteration_rate;
+        assert!(ratio > 0.3 && ratio < 0.7, "{:?}", stats);
+
+        h.cancel();
+        thread::sleep(ms(500));
+        assert!(h.stats().iteration_rate < 1.0);
+        h.wait().unwrap();
+    }
+
+    #[test]
+    fn warmup_is_left_out_of_stats() {
+        struct Cold(usize, Vec<bool>);
+        impl Cancellable for Cold {
+            type Error = Vec<bool>;
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                unreachable!()
+            }
+            fn for_each_ctx(&mut self, ctx: &mut Context) -> Result<LoopState, Self::Error> {
+                self.1.push(ctx.is_warmup());
+                self.0 += 1;
+                if self.0 == 5 {
+                    Err(std::mem::take(&mut self.1))
+                } else {
+                    Ok(LoopState::Idle)
+                }
+            }
+        }
+
+        let h = Cold(0, Vec::new()).builder().warmup(2).spawn();
+        while h.stats().iterations + h.stats().warmup_iterations < 5 {
+            thread::yield_now();
+        }
+        let stats = h.stats();
+        assert_eq!(stats.warmup_iterations, 2);
+        assert_eq!(stats.iterations, 3);
+        assert_eq!(stats.idle_iterations, 2);
+        assert_eq!(h.wait().unwrap_err(), [true, true, false, false, false]);
+    }
+
+    #[test]
+    fn context_tracks_the_loop() {
+        struct Counted(Vec<u64>);
+        impl Cancellable for Counted {
+            type Error = ();
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                unreachable!()
+            }
+            fn for_each_ctx(&mut self, ctx: &mut Context) -> Result<LoopState, Self::Error> {
+                self.0.push(ctx.iteration());
+                if ctx.iteration() == 3 {
+                    // cancelling our own loop stops it after this iteration
+                    ctx.canceller().cancel();
+                }
+                assert!(!ctx.canceller().is_cancelled() || ctx.iteration() == 3);
+                Ok(LoopState::Continue)
+            }
+        }
+        impl Finish for Counted {
+            type Output = Vec<u64>;
+            fn finish(self) -> Vec<u64> {
+                self.0
+            }
+        }
+
+        let h = Counted(Vec::new()).builder().warmup(1).spawn_finish();
+        assert_eq!(h.wait().unwrap(), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn iteration_cancellers_end_with_the_iteration() {
+        struct Scoped(Vec<Canceller>);
+        impl Cancellable for Scoped {
+            type Error = ();
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                unreachable!()
+            }
+            fn for_each_ctx(&mut self, ctx: &mut Context) -> Result<LoopState, Self::Error> {
+                let scope = ctx.iteration_canceller();
+                assert!(!scope.is_cancelled() || ctx.canceller().is_cancelled());
+                assert!(scope == ctx.iteration_canceller());
+                self.0.push(scope);
+                Ok(LoopState::Continue)
+            }
+        }
+        impl Finish for Scoped {
+            type Output = Vec<Canceller>;
+            fn finish(self) -> Vec<Canceller> {
+                self.0
+            }
+        }
+
+        let h = Scoped(Vec::new()).builder().spawn_finish();
+        while h.stats().iterations < 3 {
+            thread::yield_now();
+        }
+        h.cancel();
+        let scopes = h.wait().unwrap();
+        assert!(scopes.len() >= 3);
+        assert!(scopes.iter().all(|s| s.is_cancelled()));
+    }
+
+    #[test]
+    fn spawn_resilient_survives_panics() {
+        use std::sync::atomic::AtomicUsize;
+
+        struct Flaky(usize, Arc<AtomicUsize>);
+        impl Cancellable for Flaky {
+            type Error = ();
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                self.0 += 1;
+                self.1.fetch_add(1, Ordering::SeqCst);
+                match self.0 {
+                    1 => panic!("first"),
+                    2 => panic!("second"),
+                    _ => Ok(LoopState::Break),
+                }
+            }
+        }
+
+        // the same instance keeps counting where it left off
+        let runs = Arc::new(AtomicUsize::new(0));
+        let (tx, rx) = std::sync::mpsc::channel();
+        let h = Flaky(0, runs.clone()).builder().keep_errors(4).spawn_resilient(move |panic| {
+            tx.send(*panic.downcast::<&str>().unwrap()).unwrap();
+            None
+        });
+        while !h.status.has_exited() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        // the panicking iterations count as failed ones
+        let stats = h.stats();
+        assert_eq!(stats.iterations, 3);
+        assert!(stats.error_rate > 0.0);
+        let errors: Vec<_> = h.recent_errors().into_iter().map(|e| e.message).collect();
+        assert_eq!(errors, [r#"Panicked("first")"#, r#"Panicked("second")"#]);
+        h.wait().unwrap();
+        assert_eq!(rx.iter().collect::<Vec<_>>(), ["first", "second"]);
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+
+        // fresh instances start over, so they panic at the first iteration again
+        let runs = Arc::new(AtomicUsize::new(0));
+        let fresh = runs.clone();
+        let mut left = 3;
+        let h = Flaky(0, runs.clone()).builder().spawn_resilient(move |_| {
+            left -= 1;
+            Some(Flaky(if left == 0 { 2 } else { 0 }, fresh.clone()))
+        });
+        h.wait().unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn spawn_catch_turns_panics_into_errors() {
+        struct Panicky(bool);
+        impl Cancellable for Panicky {
+            type Error = ();
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                if self.0 {
+                    panic!("boom");
+                }
+                Err(())
+            }
+        }
+
+        match Panicky(true).builder().spawn_catch().wait() {
+            Err(e @ WaitError::Panicked(_)) => assert_eq!(e.panic_message(), Some("boom")),
+            r => panic!("unexpected result {:?}", r),
+        }
+        let status = Panicky(false).builder().spawn_catch().join();
+        assert!(matches!(status, ExitStatus::Failed(WaitError::Failed(()))));
+    }
+
+    #[test]
+    fn restart_in_place_swaps_the_service() {
+        struct Conn(usize, Arc<Mutex<Vec<String>>>);
+        impl Cancellable for Conn {
+            type Error = ();
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                thread::sleep(Duration::from_millis(1));
+                Ok(LoopState::Continue)
+            }
+            fn on_start(&mut self) -> Result<(), Self::Error> {
+                self.1.lock().unwrap().push(format!("start {}", self.0));
+                Ok(())
+            }
+            fn on_exit(&mut self, reason: ExitReason) {
+                self.1.lock().unwrap().push(format!("exit {} {:?}", self.0, reason));
+            }
+        }
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let h = Conn(1, log.clone()).spawn();
+        while h.stats().iterations < 2 {
+            thread::yield_now();
+        }
+        let l = log.clone();
+        h.restart_in_place(move || Conn(2, l));
+        while log.lock().unwrap().len() < 3 {
+            thread::yield_now();
+        }
+        assert!(h.stats().iterations >= 2);
+        h.cancel();
+        h.wait().unwrap();
+        assert_eq!(
+            *log.lock().unwrap(),
+            ["start 1", "exit 1 Restarted", "start 2", "exit 2 Cancelled"]
+        );
+    }
+
+    #[test]
+    fn slowest_iteration_is_tagged() {
+        struct Batches(usize);
+        impl Cancellable for Batches {
+            type Error = ();
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                unreachable!()
+            }
+            fn for_each_ctx(&mut self, ctx: &mut Context) -> Result<LoopState, Self::Error> {
+                self.0 += 1;
+                ctx.set_tag(format!("batch={}", self.0));
+                if self.0 == 2 {
+                    thread::sleep(Duration::from_millis(20));
+                }
+                if self.0 == 4 {
+                    return Ok(LoopState::Break);
+                }
+                Ok(LoopState::Continue)
+            }
+        }
+
+        let h = Batches(0).spawn();
+        while h.stats().iterations < 4 {
+            thread::yield_now();
+        }
+        let slowest = h.stats().slowest.unwrap();
+        assert!(slowest.took >= Duration::from_millis(20));
+        assert_eq!(slowest.tag.as_deref(), Some("batch=2"));
+        h.wait().unwrap();
+    }
+
+    #[test]
+    fn scratch_buffer_is_reused() {
+        struct Encoder(usize, Vec<usize>);
+        impl Cancellable for Encoder {
+            type Error = Vec<usize>;
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                unreachable!()
+            }
+            fn for_each_ctx(&mut self, ctx: &mut Context) -> Result<LoopState, Self::Error> {
+                let buf = ctx.scratch();
+                assert!(buf.is_empty());
+                buf.extend_from_slice(b"hello");
+                // the buffer's address tells us whether it was reallocated
+                self.1.push(buf.as_ptr() as usize);
+                self.0 += 1;
+                if self.0 == 3 {
+                    Err(std::mem::take(&mut self.1))
+                } else {
+                    Ok(LoopState::Continue)
+                }
+            }
+        }
+        let allocations = Encoder(0, Vec::new()).spawn().wait().unwrap_err();
+        assert!(allocations.iter().all(|&p| p == allocations[0]));
+    }
+
+    #[test]
+    fn it_samples_iterations() {
+        use std::sync::mpsc;
+
+        struct Tagged(u64);
+        impl Cancellable for Tagged {
+            type Error = ();
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                unreachable!()
+            }
+            fn for_each_ctx(&mut self, ctx: &mut Context) -> Result<LoopState, Self::Error> {
+                self.0 += 1;
+                ctx.set_tag(format!("n={}", self.0));
+                if self.0 == 10 {
+                    Ok(LoopState::Break)
+                } else {
+                    Ok(LoopState::Continue)
+                }
+            }
+        }
+
+        let (tx, rx) = mpsc::channel();
+        Tagged(0)
+            .builder()
+            .warmup(1)
+            .sample(3, move |s| tx.send((s.iteration, s.tag.unwrap().to_string())).unwrap())
+            .spawn()
+            .wait()
+            .unwrap();
+        let samples: Vec<_> = rx.try_iter().collect();
+        assert_eq!(
+            samples,
+            [
+                (3, "n=4".to_string()),
+                (6, "n=7".to_string()),
+                (9, "n=10".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn it_tracks_activity() {
+        struct Bursty(usize);
+        impl Cancellable for Bursty {
+            type Error = ();
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                self.for_each_ctx(&mut Context::new())
+            }
+            fn for_each_ctx(&mut self, ctx: &mut Context) -> Result<LoopState, Self::Error> {
+                self.0 += 1;
+                match self.0 {
+                    2 => Ok(LoopState::Idle),
+                    4 => {
+                        ctx.set_activity(Activity::Idle);
+                        Ok(LoopState::Continue)
+                    }
+                    5 => Ok(LoopState::Break),
+                    _ => Ok(LoopState::Continue),
+                }
+            }
+        }
+
+        let h = Bursty(0).spawn();
+        while h.stats().iterations < 5 {
+            thread::yield_now();
+        }
+        let stats = h.stats();
+        assert_eq!(stats.iterations, 5);
+        assert_eq!(stats.idle_iterations, 2);
+        assert_eq!(h.activity(), Activity::Busy);
+        h.wait().unwrap();
+    }
+
+    #[test]
+    fn it_stops_at_the_deadline() {
+        let start = Instant::now();
+        let h = Counter(0).builder().run_for(Duration::from_millis(50)).spawn();
+        h.wait().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        let start = Instant::now();
+        Counter(0).spawn_for(Duration::from_millis(20)).wait().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        let deadline = Instant::now() + Duration::from_millis(20);
+        Counter(0).spawn_with_deadline(deadline).wait().unwrap();
+        assert!(Instant::now() >= deadline);
+
+        // even a loop that is paused throughout
+        let (paused, bus) = (Canceller::new(), ExitBus::new());
+        paused.pause();
+        let exits = bus.subscribe();
+        let start = Instant::now();
+        let b = Counter(0).builder().canceller(paused).publish_exits(&bus);
+        b.run_for(Duration::from_millis(20)).spawn().wait().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(exits.recv().unwrap().reason, ExitReason::Cancelled);
+    }
+
+    #[test]
+    fn it_expires_when_idle() {
+        // idle, except for every `.1`th iteration (if any)
+        struct Quiet(usize, Option<usize>);
+        impl Cancellable for Quiet {
+            type Error = ();
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                thread::sleep(Duration::from_millis(2));
+                self.0 += 1;
+                if Some(self.0) == self.1 {
+                    self.0 = 0;
+                    Ok(LoopState::Continue)
+                } else {
+                    Ok(LoopState::Idle)
+                }
+            }
+        }
+
+        // being busy now and then keeps it from ever being idle for long
+        let h = Quiet(0, Some(5)).builder().idle_timeout(Duration::from_millis(50)).spawn();
+        thread::sleep(Duration::from_millis(150));
+        assert!(!h.is_cancelled());
+        h.cancel();
+        h.wait().unwrap();
+
+        // nor does being paused for a while
+        let h = Quiet(0, None).builder().idle_timeout(Duration::from_millis(50)).spawn();
+        h.pause();
+        thread::sleep(Duration::from_millis(100));
+        h.resume();
+        thread::sleep(Duration::from_millis(10));
+        assert!(!h.is_cancelled());
+        h.cancel();
+        h.wait().unwrap();
+
+        let start = Instant::now();
+        let h = Quiet(0, None).builder().idle_timeout(Duration::from_millis(50)).spawn();
+        let c = h.canceller();
+        h.wait().unwrap();
+        assert!(c.is_cancelled());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn it_stops_after_max_iterations() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        struct Flaky(Arc<AtomicUsize>, bool);
+        impl Cancellable for Flaky {
+            type Error = ();
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                self.1 = !self.1;
+                if self.1 {
+                    Err(())
+                } else {
+                    Ok(LoopState::Continue)
+                }
+            }
+            fn on_error(&mut self, _: ()) -> ErrorDisposition<()> {
+                ErrorDisposition::Continue
+            }
+        }
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let h = Flaky(calls.clone(), false).builder().max_iterations(100).spawn();
+        h.wait().unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 100);
+    }
+
+    #[test]
+    fn drain_is_bounded_by_the_deadline() {
+        struct Draining(usize);
+        impl Cancellable for Draining {
+            type Error = ();
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                thread::yield_now();
+                Ok(LoopState::Continue)
+            }
+            fn drain(&mut self, remaining: Duration) -> Result<LoopState, Self::Error> {
+                assert!(remaining <= Duration::from_millis(20));
+                self.0 += 1;
+                thread::sleep(Duration::from_millis(1));
+                Ok(LoopState::Continue)
+            }
+        }
+
+        let h = Draining(0).spawn();
+        let c = h.canceller();
+        h.drain(Duration::from_millis(20));
+        h.wait().unwrap();
+        assert!(c.is_cancelled());
+    }
+
+    #[test]
+    fn drain_errors_are_handled_like_any_other() {
+        struct Flaky(usize);
+        impl Cancellable for Flaky {
+            type Error = &'static str;
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                thread::yield_now();
+                Ok(LoopState::Continue)
+            }
+            fn drain(&mut self, _: Duration) -> Result<LoopState, Self::Error> {
+                self.0 += 1;
+                match self.0 {
+                    1 => Err("shrugged off"),
+                    2 => Err("retried"),
+                    _ => Ok(LoopState::Break),
+                }
+            }
+            fn on_error(&mut self, e: Self::Error) -> ErrorDisposition<Self::Error> {
+                match e {
+                    "shrugged off" => ErrorDisposition::Continue,
+                    _ => ErrorDisposition::Abort(e),
+                }
+            }
+        }
+
+        let exits = ExitBus::new();
+        let exit = exits.subscribe();
+        let h = Flaky(0)
+            .builder()
+            .retry(Backoff::new(Duration::from_millis(1), Duration::from_millis(1)).max_retries(1))
+            .keep_errors(4)
+            .publish_exits(&exits)
+            .spawn();
+        h.drain(Duration::from_secs(10));
+        // having drained, the loop counts as cancelled rather than finished
+        assert_eq!(exit.recv().unwrap().reason, ExitReason::Cancelled);
+        let errors: Vec<_> = h.recent_errors().into_iter().map(|e| e.message).collect();
+        assert_eq!(errors, ["\"shrugged off\"", "\"retried\""]);
+        assert_eq!(h.cancel_and_wait(), Ok(Shutdown::Cancelled(())));
+    }
+
+    #[test]
+    fn it_releases_on_cancel() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        struct Leased(Arc<AtomicBool>, std::sync::mpsc::Receiver<()>);
+        impl Cancellable for Leased {
+            type Error = ();
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                // busy until the test lets it go
+                let _ = self.1.recv();
+                Ok(LoopState::Continue)
+            }
+            fn release_on_cancel(&self) -> Option<Box<dyn FnOnce() + Send>> {
+                let released = self.0.clone();
+                Some(Box::new(move || released.store(true, Ordering::SeqCst)))
+            }
+        }
+
+        let released = Arc::new(AtomicBool::new(false));
+        let (done, busy) = std::sync::mpsc::channel();
+        let h = Leased(released.clone(), busy).spawn();
+        h.cancel();
+        // the service is still busy, but the lease has already been released
+        assert!(released.load(Ordering::SeqCst));
+        drop(done);
+        h.wait().unwrap();
+    }
+
+    #[test]
+    fn pre_cancel_extension_is_bounded() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        struct Transaction(Arc<AtomicBool>);
+        impl Cancellable for Transaction {
+            type Error = ();
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                thread::sleep(Duration::from_millis(1));
+                Ok(LoopState::Continue)
+            }
+            fn drain(&mut self, _: Duration) -> Result<LoopState, Self::Error> {
+                // never reaches a safe point
+                self.0.store(true, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(1));
+                Ok(LoopState::Continue)
+            }
+            fn pre_cancel(&self) -> Option<Box<dyn FnOnce() -> Option<Duration> + Send>> {
+                Some(Box::new(|| Some(Duration::from_secs(60))))
+            }
+        }
+
+        let extended = Arc::new(AtomicBool::new(false));
+        let h = Transaction(extended.clone())
+            .builder()
+            .max_cancel_extension(Duration::from_millis(20))
+            .spawn();
+        let start = Instant::now();
+        h.cancel();
+        assert!(!h.is_cancelled());
+        h.wait().unwrap();
+        assert!(extended.load(Ordering::SeqCst));
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(20) && elapsed < Duration::from_secs(60));
+    }
+
+    #[test]
+    fn throttle_is_cancellable() {
+        let start = Instant::now();
+        let h = Counter(0).builder().throttle(Duration::from_secs(60)).spawn();
+        thread::sleep(Duration::from_millis(20));
+        h.cancel();
+        h.wait().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(60));
+    }
+
+    #[test]
+    fn unrenewed_lease_cancels() {
+        let h = Counter(0)
+            .builder()
+            .throttle(Duration::from_millis(5))
+            .spawn();
+        h.renew(Duration::from_millis(50));
+        for _ in 0..5 {
+            thread::sleep(Duration::from_millis(10));
+            h.renew(Duration::from_millis(50));
+        }
+        assert!(!h.is_cancelled());
+
+        // the controller goes away and stops renewing
+        let start = Instant::now();
+        let c = h.canceller();
+        h.wait().unwrap();
+        assert!(c.is_cancelled());
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn it_retries_with_backoff() {
+        struct Flaky(usize);
+        impl Cancellable for Flaky {
+            type Error = usize;
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                self.0 += 1;
+                match self.0 {
+                    // two failures, a success that resets the backoff, then failures until the
+                    // policy gives up
+                    1 | 2 => Err(self.0),
+                    3 => Ok(LoopState::Continue),
+                    n => Err(n),
+                }
+            }
+        }
+
+        let backoff = Backoff::new(Duration::from_millis(1), Duration::from_millis(5))
+            .jitter(Jitter::Full)
+            .max_retries(3);
+        let h = Flaky(0).builder().retry(backoff).spawn();
+        assert_eq!(h.wait().unwrap_err(), 7);
+    }
+
+    #[test]
+    fn warmup_errors_do_not_use_up_retries() {
+        struct Slow(usize);
+        impl Cancellable for Slow {
+            type Error = usize;
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                self.0 += 1;
+                match self.0 {
+                    // fails all through warmup, then once more than the policy allows
+                    1..=5 => Err(self.0),
+                    6 => Ok(LoopState::Continue),
+                    n => Err(n),
+                }
+            }
+        }
+
+        let backoff = Backoff::new(Duration::from_millis(1), Duration::from_millis(5))
+            .max_retries(2);
+        let h = Slow(0).builder().retry(backoff).warmup(5).spawn();
+        assert_eq!(h.wait().unwrap_err(), 9);
+    }
+
+    #[test]
+    fn it_waits_for_dependencies() {
+        let registry = Registry::new();
+        let start = Instant::now();
+        let dependent = Counter(0)
+            .builder()
+            .register(&registry)
+            .wait_for(vec!["migrator"], Duration::from_secs(5))
+            .spawn();
+        thread::sleep(Duration::from_millis(20));
+        assert!(!dependent.is_ready());
+
+        let migrator = Counter(0)
+            .builder()
+            .name("migrator")
+            .start_after(Duration::from_millis(20))
+            .register(&registry)
+            .spawn();
+        assert!(dependent.wait_ready(Duration::from_secs(1)));
+        assert!(start.elapsed() >= Duration::from_millis(40));
+        registry.cancel_all();
+        dependent.wait().unwrap();
+        migrator.wait().unwrap();
+
+        // a dependency that never shows up fails the service
+        let h = Counter(0)
+            .builder()
+            .register(&registry)
+            .wait_for(vec!["missing"], Duration::from_millis(10))
+            .spawn();
+        assert!(!h.join().is_success());
+    }
+
+    #[test]
+    fn start_after_delays_and_is_cancellable() {
+        let start = Instant::now();
+        let h = Counter(0)
+            .builder()
+            .start_after(Duration::from_millis(20))
+            .spawn();
+        assert!(h.wait_ready(Duration::from_secs(1)));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        h.cancel();
+        h.wait().unwrap();
+
+        let start = Instant::now();
+        let h = Counter(0)
+            .builder()
+            .start_after(Duration::from_secs(60))
+            .spawn();
+        h.cancel();
+        h.wait().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(60));
+    }
+
+    #[test]
+    fn probes_drive_readiness_and_liveness() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        struct Probed {
+            ready: Arc<AtomicBool>,
+            alive: Arc<AtomicBool>,
+        }
+        impl Cancellable for Probed {
+            type Error = ();
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                std::thread::sleep(Duration::from_millis(1));
+                Ok(LoopState::Continue)
+            }
+        }
+        impl ReadinessProbe for Probed {
+            fn ready(&mut self) -> bool {
+                self.ready.load(Ordering::SeqCst)
+            }
+        }
+        impl LivenessProbe for Probed {
+            fn alive(&mut self) -> bool {
+                self.alive.load(Ordering::SeqCst)
+            }
+        }
+
+        let ready = Arc::new(AtomicBool::new(false));
+        let alive = Arc::new(AtomicBool::new(true));
+        let schedule = ProbeSchedule::new().period(Duration::from_millis(1));
+        let h = Probed {
+            ready: ready.clone(),
+            alive: alive.clone(),
+        }
+        .builder()
+        .readiness_probe(schedule)
+        .liveness_probe(schedule.failure_threshold(2))
+        .spawn();
+        assert!(!h.wait_ready(Duration::from_millis(20)));
+
+        ready.store(true, Ordering::SeqCst);
+        assert!(h.wait_ready(Duration::from_secs(1)));
+        ready.store(false, Ordering::SeqCst);
+        let start = Instant::now();
+        while h.is_ready() {
+            assert!(start.elapsed() < Duration::from_secs(1));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        alive.store(false, Ordering::SeqCst);
+        assert!(matches!(h.join(), ExitStatus::Panicked(_)));
+    }
+
+    #[test]
+    fn it_warns_when_over_budget() {
+        use std::sync::mpsc;
+
+        struct Slow;
+        impl Cancellable for Slow {
+            type Error = ();
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                unreachable!()
+            }
+            fn for_each_ctx(&mut self, ctx: &mut Context) -> Result<LoopState, Self::Error> {
+                assert_eq!(ctx.budget(), Some(Duration::from_millis(2)));
+                // use up the whole budget, and then some
+                std::thread::sleep(ctx.remaining().unwrap() + Duration::from_millis(1));
+                Ok(LoopState::Continue)
+            }
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let h = Slow
+            .builder()
+            .target_rate(500.0)
+            .on_over_budget(3, move |s| tx.send(s.iteration).unwrap())
+            .spawn();
+        assert_eq!(rx.recv().unwrap(), 3);
+        assert_eq!(rx.recv().unwrap(), 6);
+        h.cancel();
+        assert!(h.stats().over_budget_iterations >= 6);
+        h.wait().unwrap();
+    }
+
+    #[test]
+    fn continue_after_is_cancellable() {
+        struct Poller;
+        impl Cancellable for Poller {
+            type Error = ();
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                Ok(LoopState::ContinueAfter(Duration::from_secs(60)))
+            }
+        }
+
+        let h = Poller.spawn();
+        while h.stats().iterations == 0 {
+            std::thread::yield_now();
+        }
+        let start = Instant::now();
+        h.cancel();
+        h.wait().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(60));
+
+        // also when the loop runs on the current thread
+        let c = Canceller::new();
+        let cancel = c.clone();
+        let start = Instant::now();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            cancel.cancel();
+        });
+        Poller.run_with(&c).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(60));
+    }
+
+    #[test]
+    fn pause_and_windows_hold_iterations() {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let h = Counter(0).spawn();
+        while h.stats().iterations == 0 {
+            std::thread::yield_now();
+        }
+        h.pause();
+        // let an iteration that was already running finish
+        std::thread::sleep(Duration::from_millis(10));
+        let paused = h.stats().iterations;
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(h.stats().iterations, paused);
+        h.resume();
+        while h.stats().iterations == paused {
+            std::thread::yield_now();
+        }
+        h.cancel();
+        h.wait().unwrap();
+
+        // a window that opened a minute ago, and closes in an hour
+        let day = 24 * 60 * 60;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() % day;
+        let window = MaintenanceWindow::daily(
+            Duration::from_secs((now + day - 60) % day),
+            Duration::from_secs((now + 60 * 60) % day),
+        );
+        let h = Counter(0).builder().maintenance_window(window).spawn();
+        assert!(h.wait_ready(Duration::from_secs(1)));
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(h.stats().iterations, 0);
+        let start = Instant::now();
+        h.cancel();
+        h.wait().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(60));
+    }
+}
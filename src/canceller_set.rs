@@ -0,0 +1,138 @@
+use super::Canceller;
+use std::sync::{Arc, Mutex};
+
+/// A set of [`Canceller`]s that can all be cancelled at once.
+///
+/// Members can be added and removed at any time, which makes a set a convenient handle on
+/// everything associated with, say, one tenant, without tying those services to a common parent
+/// the way [`Canceller::child`] does. Cloning a set gives another handle on the same members.
+///
+/// Cancelling the set is final: any canceller added afterwards is cancelled right away, so no
+/// member can slip in while the set is being cancelled.
+///
+/// ```
+/// # use minion::*;
+/// # struct Service;
+/// # impl Cancellable for Service {
+/// #     type Error = ();
+/// #     fn for_each(&mut self) -> Result<LoopState, ()> { Ok(LoopState::Continue) }
+/// # }
+/// let tenant = CancellerSet::new();
+/// let a = Service.spawn();
+/// let b = Service.spawn();
+/// tenant.add(&a);
+/// tenant.add(&b);
+///
+/// // e.g., when the tenant is deleted
+/// tenant.cancel_all();
+/// a.wait().unwrap();
+/// b.wait().unwrap();
+/// ```
+#[derive(Clone, Default)]
+pub struct CancellerSet {
+    members: Arc<Mutex<Members>>,
+}
+
+#[derive(Default)]
+struct Members {
+    cancellers: Vec<Canceller>,
+    cancelled: bool,
+}
+
+impl CancellerSet {
+    /// Create a new, empty set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `canceller` to the set.
+    ///
+    /// If the set has already been cancelled, `canceller` is cancelled instead. Adding a
+    /// canceller that is already in the set does nothing.
+    pub fn add(&self, canceller: &Canceller) {
+        let mut members = self.members.lock().unwrap();
+        if members.cancelled {
+            drop(members);
+            canceller.cancel();
+            return;
+        }
+        members.cancellers.retain(|c| !c.is_cancelled());
+        if !members.cancellers.contains(canceller) {
+            members.cancellers.push(canceller.clone());
+        }
+    }
+
+    /// Remove `canceller` from the set, so that cancelling the set no longer cancels it.
+    ///
+    /// Returns `true` if `canceller` was in the set.
+    pub fn remove(&self, canceller: &Canceller) -> bool {
+        let mut members = self.members.lock().unwrap();
+        let before = members.cancellers.len();
+        members.cancellers.retain(|c| c != canceller);
+        members.cancellers.len() != before
+    }
+
+    /// Returns `true` if `canceller` is in the set.
+    pub fn contains(&self, canceller: &Canceller) -> bool {
+        self.members.lock().unwrap().cancellers.contains(canceller)
+    }
+
+    /// The number of cancellers in the set that have not yet been cancelled.
+    pub fn len(&self) -> usize {
+        let members = self.members.lock().unwrap();
+        members.cancellers.iter().filter(|c| !c.is_cancelled()).count()
+    }
+
+    /// Returns `true` if every canceller in the set has been cancelled (or there are none).
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Cancel every canceller in the set, and every canceller added to it from now on.
+    ///
+    /// See [`Canceller::cancel`].
+    pub fn cancel_all(&self) {
+        let cancellers = {
+            let mut members = self.members.lock().unwrap();
+            members.cancelled = true;
+            std::mem::take(&mut members.cancellers)
+        };
+        // cancel outside the lock, since cancellation callbacks may well touch the set again
+        for c in cancellers {
+            c.cancel();
+        }
+    }
+
+    /// Returns `true` if the set has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.members.lock().unwrap().cancelled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn it_cancels_current_members() {
+        let set = CancellerSet::new();
+        let (a, b, c) = (Canceller::new(), Canceller::new(), Canceller::new());
+        set.add(&a);
+        set.add(&b);
+        set.add(&b);
+        set.add(&c);
+        assert_eq!(set.len(), 3);
+        assert!(set.remove(&c));
+        assert!(!set.remove(&c));
+        assert!(set.contains(&a) && !set.contains(&c));
+
+        set.cancel_all();
+        assert!(set.is_cancelled() && set.is_empty());
+        assert!(a.is_cancelled() && b.is_cancelled());
+        assert!(!c.is_cancelled());
+
+        // late arrivals do not escape
+        set.add(&c);
+        assert!(c.is_cancelled());
+    }
+}
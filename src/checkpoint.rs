@@ -0,0 +1,203 @@
+use super::{Cancellable, LoopState};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Durable storage for the progress of services, keyed by service.
+///
+/// A checkpoint is the sequence number of the last unit of work a service has committed. See
+/// [`ExactlyOnce`].
+pub trait CheckpointStore {
+    /// The last sequence number committed under `key`, or `None` if nothing has been committed.
+    fn load(&self, key: &str) -> io::Result<Option<u64>>;
+
+    /// Record that everything up to and including `seq` has been committed under `key`.
+    fn save(&self, key: &str, seq: u64) -> io::Result<()>;
+}
+
+/// A [`CheckpointStore`] that keeps each checkpoint in its own file in a directory.
+///
+/// Checkpoints are written with [`atomic_write`](crate::io::atomic_write), so a crash mid-write
+/// leaves the previous checkpoint intact.
+#[derive(Debug, Clone)]
+pub struct FileCheckpoints {
+    dir: PathBuf,
+}
+
+impl FileCheckpoints {
+    /// Store checkpoints in `dir`, creating it if it does not yet exist.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(FileCheckpoints { dir })
+    }
+}
+
+impl CheckpointStore for FileCheckpoints {
+    fn load(&self, key: &str) -> io::Result<Option<u64>> {
+        match fs::read_to_string(self.dir.join(key)) {
+            Ok(s) => s
+                .trim()
+                .parse()
+                .map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn save(&self, key: &str, seq: u64) -> io::Result<()> {
+        crate::io::atomic_write(self.dir.join(key), seq.to_string())
+    }
+}
+
+/// A [`CheckpointStore`] that only lives as long as the process.
+///
+/// Clones share the same checkpoints, which makes this useful for tests, and for services that
+/// are restarted within the same process.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryCheckpoints {
+    seqs: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl MemoryCheckpoints {
+    /// Create an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl CheckpointStore for MemoryCheckpoints {
+    fn load(&self, key: &str) -> io::Result<Option<u64>> {
+        Ok(self.seqs.lock().unwrap().get(key).copied())
+    }
+
+    fn save(&self, key: &str, seq: u64) -> io::Result<()> {
+        self.seqs.lock().unwrap().insert(key.to_string(), seq);
+        Ok(())
+    }
+}
+
+/// A service that numbers its units of work, and skips the ones it committed before a restart.
+///
+/// Each iteration passes the next sequence number to the handler, which should process the unit
+/// of work with that number (say, the message at that offset of a replayable log). Once the
+/// handler returns [`LoopState::Continue`] or [`LoopState::Break`], the sequence number is saved
+/// to the [`CheckpointStore`], and the next iteration moves on to the next one. An
+/// [idle](LoopState::Idle) iteration, or one that asks for a [reset](LoopState::Reset), is taken
+/// to not have processed anything, and its sequence number is handed out again.
+///
+/// When the service starts, it resumes right after the last checkpoint saved under its key, so
+/// work committed before a restart is not repeated. The only work that can be repeated is a
+/// single unit the handler finished right before a crash, but whose checkpoint did not make it to
+/// the store.
+///
+/// ```
+/// # use minion::*;
+/// let log = vec!["a", "b", "c"];
+/// let checkpoints = MemoryCheckpoints::new();
+/// let h = ExactlyOnce::new(checkpoints.clone(), "log-reader", move |seq| {
+///     match log.get(seq as usize) {
+///         Some(entry) => {
+///             println!("{}", entry);
+///             Ok::<_, std::io::Error>(LoopState::Continue)
+///         }
+///         None => Ok(LoopState::Break),
+///     }
+/// })
+/// .spawn();
+/// h.wait().unwrap();
+/// ```
+pub struct ExactlyOnce<S, F> {
+    store: S,
+    key: String,
+    next: Option<u64>,
+    handler: F,
+}
+
+impl<S, F, E> ExactlyOnce<S, F>
+where
+    S: CheckpointStore,
+    F: FnMut(u64) -> Result<LoopState, E>,
+    E: From<io::Error>,
+{
+    /// Create a service that passes sequence numbers to `handler`, starting after the checkpoint
+    /// saved under `key` in `store`, or at 0 if there is none.
+    pub fn new<K: Into<String>>(store: S, key: K, handler: F) -> Self {
+        ExactlyOnce {
+            store,
+            key: key.into(),
+            next: None,
+            handler,
+        }
+    }
+}
+
+impl<S, F, E> Cancellable for ExactlyOnce<S, F>
+where
+    S: CheckpointStore,
+    F: FnMut(u64) -> Result<LoopState, E>,
+    E: From<io::Error>,
+{
+    type Error = E;
+
+    fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+        let seq = match self.next {
+            Some(seq) => seq,
+            None => self.store.load(&self.key)?.map(|s| s + 1).unwrap_or(0),
+        };
+        self.next = Some(seq);
+
+        let state = (self.handler)(seq)?;
+        if let LoopState::Continue | LoopState::ContinueAfter(_) | LoopState::Break = state {
+            self.store.save(&self.key, seq)?;
+            self.next = Some(seq + 1);
+        }
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use std::io;
+
+    #[test]
+    fn it_resumes_after_the_last_checkpoint() {
+        let checkpoints = MemoryCheckpoints::new();
+        let run = |until: u64| {
+            let mut seen = Vec::new();
+            let mut service = ExactlyOnce::new(checkpoints.clone(), "reader", |seq| {
+                seen.push(seq);
+                if seq + 1 == until {
+                    Ok::<_, io::Error>(LoopState::Break)
+                } else {
+                    Ok(LoopState::Continue)
+                }
+            });
+            service.run().unwrap();
+            drop(service);
+            seen
+        };
+
+        assert_eq!(run(3), [0, 1, 2]);
+        // restarting the service skips everything that was already committed
+        assert_eq!(run(5), [3, 4]);
+        assert_eq!(checkpoints.load("reader").unwrap(), Some(4));
+    }
+
+    #[test]
+    fn file_checkpoints_survive_reopening() {
+        let dir = std::env::temp_dir().join(format!("minion-checkpoints-{}", std::process::id()));
+        let store = FileCheckpoints::new(&dir).unwrap();
+        assert_eq!(store.load("a").unwrap(), None);
+        store.save("a", 7).unwrap();
+        store.save("a", 8).unwrap();
+
+        let store = FileCheckpoints::new(&dir).unwrap();
+        assert_eq!(store.load("a").unwrap(), Some(8));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
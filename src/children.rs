@@ -0,0 +1,165 @@
+use super::{Cancellable, Canceller, Handle, InFlight};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A set of child services whose lifetime is tied to a parent's [`Canceller`].
+///
+/// This is meant for accept loops that hand each connection off to its own service loop.
+/// Every child gets its own canceller, so that individual connections can be closed on their
+/// own, but cancelling the parent cancels all of the children that are still running. The set
+/// also keeps an [`InFlight`] count of its running children, so that whoever shuts down the
+/// parent can wait for the connections to wind down as well:
+///
+/// ```no_run
+/// # use minion::*;
+/// # use std::{io, net, time::Duration};
+/// # struct Connection(net::TcpStream);
+/// # impl Cancellable for Connection {
+/// #     type Error = io::Error;
+/// #     fn for_each(&mut self) -> Result<LoopState, io::Error> { Ok(LoopState::Break) }
+/// # }
+/// struct Acceptor(net::TcpListener, Children);
+/// impl Cancellable for Acceptor {
+///     type Error = io::Error;
+///     fn for_each(&mut self) -> Result<LoopState, io::Error> {
+///         let stream = self.0.accept()?.0;
+///         self.1.spawn(Connection(stream));
+///         Ok(LoopState::Continue)
+///     }
+/// }
+///
+/// let exit = Canceller::new();
+/// let children = Children::new(&exit);
+/// let listener = net::TcpListener::bind("127.0.0.1:6556").unwrap();
+/// let h = Acceptor(listener, children.clone())
+///     .builder()
+///     .canceller(exit)
+///     .spawn();
+///
+/// h.cancel();
+/// h.wait().unwrap();
+/// children.wait_idle(Duration::from_secs(30));
+/// ```
+#[derive(Clone)]
+pub struct Children {
+    set: Arc<Mutex<ChildSet>>,
+    in_flight: InFlight,
+}
+
+#[derive(Default)]
+struct ChildSet {
+    cancelled: bool,
+    next_id: u64,
+    live: Vec<(u64, Canceller)>,
+}
+
+impl Children {
+    /// Create an empty set of children that are all cancelled along with `parent`.
+    pub fn new(parent: &Canceller) -> Self {
+        let set = Arc::new(Mutex::new(ChildSet::default()));
+        let on_cancel = set.clone();
+        parent.on_cancel(move || {
+            let live = {
+                let mut set = on_cancel.lock().unwrap();
+                set.cancelled = true;
+                std::mem::take(&mut set.live)
+            };
+            for (_, c) in live {
+                c.cancel();
+            }
+        });
+        Children {
+            set,
+            in_flight: InFlight::new(),
+        }
+    }
+
+    /// Spawn `service` as a child.
+    ///
+    /// If the parent has already been cancelled, the child is cancelled immediately.
+    pub fn spawn<S>(&self, service: S) -> Handle<S::Error>
+    where
+        S: Cancellable + Send + 'static,
+        S::Error: Send + 'static,
+    {
+        let handle = service.spawn();
+        let guard = self.in_flight.enter();
+
+        let mut set = self.set.lock().unwrap();
+        if set.cancelled {
+            drop(set);
+            handle.cancel();
+        } else {
+            let id = set.next_id;
+            set.next_id += 1;
+            set.live.push((id, handle.canceller()));
+            drop(set);
+
+            let set = self.set.clone();
+            handle.on_exit(move |_| {
+                set.lock().unwrap().live.retain(|&(i, _)| i != id);
+            });
+        }
+        handle.on_exit(move |_| drop(guard));
+        handle
+    }
+
+    /// The number of children that have not yet exited.
+    pub fn active(&self) -> usize {
+        self.in_flight.count()
+    }
+
+    /// Block until every child has exited, or until `timeout` elapses.
+    ///
+    /// Returns `true` if all children exited. See [`InFlight::wait_idle`].
+    pub fn wait_idle(&self, timeout: Duration) -> bool {
+        self.in_flight.wait_idle(timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use std::time::Duration;
+
+    struct Connection(usize);
+    impl Cancellable for Connection {
+        type Error = ();
+        fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+            std::thread::sleep(Duration::from_millis(1));
+            if self.0 == 0 {
+                return Ok(LoopState::Continue);
+            }
+            self.0 -= 1;
+            if self.0 == 0 {
+                Ok(LoopState::Break)
+            } else {
+                Ok(LoopState::Continue)
+            }
+        }
+    }
+
+    #[test]
+    fn parent_cancellation_winds_down_children() {
+        let parent = Canceller::new();
+        let children = Children::new(&parent);
+
+        // one child that finishes on its own, and two that run until cancelled
+        children.spawn(Connection(1)).wait().unwrap();
+        let a = children.spawn(Connection(0));
+        let _b = children.spawn(Connection(0));
+        assert_eq!(children.active(), 2);
+
+        // cancelling one child leaves its siblings (and the parent) alone
+        a.cancel();
+        a.wait().unwrap();
+        assert_eq!(children.active(), 1);
+        assert!(!parent.is_cancelled());
+
+        parent.cancel();
+        assert!(children.wait_idle(Duration::from_secs(1)));
+
+        // children spawned after the parent was cancelled do not get to run
+        children.spawn(Connection(0)).wait().unwrap();
+    }
+}
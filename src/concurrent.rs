@@ -0,0 +1,229 @@
+use super::{Cancellable, Context, ExitReason, LoopState};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How long a [`Concurrent`] with every worker busy waits for one of them to finish before
+/// checking back in with the loop.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A service that fetches work items on the service thread, and processes up to a fixed number
+/// of them at a time on a small pool of worker threads.
+///
+/// This gives I/O-bound services parallelism without giving up on being a single cancellable
+/// loop: each iteration fetches one item and hands it to an idle worker, or, if every worker is
+/// busy, waits for one of them to finish. `fetch` should wait briefly for an item to arrive, and
+/// return `None` if none did, which makes for an [idle](LoopState::Idle) iteration. The service
+/// fails with the first error returned by `handler`, and a panic in `handler` is propagated to
+/// the service thread.
+///
+/// Once the loop exits, no new items are fetched, and the pool is shut down after the items that
+/// are already being processed have finished. Errors from those last items are discarded, since
+/// the loop has already exited.
+///
+/// ```
+/// # use minion::*;
+/// use std::sync::mpsc;
+/// use std::time::Duration;
+///
+/// let (tx, rx) = mpsc::channel();
+/// let fetch = move |_: &mut Context| match rx.recv_timeout(Duration::from_millis(100)) {
+///     Ok(url) => Ok(Some(url)),
+///     Err(_) => Ok(None),
+/// };
+/// let h = Concurrent::new(8, fetch, |url: String| {
+///     // fetch the page, which mostly means waiting on the network
+///     println!("fetched {}", url);
+///     Ok::<_, ()>(())
+/// })
+/// .spawn();
+///
+/// tx.send(String::from("https://example.com")).unwrap();
+/// h.cancel();
+/// h.wait().unwrap();
+/// ```
+pub struct Concurrent<T, E, F, H> {
+    fetch: F,
+    handler: Arc<H>,
+    limit: usize,
+    pool: Option<Pool<T, E>>,
+    in_flight: usize,
+}
+
+/// The outcome of processing one item, including if the handler panicked.
+type Outcome<E> = thread::Result<Result<(), E>>;
+
+struct Pool<T, E> {
+    jobs: Option<mpsc::Sender<T>>,
+    done: mpsc::Receiver<Outcome<E>>,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl<T, E> Drop for Pool<T, E> {
+    fn drop(&mut self) {
+        // once the queue is closed, every worker exits after finishing its current item
+        self.jobs.take();
+        for w in self.workers.drain(..) {
+            let _ = w.join();
+        }
+    }
+}
+
+impl<T, E, F, H> Concurrent<T, E, F, H>
+where
+    T: Send + 'static,
+    E: Send + 'static,
+    F: FnMut(&mut Context) -> Result<Option<T>, E>,
+    H: Fn(T) -> Result<(), E> + Send + Sync + 'static,
+{
+    /// Create a service that processes the items returned by `fetch` with `handler`, running up
+    /// to `limit` handlers at a time.
+    ///
+    /// The worker threads are only started once the service first runs.
+    pub fn new(limit: usize, fetch: F, handler: H) -> Self {
+        assert!(limit > 0, "cannot process zero items at a time");
+        Concurrent {
+            fetch,
+            handler: Arc::new(handler),
+            limit,
+            pool: None,
+            in_flight: 0,
+        }
+    }
+
+    /// The number of items currently being processed.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    fn start_pool(&mut self) -> &mut Pool<T, E> {
+        let (limit, handler) = (self.limit, &self.handler);
+        self.pool.get_or_insert_with(|| {
+            let (jobs, queue) = mpsc::channel::<T>();
+            let (finished, done) = mpsc::channel();
+            let queue = Arc::new(Mutex::new(queue));
+            let workers = (0..limit)
+                .map(|_| {
+                    let (queue, finished, handler) =
+                        (queue.clone(), finished.clone(), handler.clone());
+                    thread::spawn(move || loop {
+                        let item = match queue.lock().unwrap().recv() {
+                            Ok(item) => item,
+                            Err(_) => break,
+                        };
+                        let r = panic::catch_unwind(AssertUnwindSafe(|| handler(item)));
+                        let _ = finished.send(r);
+                    })
+                })
+                .collect();
+            Pool {
+                jobs: Some(jobs),
+                done,
+                workers,
+            }
+        })
+    }
+
+    /// Account for a finished item, failing (or panicking) if the item did.
+    fn finish(&mut self, outcome: Outcome<E>) -> Result<(), E> {
+        self.in_flight -= 1;
+        match outcome {
+            Ok(r) => r,
+            Err(panic) => panic::resume_unwind(panic),
+        }
+    }
+}
+
+impl<T, E, F, H> Cancellable for Concurrent<T, E, F, H>
+where
+    T: Send + 'static,
+    E: Send + 'static,
+    F: FnMut(&mut Context) -> Result<Option<T>, E>,
+    H: Fn(T) -> Result<(), E> + Send + Sync + 'static,
+{
+    type Error = E;
+
+    fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+        self.for_each_ctx(&mut Context::new())
+    }
+
+    fn for_each_ctx(&mut self, ctx: &mut Context) -> Result<LoopState, Self::Error> {
+        let finished: Vec<_> = self.start_pool().done.try_iter().collect();
+        for outcome in finished {
+            self.finish(outcome)?;
+        }
+
+        if self.in_flight >= self.limit {
+            let outcome = match self.start_pool().done.recv_timeout(POLL_INTERVAL) {
+                Ok(outcome) => outcome,
+                Err(RecvTimeoutError::Timeout) => return Ok(LoopState::Continue),
+                Err(RecvTimeoutError::Disconnected) => unreachable!("workers outlive the pool"),
+            };
+            self.finish(outcome)?;
+        }
+
+        match (self.fetch)(ctx)? {
+            Some(item) => {
+                let jobs = self.start_pool().jobs.as_ref().expect("open until dropped");
+                jobs.send(item).expect("workers outlive the pool");
+                self.in_flight += 1;
+                Ok(LoopState::Continue)
+            }
+            None => Ok(LoopState::Idle),
+        }
+    }
+
+    fn on_exit(&mut self, _: ExitReason) {
+        // waits for the items that are still being processed
+        self.pool = None;
+        self.in_flight = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn it_bounds_concurrency() {
+        let (running, most, done) = (
+            Arc::new(AtomicUsize::new(0)),
+            Arc::new(AtomicUsize::new(0)),
+            Arc::new(AtomicUsize::new(0)),
+        );
+        let mut next = 0..20;
+        let fetch = move |_: &mut Context| Ok::<_, ()>(next.next());
+        let (r, m, d) = (running.clone(), most.clone(), done.clone());
+        let h = Concurrent::new(4, fetch, move |_: usize| {
+            m.fetch_max(r.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(20));
+            r.fetch_sub(1, Ordering::SeqCst);
+            d.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        })
+        .spawn();
+
+        while done.load(Ordering::SeqCst) < 16 {
+            std::thread::yield_now();
+        }
+        h.cancel();
+        h.wait().unwrap();
+        // the items in flight at cancellation were allowed to finish
+        assert_eq!(running.load(Ordering::SeqCst), 0);
+        assert!(done.load(Ordering::SeqCst) >= 16);
+        assert_eq!(most.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn it_fails_with_the_handler() {
+        let mut next = 0..;
+        let fetch = move |_: &mut Context| Ok(next.next());
+        let h = Concurrent::new(2, fetch, |n: usize| if n == 5 { Err(n) } else { Ok(()) }).spawn();
+        assert_eq!(h.wait(), Err(5));
+    }
+}
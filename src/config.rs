@@ -0,0 +1,94 @@
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::time::SystemTime;
+
+/// How many reloads' worth of configuration changes are kept for each service.
+const HISTORY: usize = 32;
+
+/// What a single reload changed in a service's published configuration.
+///
+/// See [`Cancellable::config`](crate::Cancellable::config).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigChange {
+    /// When the reload happened.
+    pub at: SystemTime,
+    /// The individual settings that changed, in no particular order.
+    pub fields: Vec<FieldChange>,
+}
+
+/// A single setting that changed on reload.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldChange {
+    /// The dot-separated path to the setting within the configuration, such as `"db.pool_size"`.
+    ///
+    /// The path is empty if the configuration is not an object.
+    pub path: String,
+    /// The setting's value before the reload, or `None` if it was not set.
+    pub old: Option<Value>,
+    /// The setting's value after the reload, or `None` if it is no longer set.
+    pub new: Option<Value>,
+}
+
+/// The configurations a service has published over time.
+#[derive(Default)]
+pub(crate) struct ConfigLog {
+    current: Option<Value>,
+    changes: VecDeque<ConfigChange>,
+}
+
+impl ConfigLog {
+    /// Record the configuration the service has right after starting.
+    pub(crate) fn publish(&mut self, config: Option<Value>) {
+        self.current = config;
+    }
+
+    /// Record the configuration the service has right after a reload, along with what changed.
+    pub(crate) fn reloaded(&mut self, config: Option<Value>) {
+        let mut fields = Vec::new();
+        diff(String::new(), self.current.as_ref(), config.as_ref(), &mut fields);
+        if self.changes.len() == HISTORY {
+            self.changes.pop_front();
+        }
+        self.changes.push_back(ConfigChange {
+            at: SystemTime::now(),
+            fields,
+        });
+        self.current = config;
+    }
+
+    pub(crate) fn current(&self) -> Option<Value> {
+        self.current.clone()
+    }
+
+    pub(crate) fn changes(&self) -> Vec<ConfigChange> {
+        self.changes.iter().cloned().collect()
+    }
+}
+
+fn diff(path: String, old: Option<&Value>, new: Option<&Value>, out: &mut Vec<FieldChange>) {
+    match (old, new) {
+        (Some(Value::Object(old)), Some(Value::Object(new))) => {
+            let join = |k: &str| {
+                if path.is_empty() {
+                    k.to_string()
+                } else {
+                    format!("{}.{}", path, k)
+                }
+            };
+            for (k, v) in old {
+                diff(join(k), Some(v), new.get(k), out);
+            }
+            for (k, v) in new {
+                if !old.contains_key(k) {
+                    diff(join(k), None, Some(v), out);
+                }
+            }
+        }
+        (old, new) if old != new => out.push(FieldChange {
+            path,
+            old: old.cloned(),
+            new: new.cloned(),
+        }),
+        _ => {}
+    }
+}
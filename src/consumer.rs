@@ -0,0 +1,116 @@
+use super::{Cancellable, Context, LoopState};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, TryRecvError};
+use std::time::Duration;
+
+/// How long a [`Consumer`] waits for an item before reporting an idle iteration.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A service that processes the items sent on a channel.
+///
+/// Each iteration waits briefly for an item to arrive, and then processes it along with any other
+/// items that are already queued up, up to the
+/// [per-iteration limit](crate::ServiceBuilder::max_items_per_iteration). Iterations in which no
+/// item arrived are reported as [idle](LoopState::Idle), and the consumer reports a non-empty
+/// [queue](Context::set_queue_depth) whenever it may have left items for the next iteration. The
+/// service exits once every sender has been dropped and the channel is empty, or with the first
+/// error returned by the handler.
+///
+/// ```
+/// # use minion::*;
+/// use std::sync::mpsc;
+///
+/// let (tx, rx) = mpsc::channel();
+/// let h = Consumer::new(rx, |n: u64| {
+///     println!("got {}", n);
+///     Ok::<_, ()>(())
+/// })
+/// .builder()
+/// .max_items_per_iteration(64)
+/// .spawn();
+///
+/// for n in 0..1000 {
+///     tx.send(n).unwrap();
+/// }
+/// drop(tx);
+/// h.wait().unwrap();
+/// ```
+pub struct Consumer<T, F> {
+    rx: Receiver<T>,
+    handler: F,
+}
+
+impl<T, F, E> Consumer<T, F>
+where
+    F: FnMut(T) -> Result<(), E>,
+{
+    /// Create a consumer that passes every item received on `rx` to `handler`.
+    pub fn new(rx: Receiver<T>, handler: F) -> Self {
+        Consumer { rx, handler }
+    }
+}
+
+impl<T, F, E> Cancellable for Consumer<T, F>
+where
+    F: FnMut(T) -> Result<(), E>,
+{
+    type Error = E;
+
+    fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+        self.for_each_ctx(&mut Context::new())
+    }
+
+    fn for_each_ctx(&mut self, ctx: &mut Context) -> Result<LoopState, Self::Error> {
+        let first = match self.rx.recv_timeout(POLL_INTERVAL) {
+            Ok(item) => item,
+            Err(RecvTimeoutError::Timeout) => {
+                ctx.set_queue_depth(0);
+                return Ok(LoopState::Idle);
+            }
+            Err(RecvTimeoutError::Disconnected) => return Ok(LoopState::Break),
+        };
+        (self.handler)(first)?;
+
+        // channels do not expose their length, so all we know is whether there may be more
+        ctx.set_queue_depth(1);
+        let budget = ctx.max_items().unwrap_or(usize::MAX);
+        for _ in 1..budget {
+            match self.rx.try_recv() {
+                Ok(item) => (self.handler)(item)?,
+                Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => {
+                    // if disconnected, the next iteration will notice
+                    ctx.set_queue_depth(0);
+                    break;
+                }
+            }
+        }
+        Ok(LoopState::Continue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn it_limits_items_per_iteration() {
+        let (tx, rx) = mpsc::channel();
+        for n in 0..10 {
+            tx.send(n).unwrap();
+        }
+        drop(tx);
+
+        let (seen_tx, seen) = mpsc::channel();
+        let h = Consumer::new(rx, move |n: usize| seen_tx.send(n).map_err(|_| ()))
+            .builder()
+            .max_items_per_iteration(3)
+            .spawn();
+        // 3 + 3 + 3 + 1 items, and then one more iteration to notice the closed channel
+        while h.stats().iterations < 5 {
+            std::thread::yield_now();
+        }
+        assert_eq!(h.stats().iterations, 5);
+        h.wait().unwrap();
+        assert_eq!(seen.try_iter().collect::<Vec<_>>(), (0..10).collect::<Vec<_>>());
+    }
+}
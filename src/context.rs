@@ -0,0 +1,246 @@
+use super::{Activity, Canceller, Resources};
+use std::any::Any;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Information shared between a service and the loop that runs it, for the duration of one
+/// iteration.
+///
+/// Passed to [`Cancellable::for_each_ctx`](crate::Cancellable::for_each_ctx).
+#[derive(Debug)]
+pub struct Context {
+    canceller: Canceller,
+    scope: Option<Arc<Mutex<Option<Canceller>>>>,
+    started: Instant,
+    iteration: Option<u64>,
+    activity: Activity,
+    max_items: Option<usize>,
+    queued: usize,
+    warmup: bool,
+    tag: Option<String>,
+    scratch: Vec<u8>,
+    budget: Option<Duration>,
+    deadline: Option<Instant>,
+    resources: Resources,
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Context {
+            canceller: Canceller::new(),
+            scope: None,
+            started: Instant::now(),
+            iteration: None,
+            activity: Activity::default(),
+            max_items: None,
+            queued: 0,
+            warmup: false,
+            tag: None,
+            scratch: Vec::new(),
+            budget: None,
+            deadline: None,
+            resources: Resources::new(),
+        }
+    }
+}
+
+impl Context {
+    /// Create a context that is not attached to any service loop.
+    ///
+    /// This is mostly useful for implementing [`Cancellable::for_each`](crate::Cancellable::for_each)
+    /// in terms of [`Cancellable::for_each_ctx`](crate::Cancellable::for_each_ctx). Its
+    /// [canceller](Context::canceller) is not connected to anything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of iterations of the service loop that came before the current one.
+    ///
+    /// Unlike [`Stats::iterations`](crate::Stats::iterations), this counts every iteration,
+    /// including [warmup](Context::is_warmup) ones.
+    pub fn iteration(&self) -> u64 {
+        self.iteration.unwrap_or(0)
+    }
+
+    /// The canceller for the service loop.
+    ///
+    /// Iterations that take a while can check [`Canceller::is_cancelled`] along the way to stop
+    /// early once the service is cancelled, rather than finish work that will be thrown away.
+    pub fn canceller(&self) -> &Canceller {
+        &self.canceller
+    }
+
+    /// The shared resource of type `T`, if the service was given one.
+    ///
+    /// See [`ServiceBuilder::resources`](crate::ServiceBuilder::resources).
+    pub fn resource<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        self.resources.get()
+    }
+
+    /// All the shared resources the service was given.
+    pub fn resources(&self) -> &Resources {
+        &self.resources
+    }
+
+    /// A canceller that is cancelled when the service loop is, and also once the current
+    /// iteration ends.
+    ///
+    /// Hand this to short-lived helpers that an iteration starts, such as threads or requests, so
+    /// that they never outlive the iteration that started them. Every call during the same
+    /// iteration returns the same canceller.
+    pub fn iteration_canceller(&mut self) -> Canceller {
+        let parent = &self.canceller;
+        let slot = self.scope.get_or_insert_with(|| {
+            let slot = Arc::new(Mutex::new(None::<Canceller>));
+            let current = slot.clone();
+            // registered only once, since cancel callbacks are kept until the loop is cancelled
+            parent.on_cancel(move || {
+                let scope = current.lock().unwrap().clone();
+                if let Some(scope) = scope {
+                    scope.cancel();
+                }
+            });
+            slot
+        });
+        let scope = slot.lock().unwrap().get_or_insert_with(Canceller::new).clone();
+        if self.canceller.is_cancelled() {
+            scope.cancel();
+        }
+        scope
+    }
+
+    /// How long ago the service loop started.
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Report whether the current iteration found work to do.
+    ///
+    /// Every iteration starts out [busy](Activity::Busy). Returning
+    /// [`LoopState::Idle`](crate::LoopState::Idle) implies [`Activity::Idle`].
+    pub fn set_activity(&mut self, activity: Activity) {
+        self.activity = activity;
+    }
+
+    /// What the current iteration has reported so far.
+    pub fn activity(&self) -> Activity {
+        self.activity
+    }
+
+    /// Report how many work items are waiting to be processed by the service.
+    ///
+    /// Unlike the activity, this carries over into later iterations until it is reported again.
+    /// It shows up in [`Stats::queued`](crate::Stats::queued), and a service only counts as
+    /// [quiescent](crate::quiesce) once its queue is empty.
+    pub fn set_queue_depth(&mut self, queued: usize) {
+        self.queued = queued;
+    }
+
+    /// The queue depth most recently reported with [`Context::set_queue_depth`].
+    pub fn queue_depth(&self) -> usize {
+        self.queued
+    }
+
+    /// The most work items a single iteration should process, if limited.
+    ///
+    /// See [`ServiceBuilder::max_items_per_iteration`](crate::ServiceBuilder::max_items_per_iteration).
+    pub fn max_items(&self) -> Option<usize> {
+        self.max_items
+    }
+
+    /// Describe what the current iteration is working on, such as `"batch=42"` or
+    /// `"peer=10.0.0.3"`.
+    ///
+    /// The tag only applies to the current iteration. It is kept alongside the iteration's
+    /// timing, so that [`Stats::slowest`](crate::Stats::slowest) can tell *what* was slow, not
+    /// just *that* something was.
+    pub fn set_tag<T: Into<String>>(&mut self, tag: T) {
+        self.tag = Some(tag.into());
+    }
+
+    /// The tag set for the current iteration, if any.
+    pub fn tag(&self) -> Option<&str> {
+        self.tag.as_deref()
+    }
+
+    /// An empty byte buffer for the current iteration to use as it sees fit.
+    ///
+    /// The buffer is cleared every time it is handed out, but keeps its allocation across
+    /// iterations of the same service loop, so hot loops can read into or serialize through it
+    /// without allocating every time.
+    pub fn scratch(&mut self) -> &mut Vec<u8> {
+        self.scratch.clear();
+        &mut self.scratch
+    }
+
+    /// Returns `true` if the current iteration is one of the service's
+    /// [warmup](crate::ServiceBuilder::warmup) iterations.
+    ///
+    /// Services that keep their own error rates or latency figures should leave these iterations
+    /// out, just like [`Stats`](crate::Stats) does.
+    pub fn is_warmup(&self) -> bool {
+        self.warmup
+    }
+
+    /// How long the current iteration is expected to take, if the service has a
+    /// [target rate](crate::ServiceBuilder::target_rate).
+    pub fn budget(&self) -> Option<Duration> {
+        self.budget
+    }
+
+    /// When the current iteration should be done by to stay within its [budget](Context::budget).
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// How much of the current iteration's [budget](Context::budget) is left.
+    ///
+    /// This is zero once the budget has been used up.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline.map(|d| d.saturating_duration_since(Instant::now()))
+    }
+
+    pub(crate) fn set_canceller(&mut self, canceller: Canceller) {
+        self.canceller = canceller;
+    }
+
+    pub(crate) fn set_resources(&mut self, resources: Resources) {
+        self.resources = resources;
+    }
+
+    pub(crate) fn set_budget(&mut self, budget: Option<Duration>) {
+        self.budget = budget;
+    }
+
+    pub(crate) fn set_warmup(&mut self, warmup: bool) {
+        self.warmup = warmup;
+    }
+
+    pub(crate) fn set_max_items(&mut self, n: Option<usize>) {
+        self.max_items = n;
+    }
+
+    /// Cancel the current iteration's [canceller](Context::iteration_canceller), if it has one.
+    pub(crate) fn end_iteration(&mut self) {
+        if let Some(ref slot) = self.scope {
+            if let Some(scope) = slot.lock().unwrap().take() {
+                scope.cancel();
+            }
+        }
+    }
+
+    /// Prepare the context for the next iteration.
+    pub(crate) fn start_iteration(&mut self) {
+        self.iteration = Some(self.iteration.map_or(0, |i| i + 1));
+        self.activity = Activity::Busy;
+        self.tag = None;
+        self.deadline = self.budget.map(|b| Instant::now() + b);
+    }
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        // the loop is going away, possibly in the middle of an iteration
+        self.end_iteration();
+    }
+}
@@ -0,0 +1,86 @@
+use std::any::Any;
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::SystemTime;
+
+/// An error returned by one of a service's iterations.
+///
+/// See [`ServiceBuilder::keep_errors`](crate::ServiceBuilder::keep_errors).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecentError {
+    /// When the iteration failed.
+    pub at: SystemTime,
+    /// Which iteration failed, as given by [`Context::iteration`](crate::Context::iteration).
+    pub iteration: u64,
+    /// The error, formatted with its [`Debug`](fmt::Debug) implementation.
+    pub message: String,
+}
+
+/// The most recent errors returned by a service's iterations.
+#[derive(Default)]
+pub(crate) struct ErrorLog {
+    capacity: usize,
+    errors: VecDeque<RecentError>,
+}
+
+impl ErrorLog {
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+    }
+
+    pub(crate) fn record(&mut self, iteration: u64, message: String) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.errors.len() == self.capacity {
+            self.errors.pop_front();
+        }
+        self.errors.push_back(RecentError {
+            at: SystemTime::now(),
+            iteration,
+            message,
+        });
+    }
+
+    pub(crate) fn recent(&self) -> Vec<RecentError> {
+        self.errors.iter().cloned().collect()
+    }
+}
+
+/// Format an error whose type the caller cannot name, but that is known to be an `E`.
+///
+/// The builder stores this as a plain function pointer, since its fields cannot mention the
+/// service's error type.
+pub(crate) fn describe<E: fmt::Debug + 'static>(e: &dyn Any) -> String {
+    let e = e.downcast_ref::<E>().expect("error described as the wrong type");
+    format!("{:?}", e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use std::time::Duration;
+
+    struct Flaky(u64);
+    impl Cancellable for Flaky {
+        type Error = String;
+        fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+            self.0 += 1;
+            if self.0 > 6 {
+                return Ok(LoopState::Break);
+            }
+            Err(format!("attempt {}", self.0))
+        }
+    }
+
+    #[test]
+    fn it_keeps_the_last_errors() {
+        let ms = Duration::from_millis(1);
+        let h = Flaky(0).builder().retry(Backoff::new(ms, ms)).keep_errors(2).spawn();
+        assert!(h.status.wait_exit(Duration::from_secs(5)));
+        let errors = h.recent_errors();
+        let seen: Vec<_> = errors.iter().map(|e| (e.iteration, &*e.message)).collect();
+        assert_eq!(seen, [(4, "\"attempt 5\""), (5, "\"attempt 6\"")]);
+        h.wait().unwrap();
+    }
+}
@@ -0,0 +1,164 @@
+use super::{Cancellable, ExitReason};
+use std::any::TypeId;
+use std::fmt;
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+
+/// A service loop that has exited, as published on an [`ExitBus`].
+#[derive(Debug, Clone)]
+pub struct ExitEvent {
+    /// The name of the service, or the empty string if it was not given one.
+    pub name: String,
+    /// Why the loop exited.
+    pub reason: ExitReason,
+    /// The error the loop failed with (formatted with [`Debug`](std::fmt::Debug)), or its panic
+    /// message, if any.
+    pub message: Option<String>,
+    service: TypeId,
+}
+
+impl ExitEvent {
+    /// Returns `true` if the loop that exited was running an `S`.
+    pub fn is<S: Cancellable + 'static>(&self) -> bool {
+        self.service == TypeId::of::<S>()
+    }
+}
+
+/// A channel on which service loops announce that they have exited, so that other parts of a
+/// program can react to it without holding on to every [`Handle`](crate::Handle).
+///
+/// Services publish to a bus once spawned with
+/// [`ServiceBuilder::publish_exits`](crate::ServiceBuilder::publish_exits). Anyone can then
+/// subscribe to the bus: alerting may want to hear about every exit, whereas an autoscaler may
+/// only care about the workers it manages, and can [subscribe to](ExitBus::subscribe_to) just
+/// those. Most programs use the process-wide [`ExitBus::global`]. Events are only delivered to
+/// subscribers that were subscribed when the loop exited.
+///
+/// ```
+/// # use minion::*;
+/// # struct Worker;
+/// # impl Cancellable for Worker {
+/// #     type Error = String;
+/// #     fn for_each(&mut self) -> Result<LoopState, String> { Err("disk full".into()) }
+/// # }
+/// let bus = ExitBus::new();
+/// let exits = bus.subscribe_to::<Worker>();
+/// Worker.builder().name("worker").publish_exits(&bus).spawn();
+///
+/// let exit = exits.recv().unwrap();
+/// assert_eq!(exit.name, "worker");
+/// assert_eq!(exit.reason, ExitReason::Failed);
+/// assert_eq!(exit.message.as_deref(), Some("\"disk full\""));
+/// ```
+#[derive(Clone, Default)]
+pub struct ExitBus {
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+struct Subscriber {
+    service: Option<TypeId>,
+    tx: mpsc::Sender<ExitEvent>,
+}
+
+impl ExitBus {
+    /// Create a new bus with no subscribers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The process-wide bus.
+    pub fn global() -> &'static ExitBus {
+        static GLOBAL: OnceLock<ExitBus> = OnceLock::new();
+        GLOBAL.get_or_init(ExitBus::new)
+    }
+
+    /// Receive every exit published on this bus from now on.
+    pub fn subscribe(&self) -> mpsc::Receiver<ExitEvent> {
+        self.add(None)
+    }
+
+    /// Receive the exits of loops running an `S` that are published on this bus from now on.
+    pub fn subscribe_to<S: Cancellable + 'static>(&self) -> mpsc::Receiver<ExitEvent> {
+        self.add(Some(TypeId::of::<S>()))
+    }
+
+    fn add(&self, service: Option<TypeId>) -> mpsc::Receiver<ExitEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers
+            .lock()
+            .unwrap()
+            .push(Subscriber { service, tx });
+        rx
+    }
+
+    /// Announce that the loop running an `S` has exited.
+    pub(crate) fn publish<S: 'static>(
+        &self,
+        name: String,
+        reason: ExitReason,
+        message: Option<String>,
+    ) {
+        let event = ExitEvent {
+            name,
+            reason,
+            message,
+            service: TypeId::of::<S>(),
+        };
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|s| match s.service {
+            Some(service) if service != event.service => true,
+            // subscribers that have gone away need not be remembered
+            _ => s.tx.send(event.clone()).is_ok(),
+        });
+    }
+}
+
+impl fmt::Debug for ExitBus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExitBus")
+            .field("subscribers", &self.subscribers.lock().unwrap().len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    struct Done;
+    impl Cancellable for Done {
+        type Error = ();
+        fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+            Ok(LoopState::Break)
+        }
+    }
+
+    struct Crashing;
+    impl Cancellable for Crashing {
+        type Error = ();
+        fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+            panic!("boom");
+        }
+    }
+
+    #[test]
+    fn subscribers_hear_about_exits() {
+        let bus = ExitBus::new();
+        let all = bus.subscribe();
+        let crashes = bus.subscribe_to::<Crashing>();
+        drop(bus.subscribe());
+
+        Done.builder().name("done").publish_exits(&bus).spawn().wait().unwrap();
+        let h = Crashing.builder().publish_exits(&bus).spawn_catch();
+        assert!(h.wait().is_err());
+
+        let exits: Vec<_> = all.try_iter().collect();
+        assert_eq!(exits.len(), 2);
+        assert!(exits[0].is::<Done>());
+        assert_eq!((&*exits[0].name, exits[0].reason), ("done", ExitReason::Finished));
+        let crash = crashes.try_recv().unwrap();
+        assert_eq!(crash.reason, ExitReason::Panicked);
+        assert_eq!(crash.message.as_deref(), Some("boom"));
+        assert!(crashes.try_recv().is_err());
+        assert_eq!(bus.subscribers.lock().unwrap().len(), 2);
+    }
+}
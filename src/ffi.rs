@@ -0,0 +1,73 @@
+use super::Canceller;
+use std::ffi::c_void;
+
+/// The type of [`FfiCanceller::callback`]: returns `true` once the service loop behind `ctx` has
+/// been cancelled.
+///
+/// In C, this is `bool (*)(void *ctx)`.
+pub type CancelCallback = extern "C" fn(ctx: *mut c_void) -> bool;
+
+/// A [`Canceller`] in a form that C (or C++) code can poll.
+///
+/// Long-running native routines called from [`Cancellable::for_each`](crate::Cancellable::for_each)
+/// cannot check a [`Canceller`] directly, but they can be handed a plain function pointer and a
+/// context pointer to call it with. This bundles the two:
+///
+/// ```ignore
+/// // in C: int render(const char *scene, bool (*cancelled)(void *), void *ctx);
+/// extern "C" {
+///     fn render(scene: *const c_char, cancelled: CancelCallback, ctx: *mut c_void) -> c_int;
+/// }
+///
+/// let check = FfiCanceller::new(ctx.canceller());
+/// let r = unsafe { render(scene.as_ptr(), check.callback(), check.context()) };
+/// ```
+///
+/// The context pointer is only valid for as long as the `FfiCanceller` is alive, so it must
+/// outlive every call the native code makes through it. The callback is cheap enough to call
+/// in tight loops, and never unwinds across the FFI boundary.
+#[derive(Debug)]
+pub struct FfiCanceller {
+    canceller: Box<Canceller>,
+}
+
+impl FfiCanceller {
+    /// Make `canceller` pollable from native code.
+    pub fn new(canceller: &Canceller) -> Self {
+        FfiCanceller {
+            canceller: Box::new(canceller.clone()),
+        }
+    }
+
+    /// The function that native code should call, with [`FfiCanceller::context`], to check for
+    /// cancellation.
+    pub fn callback(&self) -> CancelCallback {
+        is_cancelled
+    }
+
+    /// The context pointer to pass to [`FfiCanceller::callback`].
+    pub fn context(&self) -> *mut c_void {
+        &*self.canceller as *const Canceller as *mut c_void
+    }
+}
+
+extern "C" fn is_cancelled(ctx: *mut c_void) -> bool {
+    // ctx comes from FfiCanceller::context, which points into a live FfiCanceller
+    let canceller = unsafe { &*(ctx as *const Canceller) };
+    canceller.is_cancelled()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn native_code_sees_cancellation() {
+        let exit = Canceller::new();
+        let check = FfiCanceller::new(&exit);
+        let (callback, ctx) = (check.callback(), check.context());
+        assert!(!callback(ctx));
+        exit.cancel();
+        assert!(callback(ctx));
+    }
+}
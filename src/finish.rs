@@ -0,0 +1,128 @@
+use super::Cancellable;
+
+/// A service that computes a result over the course of its loop.
+///
+/// Services such as batch jobs often accumulate something (a summary, a count of records
+/// processed, the last offset seen) that is only of interest once they are done. Rather than
+/// smuggling it out through shared state, have the service implement `Finish`, and spawn it with
+/// [`ServiceBuilder::spawn_finish`](crate::ServiceBuilder::spawn_finish). Once the loop exits
+/// cleanly, be it through [`LoopState::Break`](crate::LoopState::Break) or cancellation, the
+/// service is turned into its output, which [`Handle::wait`](crate::Handle::wait) then returns:
+///
+/// ```
+/// # use minion::*;
+/// struct Summer {
+///     batches: Vec<Vec<u64>>,
+///     total: u64,
+/// }
+///
+/// impl Cancellable for Summer {
+///     type Error = ();
+///     fn for_each(&mut self) -> Result<LoopState, ()> {
+///         match self.batches.pop() {
+///             Some(batch) => {
+///                 self.total += batch.iter().sum::<u64>();
+///                 Ok(LoopState::Continue)
+///             }
+///             None => Ok(LoopState::Break),
+///         }
+///     }
+/// }
+///
+/// impl Finish for Summer {
+///     type Output = u64;
+///     fn finish(self) -> u64 {
+///         self.total
+///     }
+/// }
+///
+/// let batches = vec![vec![1, 2], vec![3], vec![4, 5]];
+/// let h = Summer { batches, total: 0 }.builder().spawn_finish();
+/// assert_eq!(h.wait(), Ok(15));
+/// ```
+///
+/// To run such a service on the current thread instead, use [`Finish::run_finish`].
+///
+/// The output also comes with every other way of waiting for the loop, such as
+/// [`Handle::join`](crate::Handle::join),
+/// [`Handle::cancel_and_wait`](crate::Handle::cancel_and_wait), and
+/// [`Handle::into_async`](crate::Handle::into_async).
+pub trait Finish: Cancellable {
+    /// What the service produces.
+    type Output;
+
+    /// Turn the service into its output once its loop has exited cleanly.
+    ///
+    /// This is not called if the loop returns an error or panics.
+    fn finish(self) -> Self::Output;
+
+    /// Like [`Cancellable::run`], but return the service's output once the loop exits cleanly.
+    fn run_finish(mut self) -> Result<Self::Output, Self::Error>
+    where
+        Self: Sized,
+    {
+        self.run()?;
+        Ok(self.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use std::time::Duration;
+
+    struct Counter(usize);
+    impl Cancellable for Counter {
+        type Error = ();
+        fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+            self.0 += 1;
+            std::thread::sleep(Duration::from_millis(1));
+            Ok(LoopState::Continue)
+        }
+    }
+    impl Finish for Counter {
+        type Output = usize;
+        fn finish(self) -> usize {
+            self.0
+        }
+    }
+
+    #[test]
+    fn cancelled_services_finish_too() {
+        let h = Counter(0).builder().spawn_finish();
+        while h.stats().iterations < 3 {
+            std::thread::yield_now();
+        }
+        match h.cancel_and_wait() {
+            Ok(Shutdown::Cancelled(n)) => assert!(n >= 3),
+            r => panic!("unexpected shutdown: {:?}", r),
+        }
+    }
+
+    struct Countdown(usize);
+    impl Cancellable for Countdown {
+        type Error = ();
+        fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+            if self.0 == 0 {
+                return Ok(LoopState::Break);
+            }
+            self.0 -= 1;
+            Ok(LoopState::Continue)
+        }
+    }
+    impl Finish for Countdown {
+        type Output = &'static str;
+        fn finish(self) -> &'static str {
+            "liftoff"
+        }
+    }
+
+    #[test]
+    fn every_way_of_waiting_finishes() {
+        assert_eq!(Countdown(3).run_finish(), Ok("liftoff"));
+        let status = Countdown(3).builder().spawn_finish().join();
+        assert!(matches!(status, ExitStatus::Exited("liftoff")));
+        let (waiter, _) = Countdown(3).builder().spawn_finish().split();
+        assert_eq!(waiter.wait(), Ok("liftoff"));
+    }
+}
@@ -0,0 +1,97 @@
+use super::{Canceller, Handle};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{self, Poll, Wake, Waker};
+use std::thread;
+
+struct ThreadWaker {
+    woken: AtomicBool,
+    canceller: Canceller,
+}
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::SeqCst);
+        self.canceller.notify();
+    }
+}
+
+/// Drive `fut` to completion on a new thread, and return a [`Handle`] to it.
+///
+/// The future is polled by a minimal single-future executor, so this is best suited to
+/// applications that are otherwise built around threads but occasionally need to host some async
+/// work. Cancelling the returned handle drops the future the next time it yields (that is, at its
+/// next `.await` point that is not immediately ready), and [`Handle::wait`] then returns `Ok(())`.
+///
+/// ```
+/// # use minion::*;
+/// # use std::future;
+/// let h = from_future(future::pending::<Result<(), ()>>());
+/// h.cancel();
+/// h.wait().unwrap();
+/// ```
+pub fn from_future<F, E>(fut: F) -> Handle<E>
+where
+    F: Future<Output = Result<(), E>> + Send + 'static,
+    E: Send + 'static,
+{
+    let canceller = Canceller::new();
+    let cancelled = canceller.clone();
+    Handle::spawn(thread::Builder::new(), canceller, move |status| {
+        let waker = Arc::new(ThreadWaker {
+            woken: AtomicBool::new(false),
+            canceller: cancelled.clone(),
+        });
+        let w = Waker::from(waker.clone());
+        let mut cx = task::Context::from_waker(&w);
+        let mut fut = Box::pin(fut);
+        status.set_ready();
+        while cancelled.keep_running() {
+            if let Poll::Ready(r) = fut.as_mut().poll(&mut cx) {
+                return r;
+            }
+            if !cancelled.wait_for(|| waker.woken.swap(false, Ordering::SeqCst)) {
+                break;
+            }
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future;
+    use std::pin::Pin;
+
+    struct YieldN(usize);
+
+    impl Future for YieldN {
+        type Output = Result<(), usize>;
+        fn poll(mut self: Pin<&mut Self>, cx: &mut task::Context) -> Poll<Self::Output> {
+            if self.0 == 0 {
+                return Poll::Ready(Err(42));
+            }
+            self.0 -= 1;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn it_completes() {
+        assert_eq!(from_future(YieldN(3)).wait(), Err(42));
+    }
+
+    #[test]
+    fn it_cancels() {
+        let h = from_future(future::pending::<Result<(), ()>>());
+        h.cancel();
+        h.wait().unwrap();
+    }
+}
@@ -0,0 +1,799 @@
+use super::{Cancellable, Canceller, ExitStatus, Handle, Jitter};
+use std::fmt;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How [`GroupHandle::wait_all`] should treat members that fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregation {
+    /// Return as soon as any member errors or panics, cancelling (and waiting for) all other
+    /// members first. The error of the first member to fail is returned.
+    FailFast,
+    /// Wait for every member to exit, and return all of their exit statuses.
+    JoinAll,
+}
+
+/// The name and exit status of a group member.
+pub type MemberStatus<E> = (String, ExitStatus<E>);
+
+/// The error returned when [`GroupHandle::rolling_restart`] is aborted.
+pub struct RolloutError<E> {
+    /// The member that exited, or that did not become ready in time.
+    pub member: String,
+    /// The members that were replaced before the rollout was aborted (including `member`),
+    /// along with the exit statuses of the instances they replaced.
+    pub replaced: Vec<MemberStatus<E>>,
+}
+
+impl<E: fmt::Debug> fmt::Debug for RolloutError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RolloutError")
+            .field("member", &self.member)
+            .field("replaced", &self.replaced)
+            .finish()
+    }
+}
+
+/// The outcome of [`GroupHandle::shutdown`].
+pub struct GroupShutdown<E> {
+    /// The members that exited within the grace period, with their exit statuses, in the order
+    /// they exited.
+    pub stopped: Vec<MemberStatus<E>>,
+    /// The members that were still running when the grace period ran out, in the order they were
+    /// added. They were left running in the background.
+    pub detached: Vec<String>,
+}
+
+impl<E: fmt::Debug> fmt::Debug for GroupShutdown<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GroupShutdown")
+            .field("stopped", &self.stopped)
+            .field("detached", &self.detached)
+            .finish()
+    }
+}
+
+/// A collection of named service loops that are managed together.
+///
+/// ```
+/// # use minion::*;
+/// # struct Service;
+/// # impl Cancellable for Service {
+/// #     type Error = ();
+/// #     fn for_each(&mut self) -> Result<LoopState, ()> { Ok(LoopState::Continue) }
+/// # }
+/// let mut group = GroupHandle::new();
+/// group.push("a", Service.spawn());
+/// group.push("b", Service.spawn());
+///
+/// group.cancel_all();
+/// for (name, status) in group.wait_all(Aggregation::JoinAll).unwrap() {
+///     assert!(status.is_success(), "{} failed", name);
+/// }
+/// ```
+pub struct GroupHandle<E> {
+    members: Vec<Member<E>>,
+    fail_fast: Option<Arc<Mutex<Tripwire>>>,
+    stagger: Option<Duration>,
+    jitter: Jitter,
+}
+
+pub(crate) type Factory<E> = Box<dyn FnMut(&str) -> Handle<E> + Send>;
+
+struct Member<E> {
+    name: String,
+    handle: Handle<E>,
+    factory: Option<Factory<E>>,
+}
+
+/// Shared state for groups in fail-fast mode.
+#[derive(Default)]
+struct Tripwire {
+    tripped: bool,
+    cancellers: Vec<Canceller>,
+}
+
+impl Tripwire {
+    fn trip(&mut self) {
+        self.tripped = true;
+        for c in &self.cancellers {
+            c.cancel();
+        }
+    }
+}
+
+impl<E> Default for GroupHandle<E> {
+    fn default() -> Self {
+        GroupHandle {
+            members: Vec::new(),
+            fail_fast: None,
+            stagger: None,
+            jitter: Jitter::None,
+        }
+    }
+}
+
+impl<E> GroupHandle<E> {
+    /// Create a new, empty group.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a new, empty group in which any member erroring or panicking immediately cancels
+    /// all other members.
+    ///
+    /// This is useful for tightly coupled sets of workers where partial operation is worse than
+    /// none. Use [`Aggregation::FailFast`] with [`GroupHandle::wait_all`] to get the error that
+    /// brought the group down.
+    pub fn fail_fast() -> Self {
+        GroupHandle {
+            members: Vec::new(),
+            fail_fast: Some(Arc::default()),
+            stagger: None,
+            jitter: Jitter::None,
+        }
+    }
+
+    /// Delay the start of each member spawned with [`GroupHandle::spawn`] by `interval` more
+    /// than the member before it.
+    ///
+    /// This keeps a large group of pollers spawned at boot from all hitting their upstreams at
+    /// the same instant. Only the initial spawn is staggered; [restarts](GroupHandle::restart)
+    /// start right away. See [`SpawnOptions::start_after`](crate::SpawnOptions::start_after).
+    pub fn stagger(mut self, interval: Duration) -> Self {
+        self.stagger = Some(interval);
+        self
+    }
+
+    /// Randomize the [staggered](GroupHandle::stagger) start delays according to `jitter`.
+    pub fn stagger_jitter(mut self, jitter: Jitter) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Add a running service loop to the group under the given name.
+    ///
+    /// If this is a [fail-fast](GroupHandle::fail_fast) group in which a member has already
+    /// failed, the new member is cancelled immediately.
+    pub fn push<N: Into<String>>(&mut self, name: N, handle: Handle<E>) {
+        self.track(&handle);
+        self.members.push(Member {
+            name: name.into(),
+            handle,
+            factory: None,
+        });
+    }
+
+    /// Spawn a service produced by `factory` and add it to the group under the given name.
+    ///
+    /// Unlike members added with [`GroupHandle::push`], members added this way can later be
+    /// restarted individually with [`GroupHandle::restart`].
+    pub fn spawn<N, F, S>(&mut self, name: N, mut factory: F)
+    where
+        N: Into<String>,
+        F: FnMut() -> S + Send + 'static,
+        S: Cancellable<Error = E> + Send + 'static,
+        E: Send + 'static,
+    {
+        let spawned = self.members.iter().filter(|m| m.factory.is_some()).count();
+        let mut delay = self.stagger.map(|s| s * spawned as u32);
+        let jitter = self.jitter;
+        let mut factory: Factory<E> = Box::new(move |name| {
+            let builder = factory().builder().name(name);
+            match delay.take() {
+                Some(delay) => builder.start_after(delay).start_jitter(jitter).spawn(),
+                None => builder.spawn(),
+            }
+        });
+        let name = name.into();
+        let handle = factory(&name);
+        self.track(&handle);
+        self.members.push(Member {
+            name,
+            handle,
+            factory: Some(factory),
+        });
+    }
+
+    /// Restart the named member by cancelling it, waiting for it to exit, and then spawning a
+    /// fresh instance from its factory. The rest of the group keeps running throughout.
+    ///
+    /// Returns the exit status of the instance that was replaced, or `None` if the group has no
+    /// member by that name that was added with [`GroupHandle::spawn`].
+    pub fn restart(&mut self, name: &str) -> Option<ExitStatus<E>> {
+        let i = self
+            .members
+            .iter()
+            .position(|m| m.name == name && m.factory.is_some())?;
+
+        Some(self.replace(i))
+    }
+
+    /// Restart every factory-spawned member, `batch_size` members at a time.
+    ///
+    /// Each wave of members is cancelled and waited for, and then respawned from their factories.
+    /// The next wave is only started once every member of the current wave has become
+    /// [ready](Handle::is_ready) again, and `delay` has passed. Members added with
+    /// [`GroupHandle::push`] are left alone.
+    ///
+    /// On success, returns the exit statuses of all the replaced instances. If a respawned member
+    /// exits before the next wave starts, or does not become ready within `ready_timeout`, the
+    /// rollout stops right away, and the returned error names that member. The member stays in
+    /// the group either way, so it can be cancelled, or its exit status retrieved, with
+    /// [`GroupHandle::wait_all`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `batch_size` is zero.
+    pub fn rolling_restart(
+        &mut self,
+        batch_size: usize,
+        delay: Duration,
+        ready_timeout: Duration,
+    ) -> Result<Vec<MemberStatus<E>>, RolloutError<E>> {
+        assert!(batch_size > 0, "batch size must be positive");
+        let restartable: Vec<_> = (0..self.members.len())
+            .filter(|&i| self.members[i].factory.is_some())
+            .collect();
+
+        let mut replaced = Vec::new();
+        let mut previous: &[usize] = &[];
+        for batch in restartable.chunks(batch_size) {
+            if let Some(i) = self.first_exit(previous, delay) {
+                return Err(RolloutError {
+                    member: self.members[i].name.clone(),
+                    replaced,
+                });
+            }
+
+            // let the whole wave drain concurrently
+            for &i in batch {
+                self.members[i].handle.cancel();
+            }
+            for &i in batch {
+                let status = self.replace(i);
+                replaced.push((self.members[i].name.clone(), status));
+            }
+            let ready_by = Instant::now() + ready_timeout;
+            for &i in batch {
+                let m = &self.members[i];
+                let left = ready_by.saturating_duration_since(Instant::now());
+                if !m.handle.status.wait_ready(Some(left)) {
+                    return Err(RolloutError {
+                        member: m.name.clone(),
+                        replaced,
+                    });
+                }
+            }
+            previous = batch;
+        }
+        Ok(replaced)
+    }
+
+    /// Wait up to `timeout` for any of the members at `indices` to exit, and return the first
+    /// one that does.
+    fn first_exit(&self, indices: &[usize], timeout: Duration) -> Option<usize> {
+        if indices.is_empty() {
+            return None;
+        }
+        let (tx, rx) = mpsc::channel();
+        for &i in indices {
+            let tx = tx.clone();
+            self.members[i].handle.on_exit(move |_| {
+                let _ = tx.send(i);
+            });
+        }
+        rx.recv_timeout(timeout).ok()
+    }
+
+    /// Cancel the `i`th member, wait for it to exit, and then replace it with a fresh instance
+    /// from its factory.
+    fn replace(&mut self, i: usize) -> ExitStatus<E> {
+        let Member {
+            name,
+            handle,
+            factory,
+        } = self.members.remove(i);
+        handle.cancel();
+        self.untrack(&handle);
+        let status = handle.join();
+
+        let mut factory = factory.expect("only members with factories are restarted");
+        let handle = factory(&name);
+        self.track(&handle);
+        self.members.insert(
+            i,
+            Member {
+                name,
+                handle,
+                factory: Some(factory),
+            },
+        );
+        status
+    }
+
+    /// Wire up a new member's handle to the group's fail-fast machinery, if any.
+    fn track(&self, handle: &Handle<E>) {
+        if let Some(ref tripwire) = self.fail_fast {
+            let mut t = tripwire.lock().unwrap();
+            t.cancellers.push(handle.canceller());
+            if t.tripped {
+                handle.cancel();
+            }
+            drop(t);
+
+            let tripwire = tripwire.clone();
+            handle.on_exit(move |failed| {
+                if failed {
+                    tripwire.lock().unwrap().trip();
+                }
+            });
+        }
+    }
+
+    /// Stop cancelling a replaced member's handle along with the rest of the group.
+    fn untrack(&self, handle: &Handle<E>) {
+        if let Some(ref tripwire) = self.fail_fast {
+            let replaced = handle.canceller();
+            tripwire
+                .lock()
+                .unwrap()
+                .cancellers
+                .retain(|c| *c != replaced);
+        }
+    }
+
+    /// The number of service loops in the group.
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    /// Returns `true` if the group has no members.
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// The names of the group's members, in the order they were added.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.members.iter().map(|m| &*m.name)
+    }
+
+    /// Cancel every service loop in the group.
+    ///
+    /// See [`Canceller::cancel`](crate::Canceller::cancel).
+    pub fn cancel_all(&self) {
+        for m in &self.members {
+            m.handle.cancel();
+        }
+    }
+
+    /// Shut down every member of the group within `grace`, escalating as the deadline nears.
+    ///
+    /// Every member is first asked to [drain](crate::Canceller::drain), so that it can finish
+    /// the work it has already accepted. Members still running halfway through the grace period
+    /// are interrupted by [cancelling](crate::Canceller::cancel) them outright, skipping any
+    /// extension their [`Cancellable::pre_cancel`] hook asks for. Unlike a drain, this also wakes
+    /// up members that are blocked inside [`Cancellable::for_each`] on anything that observes
+    /// cancellation: their [iteration canceller](crate::Context::iteration_canceller), a
+    /// [`CancelListener`](crate::CancelListener) (by waiting on it, awaiting it, or polling its
+    /// file descriptor), the helpers in [`net`](crate::net), or an action returned by
+    /// [`Cancellable::release_on_cancel`]. Members still running once the grace period is over
+    /// are detached: their threads are left to exit in the background, and their names are
+    /// listed in [`GroupShutdown::detached`], so the caller knows exactly which services failed
+    /// to stop in time.
+    ///
+    /// A member that panicked is listed among the stopped members with
+    /// [`ExitStatus::Panicked`]; its panic is not propagated.
+    ///
+    /// ```
+    /// # use minion::*;
+    /// # use std::time::Duration;
+    /// # struct Service;
+    /// # impl Cancellable for Service {
+    /// #     type Error = ();
+    /// #     fn for_each(&mut self) -> Result<LoopState, ()> { Ok(LoopState::Continue) }
+    /// # }
+    /// let mut group = GroupHandle::new();
+    /// group.push("a", Service.spawn());
+    /// group.push("b", Service.spawn());
+    ///
+    /// let shutdown = group.shutdown(Duration::from_secs(10));
+    /// assert!(shutdown.detached.is_empty(), "did not stop: {:?}", shutdown.detached);
+    /// ```
+    pub fn shutdown(self, grace: Duration) -> GroupShutdown<E> {
+        let start = Instant::now();
+        let (escalate, deadline) = (start + grace / 2, start + grace);
+        let (tx, rx) = mpsc::channel();
+        for (i, m) in self.members.iter().enumerate() {
+            m.handle.drain(grace / 2);
+            let tx = tx.clone();
+            m.handle.on_exit(move |_| {
+                let _ = tx.send(i);
+            });
+        }
+
+        let mut members: Vec<_> = self.members.into_iter().map(Some).collect();
+        let mut stopped = Vec::new();
+        let mut escalated = false;
+        while stopped.len() < members.len() {
+            let now = Instant::now();
+            if !escalated && now >= escalate {
+                for m in members.iter().flatten() {
+                    // a pre_cancel hook may turn the first cancel into a drain, but not a second
+                    m.handle.cancel();
+                    m.handle.cancel();
+                }
+                escalated = true;
+            }
+            let until = if escalated { deadline } else { escalate };
+            if now >= until {
+                break;
+            }
+            if let Ok(i) = rx.recv_timeout(until - now) {
+                let m = members[i].take().expect("members only exit once");
+                stopped.push((m.name, m.handle.join()));
+            }
+        }
+
+        GroupShutdown {
+            stopped,
+            detached: members.into_iter().flatten().map(|m| m.name).collect(),
+        }
+    }
+
+    /// Block the current thread until the group's members have exited.
+    ///
+    /// With [`Aggregation::JoinAll`], this waits for every member and always returns `Ok` with
+    /// each member's name and exit status, in the order the members were added.
+    ///
+    /// With [`Aggregation::FailFast`], the first member to error or panic causes all other
+    /// members to be cancelled. Once they have all exited, the name and exit status of that first
+    /// failed member is returned in the `Err` value. If no member fails, the result is the same as
+    /// for [`Aggregation::JoinAll`].
+    pub fn wait_all(
+        self,
+        aggregation: Aggregation,
+    ) -> Result<Vec<MemberStatus<E>>, MemberStatus<E>> {
+        self.wait_all_with(aggregation, |_, _, _, _| {})
+    }
+
+    /// Like [`GroupHandle::wait_all`], but call `progress` as each member exits.
+    ///
+    /// `progress` is given the number of members that have exited so far (including this one),
+    /// the total number of members, and the name and exit status of the member that just exited,
+    /// in the order the members exit. A long shutdown of a large group can use this to tell
+    /// operators (or a service manager waiting on the process) that it is still making progress,
+    /// rather than appearing hung.
+    ///
+    /// ```
+    /// # use minion::*;
+    /// # struct Service;
+    /// # impl Cancellable for Service {
+    /// #     type Error = ();
+    /// #     fn for_each(&mut self) -> Result<LoopState, ()> { Ok(LoopState::Continue) }
+    /// # }
+    /// let mut group = GroupHandle::new();
+    /// group.push("a", Service.spawn());
+    /// group.push("b", Service.spawn());
+    ///
+    /// group.cancel_all();
+    /// let progress = |done, total, name: &str, status: &ExitStatus<()>| {
+    ///     eprintln!("[{}/{}] {} stopped: {:?}", done, total, name, status);
+    /// };
+    /// group.wait_all_with(Aggregation::JoinAll, progress).unwrap();
+    /// ```
+    pub fn wait_all_with<F>(
+        self,
+        aggregation: Aggregation,
+        mut progress: F,
+    ) -> Result<Vec<MemberStatus<E>>, MemberStatus<E>>
+    where
+        F: FnMut(usize, usize, &str, &ExitStatus<E>),
+    {
+        let (tx, rx) = mpsc::channel();
+        for (i, m) in self.members.iter().enumerate() {
+            let tx = tx.clone();
+            m.handle.on_exit(move |_| {
+                let _ = tx.send(i);
+            });
+        }
+
+        let cancellers: Vec<_> = self.members.iter().map(|m| m.handle.canceller()).collect();
+        let mut members: Vec<_> = self.members.into_iter().map(Some).collect();
+        let mut statuses: Vec<_> = (0..members.len()).map(|_| None).collect();
+        let mut failed = None;
+        let total = members.len();
+        for done in 1..=total {
+            let i = rx.recv().expect("every member notifies on exit");
+            let m = members[i].take().expect("members only exit once");
+            let (name, status) = (m.name, m.handle.join());
+            progress(done, total, &name, &status);
+            let fail_fast = aggregation == Aggregation::FailFast;
+            if fail_fast && failed.is_none() && !status.is_success() {
+                for c in &cancellers {
+                    c.cancel();
+                }
+                failed = Some((name, status));
+            } else {
+                statuses[i] = Some((name, status));
+            }
+        }
+
+        match failed {
+            Some(failed) => Err(failed),
+            None => Ok(statuses.into_iter().map(Option::unwrap).collect()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use super::Member;
+    use std::time::Duration;
+
+    struct Sleepy;
+
+    impl Cancellable for Sleepy {
+        type Error = &'static str;
+        fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+            thread::sleep(Duration::from_millis(1));
+            Ok(LoopState::Continue)
+        }
+    }
+
+    struct Failing;
+
+    impl Cancellable for Failing {
+        type Error = &'static str;
+        fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+            thread::sleep(Duration::from_millis(10));
+            Err("failed")
+        }
+    }
+
+    #[test]
+    fn fail_fast_cancels_the_rest() {
+        let mut group = GroupHandle::new();
+        group.push("sleepy", Sleepy.spawn());
+        group.push("failing", Failing.spawn());
+        match group.wait_all(Aggregation::FailFast) {
+            Err((name, ExitStatus::Failed(e))) => {
+                assert_eq!(name, "failing");
+                assert_eq!(e, "failed");
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn fail_fast_group_cancels_without_waiting() {
+        let mut group = GroupHandle::fail_fast();
+        let sleepy = Sleepy.spawn();
+        let sleepy_status = sleepy.status.clone();
+        group.push("sleepy", sleepy);
+        group.push("failing", Failing.spawn());
+
+        // nobody is waiting on the group, yet sleepy should still exit
+        let mut state = sleepy_status.state.lock().unwrap();
+        while !state.exited {
+            state = sleepy_status.cvar.wait(state).unwrap();
+        }
+        drop(state);
+
+        let (name, _) = group.wait_all(Aggregation::FailFast).unwrap_err();
+        assert_eq!(name, "failing");
+    }
+
+    #[test]
+    fn it_restarts_a_single_member() {
+        let mut group = GroupHandle::new();
+        group.spawn("sleepy", || Sleepy);
+        group.push("other", Sleepy.spawn());
+
+        assert!(group.restart("sleepy").unwrap().is_success());
+        assert!(group.restart("other").is_none());
+        assert!(group.restart("missing").is_none());
+
+        group.cancel_all();
+        let statuses = group.wait_all(Aggregation::JoinAll).unwrap();
+        assert_eq!(statuses.len(), 2);
+        assert!(statuses.iter().all(|(_, s)| s.is_success()));
+
+        // replaced members no longer take part in fail-fast cancellation
+        let mut group = GroupHandle::fail_fast();
+        group.spawn("sleepy", || Sleepy);
+        for _ in 0..3 {
+            assert!(group.restart("sleepy").unwrap().is_success());
+        }
+        let tripwire = group.fail_fast.clone().unwrap();
+        let cancellers = tripwire.lock().unwrap().cancellers.clone();
+        assert_eq!(cancellers, [group.members[0].handle.canceller()]);
+        group.cancel_all();
+        group.wait_all(Aggregation::JoinAll).unwrap();
+    }
+
+    #[test]
+    fn it_staggers_spawned_members() {
+        let start = std::time::Instant::now();
+        let mut group = GroupHandle::new().stagger(Duration::from_millis(20));
+        for name in &["a", "b", "c"] {
+            group.spawn(*name, || Sleepy);
+        }
+        assert!(group.members[2].handle.wait_ready(Duration::from_secs(1)));
+        assert!(start.elapsed() >= Duration::from_millis(40));
+
+        // shutting down does not wait out the remaining delays
+        let mut group = GroupHandle::new().stagger(Duration::from_secs(60));
+        group.spawn("a", || Sleepy);
+        group.spawn("b", || Sleepy);
+        group.cancel_all();
+        group.wait_all(Aggregation::JoinAll).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(60));
+    }
+
+    #[test]
+    fn it_rolls_through_all_members() {
+        let mut group = GroupHandle::new();
+        for name in &["a", "b", "c"] {
+            group.spawn(*name, || Sleepy);
+        }
+        group.push("pushed", Sleepy.spawn());
+
+        let timeout = Duration::from_secs(1);
+        let replaced = group.rolling_restart(2, Duration::from_millis(1), timeout).unwrap();
+        let names: Vec<_> = replaced.iter().map(|(n, _)| &**n).collect();
+        assert_eq!(names, ["a", "b", "c"]);
+        assert!(group.members.iter().all(|m| m.handle.is_ready()));
+
+        group.cancel_all();
+        group.wait_all(Aggregation::JoinAll).unwrap();
+    }
+
+    #[test]
+    fn rollout_gives_up_on_stalled_members() {
+        struct Unready;
+        impl Cancellable for Unready {
+            type Error = &'static str;
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                thread::sleep(Duration::from_millis(1));
+                Ok(LoopState::Continue)
+            }
+        }
+        impl ReadinessProbe for Unready {
+            fn ready(&mut self) -> bool {
+                false
+            }
+        }
+
+        // a member that stays alive, but never becomes ready once restarted
+        let mut group = GroupHandle::new();
+        let schedule = ProbeSchedule::new().period(Duration::from_millis(1));
+        group.members.push(Member {
+            name: String::from("unready"),
+            handle: Unready.spawn(),
+            factory: Some(Box::new(move |name| {
+                Unready.builder().name(name).readiness_probe(schedule).spawn()
+            })),
+        });
+        let start = std::time::Instant::now();
+        let (delay, timeout) = (Duration::from_millis(1), Duration::from_millis(50));
+        let e = group.rolling_restart(1, delay, timeout).unwrap_err();
+        assert_eq!(e.member, "unready");
+        assert!(start.elapsed() < Duration::from_secs(1));
+        group.cancel_all();
+        group.wait_all(Aggregation::JoinAll).unwrap();
+
+        // a member that fails while the next wave is held back stops the rollout right away
+        let mut group = GroupHandle::new();
+        group.spawn("failing", || Failing);
+        group.spawn("sleepy", || Sleepy);
+        let start = std::time::Instant::now();
+        let (delay, timeout) = (Duration::from_secs(60), Duration::from_secs(1));
+        let e = group.rolling_restart(1, delay, timeout).unwrap_err();
+        assert_eq!(e.member, "failing");
+        assert_eq!(e.replaced.len(), 1);
+        assert!(start.elapsed() < Duration::from_secs(1));
+        group.cancel_all();
+        assert!(group.wait_all(Aggregation::JoinAll).is_ok());
+    }
+
+    #[test]
+    fn it_reports_progress() {
+        let mut group = GroupHandle::new();
+        let sleepy = Sleepy.spawn();
+        let c = sleepy.canceller();
+        group.push("sleepy", sleepy);
+        group.push("failing", Failing.spawn());
+        let mut seen = Vec::new();
+        let statuses = group
+            .wait_all_with(Aggregation::JoinAll, |done, total, name, status| {
+                seen.push((done, total, name.to_string(), status.is_success()));
+                c.cancel();
+            })
+            .unwrap();
+        assert_eq!(statuses[0].0, "sleepy");
+        assert_eq!(seen[0], (1, 2, "failing".to_string(), false));
+        assert_eq!(seen[1], (2, 2, "sleepy".to_string(), true));
+    }
+
+    #[test]
+    fn shutdown_escalates_and_detaches() {
+        struct Stuck;
+        impl Cancellable for Stuck {
+            type Error = &'static str;
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                thread::sleep(Duration::from_millis(500));
+                Ok(LoopState::Continue)
+            }
+        }
+        struct Stubborn;
+        impl Cancellable for Stubborn {
+            type Error = &'static str;
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                thread::sleep(Duration::from_millis(1));
+                Ok(LoopState::Continue)
+            }
+            fn drain(&mut self, _: Duration) -> Result<LoopState, Self::Error> {
+                // never finishes draining, so only cancellation stops it
+                thread::sleep(Duration::from_millis(1));
+                Ok(LoopState::Continue)
+            }
+        }
+
+        let mut group = GroupHandle::new();
+        group.push("sleepy", Sleepy.spawn());
+        group.push("stubborn", Stubborn.spawn());
+        group.push("stuck", Stuck.spawn());
+        thread::sleep(Duration::from_millis(10));
+        let start = std::time::Instant::now();
+        let shutdown = group.shutdown(Duration::from_millis(200));
+        assert!(start.elapsed() < Duration::from_millis(400));
+        let stopped: Vec<_> = shutdown.stopped.iter().map(|(n, _)| &**n).collect();
+        assert_eq!(stopped, ["sleepy", "stubborn"]);
+        assert_eq!(shutdown.detached, ["stuck"]);
+    }
+
+    #[test]
+    fn shutdown_interrupts_blocked_members() {
+        struct Blocked;
+        impl Cancellable for Blocked {
+            type Error = &'static str;
+            fn for_each_ctx(&mut self, ctx: &mut Context) -> Result<LoopState, Self::Error> {
+                // a drain does not end this wait, only the escalation to cancel does
+                ctx.iteration_canceller().listener().wait();
+                Ok(LoopState::Continue)
+            }
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                unreachable!()
+            }
+            fn pre_cancel(&self) -> Option<Box<dyn FnOnce() -> Option<Duration> + Send>> {
+                Some(Box::new(|| Some(Duration::from_secs(60))))
+            }
+        }
+
+        let mut group = GroupHandle::new();
+        group.push("blocked", Blocked.spawn());
+        thread::sleep(Duration::from_millis(10));
+        let start = std::time::Instant::now();
+        let shutdown = group.shutdown(Duration::from_millis(200));
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(start.elapsed() < Duration::from_millis(200));
+        assert!(shutdown.detached.is_empty());
+        assert_eq!(shutdown.stopped.len(), 1);
+        assert!(shutdown.stopped[0].1.is_success());
+    }
+
+    #[test]
+    fn join_all_collects_everything() {
+        let mut group = GroupHandle::new();
+        let sleepy = Sleepy.spawn();
+        sleepy.cancel();
+        group.push("sleepy", sleepy);
+        group.push("failing", Failing.spawn());
+        let statuses = group.wait_all(Aggregation::JoinAll).unwrap();
+        assert_eq!(statuses.len(), 2);
+        assert!(statuses[0].1.is_success());
+        assert!(!statuses[1].1.is_success());
+    }
+}
@@ -0,0 +1,90 @@
+use super::Canceller;
+
+/// A guard that cancels a service loop when it goes out of scope.
+///
+/// Obtained through [`Canceller::guard`]. Since the guard cancels when it is dropped, the service
+/// loop is stopped however its owning scope ends, including by unwinding from a panic. This makes
+/// it easy to tie request-scoped or test-scoped services to the scope that uses them:
+///
+/// ```
+/// # use minion::*;
+/// # struct Service;
+/// # impl Cancellable for Service {
+/// #     type Error = ();
+/// #     fn for_each(&mut self) -> Result<LoopState, ()> { Ok(LoopState::Continue) }
+/// # }
+/// let h = Service.spawn();
+/// {
+///     let _guard = h.guard();
+///     // use the service
+/// }
+/// assert!(h.is_cancelled());
+/// h.wait().unwrap();
+/// ```
+#[must_use = "the service loop is cancelled as soon as the guard is dropped"]
+pub struct CancelGuard {
+    canceller: Option<Canceller>,
+}
+
+impl CancelGuard {
+    /// Let the service loop keep running after the guard goes out of scope.
+    ///
+    /// Returns the guarded canceller, so that the loop can still be cancelled later.
+    pub fn disarm(mut self) -> Canceller {
+        self.canceller.take().expect("guard is only disarmed once")
+    }
+}
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        if let Some(ref canceller) = self.canceller {
+            canceller.cancel();
+        }
+    }
+}
+
+impl Canceller {
+    /// Get a guard that cancels the service loop when it is dropped.
+    pub fn guard(&self) -> CancelGuard {
+        CancelGuard {
+            canceller: Some(self.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use std::panic;
+
+    struct Forever;
+    impl Cancellable for Forever {
+        type Error = ();
+        fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            Ok(LoopState::Continue)
+        }
+    }
+
+    #[test]
+    fn it_cancels_on_unwind() {
+        let h = Forever.spawn();
+        let c = h.canceller();
+        let r = panic::catch_unwind(move || {
+            let _guard = c.guard();
+            panic!("request failed");
+        });
+        assert!(r.is_err());
+        assert!(h.is_cancelled());
+        h.wait().unwrap();
+    }
+
+    #[test]
+    fn it_can_be_disarmed() {
+        let h = Forever.spawn();
+        let c = h.guard().disarm();
+        assert!(!h.is_cancelled());
+        c.cancel();
+        h.wait().unwrap();
+    }
+}
@@ -0,0 +1,229 @@
+use super::{ExitStatus, Handle};
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// A slot for passing a resource from one generation of a service to the next.
+///
+/// The typical use is handing a bound socket from a service that is shutting down to its
+/// replacement, so that restarts neither close the listening socket nor drop connections that
+/// are queued on it. The old service puts its listener into the slot as it tears down (for
+/// example in its [`Drop`] implementation, which runs on the service thread once its loop has
+/// exited), and the replacement's factory takes it back out:
+///
+/// ```
+/// # use minion::*;
+/// # use std::{io, net, time::Duration};
+/// struct Server {
+///     listener: Option<net::TcpListener>,
+///     handoff: Handoff<net::TcpListener>,
+/// }
+///
+/// impl Cancellable for Server {
+///     type Error = io::Error;
+///     fn for_each(&mut self) -> Result<LoopState, io::Error> {
+///         // accept and handle connections on self.listener
+/// #       Ok(LoopState::Continue)
+///     }
+/// }
+///
+/// impl Drop for Server {
+///     fn drop(&mut self) {
+///         if let Some(l) = self.listener.take() {
+///             self.handoff.put(l);
+///         }
+///     }
+/// }
+///
+/// let handoff = Handoff::new();
+/// let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+/// let addr = listener.local_addr().unwrap();
+/// let old = Server { listener: Some(listener), handoff: handoff.clone() }.spawn();
+///
+/// // time for an upgrade!
+/// old.cancel();
+/// let listener = handoff.take_timeout(Duration::from_secs(5)).unwrap();
+/// assert_eq!(listener.local_addr().unwrap(), addr);
+/// let new = Server { listener: Some(listener), handoff: handoff.clone() }.spawn();
+/// old.wait().unwrap();
+/// # new.cancel();
+/// # new.wait().unwrap();
+/// ```
+pub struct Handoff<T> {
+    slot: Arc<(Mutex<Option<T>>, Condvar)>,
+}
+
+impl<T> Clone for Handoff<T> {
+    fn clone(&self) -> Self {
+        Handoff {
+            slot: self.slot.clone(),
+        }
+    }
+}
+
+impl<T> Default for Handoff<T> {
+    fn default() -> Self {
+        Handoff {
+            slot: Arc::new((Mutex::new(None), Condvar::new())),
+        }
+    }
+}
+
+impl<T> Handoff<T> {
+    /// Create a new, empty slot.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Place `value` in the slot, and return whatever was there before.
+    pub fn put(&self, value: T) -> Option<T> {
+        let old = self.slot.0.lock().unwrap().replace(value);
+        self.slot.1.notify_all();
+        old
+    }
+
+    /// Take the value out of the slot, if there is one.
+    pub fn take(&self) -> Option<T> {
+        self.slot.0.lock().unwrap().take()
+    }
+
+    /// Take the value out of the slot, waiting up to `timeout` for one to be put there.
+    pub fn take_timeout(&self, timeout: Duration) -> Option<T> {
+        let deadline = Instant::now() + timeout;
+        let mut slot = self.slot.0.lock().unwrap();
+        loop {
+            if let Some(v) = slot.take() {
+                return Some(v);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            slot = self.slot.1.wait_timeout(slot, deadline - now).unwrap().0;
+        }
+    }
+}
+
+/// The error returned when [`handover`] rolls back to the old service.
+pub struct HandoverError<E> {
+    /// The old service, which was left running untouched.
+    pub old: Handle<E>,
+    /// The exit status of the replacement, which was cancelled after it failed to become ready.
+    pub new: ExitStatus<E>,
+}
+
+impl<E: fmt::Debug> fmt::Debug for HandoverError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HandoverError")
+            .field("new", &self.new)
+            .finish()
+    }
+}
+
+/// Replace the service behind `old` with the one behind `new` without downtime.
+///
+/// The handover proceeds in the following steps:
+///
+///  1. Wait up to `ready_timeout` for `new` to become [ready](Handle::is_ready).
+///  2. Call `switch`, which should redirect traffic from the old service to the new one.
+///  3. Cancel the old service and wait for it to exit.
+///
+/// On success, the handle to the new service is returned along with the old service's exit
+/// status. If the new service does not become ready in time (or exits before becoming ready),
+/// `switch` is never called; instead the new service is cancelled, and the still-running old
+/// service is handed back in the error.
+///
+/// ```
+/// # use minion::*;
+/// # use std::time::Duration;
+/// # struct Service;
+/// # impl Cancellable for Service {
+/// #     type Error = ();
+/// #     fn for_each(&mut self) -> Result<LoopState, ()> { Ok(LoopState::Continue) }
+/// # }
+/// let blue = Service.spawn();
+/// let green = Service.spawn();
+/// let (green, blue_status) = handover(blue, green, Duration::from_secs(5), || {
+///     // e.g., update the load balancer to point at green
+/// })
+/// .unwrap();
+/// assert!(blue_status.is_success());
+/// # green.cancel();
+/// # green.wait().unwrap();
+/// ```
+pub fn handover<E, F>(
+    old: Handle<E>,
+    new: Handle<E>,
+    ready_timeout: Duration,
+    switch: F,
+) -> Result<(Handle<E>, ExitStatus<E>), HandoverError<E>>
+where
+    F: FnOnce(),
+{
+    if !new.wait_ready(ready_timeout) {
+        new.cancel();
+        return Err(HandoverError {
+            old,
+            new: new.join(),
+        });
+    }
+
+    switch();
+    old.cancel();
+    Ok((new, old.join()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use std::time::Duration;
+
+    struct Service;
+
+    impl Cancellable for Service {
+        type Error = ();
+        fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+            thread::yield_now();
+            Ok(LoopState::Continue)
+        }
+    }
+
+    #[test]
+    fn it_rolls_back_if_the_new_service_fails() {
+        let old = Service.spawn();
+        let new = from_future(std::future::ready(Err(())));
+        let mut switched = false;
+        let e = match handover(old, new, Duration::from_secs(1), || switched = true) {
+            Ok(_) => panic!("handover should have been rolled back"),
+            Err(e) => e,
+        };
+        assert!(!switched);
+        assert!(!e.new.is_success());
+        assert!(e.old.is_ready());
+        e.old.cancel();
+        e.old.wait().unwrap();
+    }
+
+    #[test]
+    fn handoff_waits_for_a_value() {
+        let slot = Handoff::new();
+        assert!(slot.take().is_none());
+        let s = slot.clone();
+        let t = thread::spawn(move || s.take_timeout(Duration::from_secs(10)));
+        thread::sleep(Duration::from_millis(10));
+        assert!(slot.put(42).is_none());
+        assert_eq!(t.join().unwrap(), Some(42));
+    }
+
+    #[test]
+    fn it_switches_over() {
+        let old = Service.spawn();
+        let new = Service.spawn();
+        let mut switched = false;
+        let (new, old) = handover(old, new, Duration::from_secs(1), || switched = true).unwrap();
+        assert!(switched);
+        assert!(old.is_success());
+        new.cancel();
+        new.wait().unwrap();
+    }
+}
@@ -0,0 +1,204 @@
+use super::{Cancellable, LoopState, Registry};
+use std::io;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+/// The health of one service, as published by a [`Heartbeat`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Beat {
+    /// The name the service is registered under.
+    pub name: String,
+    /// Whether the service is [ready](crate::Handle::is_ready).
+    pub ready: bool,
+    /// The number of iterations the service has completed, including warmup iterations.
+    pub iterations: u64,
+    /// How long ago the service last completed an iteration, or `None` if it has yet to complete
+    /// one.
+    pub since_last_iteration: Option<Duration>,
+}
+
+/// A service that periodically publishes the health of every service in a [`Registry`].
+///
+/// Each iteration takes a [`Beat`] from every running service in the registry, and hands them
+/// all to the sink. Since the heartbeat runs on a thread of its own, an external watchdog that
+/// listens for it can tell when a service is stuck in an iteration (its `since_last_iteration`
+/// keeps growing) even if the rest of the process looks fine, and can tell that the whole
+/// process is stuck when the heartbeats stop arriving. Spawn the heartbeat with a
+/// [throttle](crate::ServiceBuilder::throttle) to decide how often it publishes:
+///
+/// ```no_run
+/// # use minion::*;
+/// # use std::time::Duration;
+/// let mut statsd = StatsdSink::new("127.0.0.1:8125", "my-daemon")?;
+/// let h = Heartbeat::new(Registry::global(), move |beats| statsd.send(beats))
+///     .builder()
+///     .throttle(Duration::from_secs(10))
+///     .spawn();
+/// # h.cancel();
+/// # Ok::<_, std::io::Error>(())
+/// ```
+pub struct Heartbeat<F> {
+    registry: Registry,
+    sink: F,
+}
+
+impl<F, E> Heartbeat<F>
+where
+    F: FnMut(&[Beat]) -> Result<(), E>,
+{
+    /// Create a heartbeat that publishes the health of the services in `registry` to `sink`.
+    pub fn new(registry: &Registry, sink: F) -> Self {
+        Heartbeat {
+            registry: registry.clone(),
+            sink,
+        }
+    }
+}
+
+impl<F, E> Cancellable for Heartbeat<F>
+where
+    F: FnMut(&[Beat]) -> Result<(), E>,
+{
+    type Error = E;
+
+    fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+        let mut beats = Vec::new();
+        self.registry.each(|name, _, status| {
+            let stats = status.counters.snapshot();
+            beats.push(Beat {
+                name: name.to_string(),
+                ready: status.is_ready(),
+                iterations: stats.iterations + stats.warmup_iterations,
+                since_last_iteration: stats.last_iteration.map(|t| t.elapsed()),
+            });
+        });
+        (self.sink)(&beats)?;
+        Ok(LoopState::Continue)
+    }
+}
+
+/// A [`Heartbeat`] sink that sends beats to a StatsD server over UDP.
+///
+/// Every beat becomes three gauges, `<prefix>.<name>.ready` (`0` or `1`),
+/// `<prefix>.<name>.iterations`, and `<prefix>.<name>.since_last_iteration_ms` (left out until the
+/// service has completed an iteration), all sent together in a single datagram. Characters that
+/// StatsD gives meaning to are replaced with `_` in service names.
+#[derive(Debug)]
+pub struct StatsdSink {
+    socket: UdpSocket,
+    prefix: String,
+}
+
+impl StatsdSink {
+    /// Send gauges named under `prefix` to the StatsD server at `addr`.
+    pub fn new<A: ToSocketAddrs>(addr: A, prefix: &str) -> io::Result<Self> {
+        let addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to send to"))?;
+        let local = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(addr)?;
+        Ok(StatsdSink {
+            socket,
+            prefix: prefix.to_string(),
+        })
+    }
+
+    /// Send `beats` to the server.
+    pub fn send(&mut self, beats: &[Beat]) -> io::Result<()> {
+        if beats.is_empty() {
+            return Ok(());
+        }
+        let mut packet = String::new();
+        for beat in beats {
+            let name: String = beat
+                .name
+                .chars()
+                .map(|c| match c {
+                    ':' | '|' | '@' | '\n' | ' ' => '_',
+                    c => c,
+                })
+                .collect();
+            let metric = format!("{}.{}", self.prefix, name);
+            packet.push_str(&format!("{}.ready:{}|g\n", metric, beat.ready as u8));
+            packet.push_str(&format!("{}.iterations:{}|g\n", metric, beat.iterations));
+            if let Some(since) = beat.since_last_iteration {
+                packet.push_str(&format!(
+                    "{}.since_last_iteration_ms:{}|g\n",
+                    metric,
+                    since.as_millis()
+                ));
+            }
+        }
+        packet.pop();
+        self.socket.send(packet.as_bytes()).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use std::time::Duration;
+
+    struct Forever;
+    impl Cancellable for Forever {
+        type Error = ();
+        fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+            std::thread::sleep(Duration::from_millis(1));
+            Ok(LoopState::Continue)
+        }
+    }
+
+    #[test]
+    fn it_publishes_beats() {
+        use std::sync::mpsc;
+
+        let registry = Registry::new();
+        let h = Forever.builder().name("worker").register(&registry).spawn();
+        assert!(h.wait_ready(Duration::from_secs(1)));
+
+        let (tx, rx) = mpsc::channel();
+        let beats = Heartbeat::new(&registry, move |beats: &[Beat]| tx.send(beats.to_vec()))
+            .builder()
+            .throttle(Duration::from_millis(10))
+            .spawn();
+        let beat = loop {
+            let mut beat = rx.recv().unwrap();
+            assert_eq!(beat.len(), 1);
+            let beat = beat.remove(0);
+            if beat.iterations > 0 {
+                break beat;
+            }
+        };
+        assert_eq!(beat.name, "worker");
+        assert!(beat.ready);
+        assert!(beat.since_last_iteration.is_some());
+
+        h.cancel();
+        h.wait().unwrap();
+        beats.cancel();
+        beats.wait().unwrap();
+    }
+
+    #[test]
+    fn statsd_sends_gauges() {
+        let server = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut sink = StatsdSink::new(server.local_addr().unwrap(), "app").unwrap();
+        sink.send(&[Beat {
+            name: "a:b".to_string(),
+            ready: true,
+            iterations: 7,
+            since_last_iteration: Some(Duration::from_millis(42)),
+        }])
+        .unwrap();
+
+        let mut buf = [0; 512];
+        let n = server.recv(&mut buf).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&buf[..n]).unwrap(),
+            "app.a_b.ready:1|g\napp.a_b.iterations:7|g\napp.a_b.since_last_iteration_ms:42|g"
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// A counter of work items that are still being processed.
+///
+/// Cancelling a service loop only stops it from *accepting* new work; connections or jobs it has
+/// already handed off (say, to other threads) may still be in progress. `InFlight` lets the
+/// service account for those items, so that whoever shuts the service down can wait for them to
+/// finish before, for example, exiting the process.
+///
+/// The service calls [`InFlight::enter`] for every item it accepts, and keeps the returned guard
+/// alive (moving it to whichever thread ends up processing the item) until the item is done:
+///
+/// ```
+/// # use minion::*;
+/// # use std::{thread, time::Duration};
+/// struct Service(InFlight);
+/// impl Cancellable for Service {
+///     type Error = ();
+///     fn for_each(&mut self) -> Result<LoopState, ()> {
+///         // wait for a work item to arrive
+///         thread::sleep(Duration::from_millis(10));
+///         let guard = self.0.enter();
+///         thread::spawn(move || {
+///             // process the work item
+///             drop(guard);
+///         });
+///         Ok(LoopState::Continue)
+///     }
+/// }
+///
+/// let in_flight = InFlight::new();
+/// let h = Service(in_flight.clone()).spawn();
+///
+/// // stop accepting new work
+/// h.cancel();
+/// h.wait().unwrap();
+/// // and then wait for work that was already accepted to finish
+/// assert!(in_flight.wait_idle(Duration::from_secs(30)));
+/// ```
+#[derive(Clone, Default)]
+pub struct InFlight {
+    inner: Arc<(Mutex<usize>, Condvar)>,
+}
+
+/// A work item tracked by an [`InFlight`] counter.
+///
+/// The item is considered finished once the guard is dropped. Cloning the guard counts as
+/// another item.
+pub struct InFlightGuard {
+    tracker: InFlight,
+}
+
+impl InFlight {
+    /// Create a new counter with no items in flight.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that a new work item has been accepted.
+    pub fn enter(&self) -> InFlightGuard {
+        *self.inner.0.lock().unwrap() += 1;
+        InFlightGuard {
+            tracker: self.clone(),
+        }
+    }
+
+    /// The number of work items currently in flight.
+    pub fn count(&self) -> usize {
+        *self.inner.0.lock().unwrap()
+    }
+
+    /// Block until no work items are in flight, or until `timeout` elapses.
+    ///
+    /// Returns `true` if the count reached zero.
+    pub fn wait_idle(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut count = self.inner.0.lock().unwrap();
+        while *count != 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            count = self.inner.1.wait_timeout(count, deadline - now).unwrap().0;
+        }
+        true
+    }
+}
+
+impl Clone for InFlightGuard {
+    fn clone(&self) -> Self {
+        self.tracker.enter()
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let mut count = self.tracker.inner.0.lock().unwrap();
+        *count -= 1;
+        if *count == 0 {
+            self.tracker.inner.1.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn it_counts() {
+        let in_flight = InFlight::new();
+        let a = in_flight.enter();
+        let b = a.clone();
+        assert_eq!(in_flight.count(), 2);
+        drop(a);
+        assert!(!in_flight.wait_idle(Duration::from_millis(1)));
+
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            drop(b);
+        });
+        assert!(in_flight.wait_idle(Duration::from_secs(10)));
+        t.join().unwrap();
+    }
+}
@@ -0,0 +1,110 @@
+//! Helpers for persisting state from services.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Replace the contents of the file at `path` with `contents`, such that anyone reading the file
+/// sees either all of the old contents or all of the new ones.
+///
+/// The contents are written to a temporary file next to `path`, flushed to disk, and then renamed
+/// into place. If the process dies partway through, say because the service was
+/// [draining](crate::Cancellable::drain) and ran out of time, `path` is left as it was, rather
+/// than truncated or half-written as it could be with [`fs::write`]. This makes it a good fit for
+/// saving a service's state on its way out:
+///
+/// ```no_run
+/// # use minion::*;
+/// # use std::time::Duration;
+/// struct Indexer {
+///     offset: u64,
+/// }
+///
+/// impl Cancellable for Indexer {
+///     type Error = std::io::Error;
+///     fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+///         self.offset += 1;
+///         Ok(LoopState::Continue)
+///     }
+///     fn drain(&mut self, _: Duration) -> Result<LoopState, Self::Error> {
+///         minion::io::atomic_write("/var/lib/indexer/offset", self.offset.to_string())?;
+///         Ok(LoopState::Break)
+///     }
+/// }
+/// ```
+///
+/// The temporary file is removed again if writing it fails.
+pub fn atomic_write<P, C>(path: P, contents: C) -> io::Result<()>
+where
+    P: AsRef<Path>,
+    C: AsRef<[u8]>,
+{
+    let path = path.as_ref();
+    let tmp = temp_path(path)?;
+    let r = write_synced(&tmp, contents.as_ref()).and_then(|()| fs::rename(&tmp, path));
+    if r.is_err() {
+        let _ = fs::remove_file(&tmp);
+        return r;
+    }
+    sync_dir(path)
+}
+
+/// A path next to `path` that no other write (from this process) is using.
+fn temp_path(path: &Path) -> io::Result<PathBuf> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "cannot write to a directory path")
+    })?;
+    let mut tmp = std::ffi::OsString::from(".");
+    tmp.push(name);
+    tmp.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    Ok(path.with_file_name(tmp))
+}
+
+fn write_synced(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut f = File::create(path)?;
+    f.write_all(contents)?;
+    f.sync_all()
+}
+
+/// Make sure a rename into the directory holding `path` survives a crash.
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_replaces_the_file() {
+        let dir = std::env::temp_dir().join(format!("minion-atomic-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("state");
+
+        atomic_write(&path, "one").unwrap();
+        atomic_write(&path, "two").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "two");
+        // nothing is left lying around
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        assert!(atomic_write(dir.join("missing").join("state"), "x").is_err());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
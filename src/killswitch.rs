@@ -0,0 +1,121 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// A persistent record of which services operators have disabled.
+///
+/// A service spawned with [`ServiceBuilder::kill_switch`](crate::ServiceBuilder::kill_switch)
+/// checks the switch under its name before it starts, and exits right away without running if it
+/// has been disabled. Since the record outlives the process, a misbehaving loop stays down across
+/// restarts until someone re-enables it.
+pub trait KillSwitch: Send {
+    /// Returns `true` if the service named `name` should not run.
+    fn is_disabled(&self, name: &str) -> bool;
+}
+
+/// A [`KillSwitch`] that disables a service by placing a `<name>.disabled` file in a directory.
+///
+/// Operators can flip the switch with [`FileKillSwitch::disable`] and
+/// [`FileKillSwitch::enable`], or just as well by creating and removing the files by hand:
+///
+/// ```no_run
+/// # use minion::*;
+/// # struct Service;
+/// # impl Cancellable for Service {
+/// #     type Error = ();
+/// #     fn for_each(&mut self) -> Result<LoopState, ()> { Ok(LoopState::Continue) }
+/// # }
+/// let switch = FileKillSwitch::new("/var/lib/my-daemon/disabled")?;
+/// // e.g., from an admin command
+/// switch.disable("indexer")?;
+///
+/// // on the next start, the indexer does not run
+/// let h = Service
+///     .builder()
+///     .name("indexer")
+///     .kill_switch(switch.clone())
+///     .spawn();
+/// assert!(!h.wait_ready(std::time::Duration::from_secs(1)));
+/// # Ok::<_, std::io::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct FileKillSwitch {
+    dir: PathBuf,
+}
+
+impl FileKillSwitch {
+    /// Keep the switch in `dir`, creating it if it does not yet exist.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(FileKillSwitch { dir })
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.disabled", name))
+    }
+
+    /// Keep the service named `name` from starting until it is [enabled](FileKillSwitch::enable)
+    /// again.
+    ///
+    /// This does not stop an instance of the service that is already running.
+    pub fn disable(&self, name: &str) -> io::Result<()> {
+        fs::write(self.path(name), b"")
+    }
+
+    /// Let the service named `name` start again.
+    pub fn enable(&self, name: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(name)) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            r => r,
+        }
+    }
+}
+
+impl KillSwitch for FileKillSwitch {
+    fn is_disabled(&self, name: &str) -> bool {
+        self.path(name).exists()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use std::time::Duration;
+
+    struct Forever;
+    impl Cancellable for Forever {
+        type Error = ();
+        fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+            std::thread::sleep(Duration::from_millis(1));
+            Ok(LoopState::Continue)
+        }
+    }
+
+    #[test]
+    fn disabled_services_do_not_start() {
+        let dir = std::env::temp_dir().join(format!("minion-kill-{}", std::process::id()));
+        let switch = FileKillSwitch::new(&dir).unwrap();
+        let spawn = || {
+            Forever
+                .builder()
+                .name("flaky")
+                .kill_switch(switch.clone())
+                .spawn()
+        };
+
+        switch.disable("flaky").unwrap();
+        // as after a process restart
+        let switch = FileKillSwitch::new(&dir).unwrap();
+        assert!(switch.is_disabled("flaky"));
+        let h = spawn();
+        h.wait().unwrap();
+
+        switch.enable("flaky").unwrap();
+        let h = spawn();
+        assert!(h.wait_ready(Duration::from_secs(1)));
+        h.cancel();
+        h.wait().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -37,9 +37,159 @@
 //! ```
 #![deny(missing_docs)]
 
+use std::any::Any;
+use std::fmt;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
 use std::thread;
+use std::time::{Duration, Instant};
+
+mod autoscale;
+pub use autoscale::{Autoscaler, ScalePolicy};
+
+mod backoff;
+pub use backoff::{retry, Backoff, Jitter, RetryError};
+
+mod builder;
+pub use builder::{ServiceBuilder, SpawnOptions};
+
+mod canceller_set;
+pub use canceller_set::CancellerSet;
+
+mod checkpoint;
+pub use checkpoint::{CheckpointStore, ExactlyOnce, FileCheckpoints, MemoryCheckpoints};
+
+mod children;
+pub use children::Children;
+
+mod concurrent;
+pub use concurrent::Concurrent;
+
+#[cfg(feature = "serde")]
+mod config;
+
+mod consumer;
+pub use consumer::Consumer;
+#[cfg(feature = "serde")]
+pub use config::{ConfigChange, FieldChange};
+
+mod context;
+pub use context::Context;
+
+mod errors;
+pub use errors::RecentError;
+
+mod events;
+pub use events::{ExitBus, ExitEvent};
+
+mod ffi;
+pub use ffi::{CancelCallback, FfiCanceller};
+
+mod finish;
+pub use finish::Finish;
+
+mod future;
+pub use future::from_future;
+
+mod group;
+pub use group::{Aggregation, GroupHandle, GroupShutdown, MemberStatus, RolloutError};
+
+mod guard;
+pub use guard::CancelGuard;
+
+mod handover;
+pub use handover::{handover, Handoff, HandoverError};
+
+mod heartbeat;
+pub use heartbeat::{Beat, Heartbeat, StatsdSink};
+
+mod inflight;
+pub use inflight::{InFlight, InFlightGuard};
+
+mod killswitch;
+pub use killswitch::{FileKillSwitch, KillSwitch};
+
+mod listener;
+pub use listener::CancelListener;
+
+mod token;
+pub use token::{CancelToken, Cancelled, StaticCanceller};
+
+pub mod io;
+pub mod net;
+pub use net::per_conn;
+pub mod sim;
+pub mod soak;
+
+mod poll;
+pub use poll::DirScanner;
+
+#[cfg(all(unix, feature = "process"))]
+pub mod process;
+
+#[cfg(all(unix, feature = "upgrade"))]
+pub mod upgrade;
+
+#[cfg(all(unix, feature = "shm"))]
+mod shm;
+#[cfg(all(unix, feature = "shm"))]
+pub use shm::SharedFlag;
+
+mod probe;
+pub use probe::{LivenessProbe, ProbeSchedule, ReadinessProbe};
+
+mod registry;
+pub use registry::Registry;
+
+mod resources;
+pub use resources::Resources;
+
+mod rotate;
+pub use rotate::LogRotator;
+
+/// Block until every service in the [global registry](Registry::global) is quiescent, or until
+/// `timeout` elapses.
+///
+/// This is useful before, say, taking a consistent snapshot of a running daemon. See
+/// [`Registry::quiesce`].
+pub fn quiesce(timeout: Duration) -> bool {
+    Registry::global().quiesce(timeout)
+}
+
+mod service_group;
+pub use service_group::{ServiceError, ServiceGroup};
+
+#[cfg(feature = "serde")]
+mod spec;
+#[cfg(feature = "serde")]
+pub use spec::{ServiceSpec, SpecError};
+
+mod split;
+pub use split::{Controller, Waiter};
+
+mod stats;
+pub use stats::{Activity, Sample, SlowIteration, Stats};
+
+mod supervisor;
+pub use supervisor::{RestartPolicy, RestartsExceeded, Supervisor};
+
+mod timer;
+
+mod transaction;
+pub use transaction::Transactional;
+
+mod window;
+pub use window::MaintenanceWindow;
+
+#[cfg(feature = "tokio")]
+mod async_handle;
+#[cfg(feature = "tokio")]
+pub use async_handle::AsyncHandle;
+
+#[cfg(feature = "tokio")]
+mod async_service;
+#[cfg(feature = "tokio")]
+pub use async_service::{AsyncCancellable, IterationFuture};
 
 /// Indicate whether main service loop should continue accepting new work.
 pub enum LoopState {
@@ -47,8 +197,42 @@ pub enum LoopState {
     Continue,
     /// Stop accepting work and return.
     Break,
+    /// Reinitialize the service with [`Cancellable::reset`], and then continue accepting work.
+    Reset,
+    /// Accept more work, but note that there was none this time around.
+    ///
+    /// This marks the iteration as [`Activity::Idle`].
+    Idle,
+    /// Accept more work, but only after waiting for the given duration.
+    ///
+    /// The wait is cut short if the service loop is cancelled, so polling services can return
+    /// this rather than sleeping in [`Cancellable::for_each`], which would hold up cancellation
+    /// for as long as the sleep.
+    ContinueAfter(Duration),
+}
+
+/// What the service loop should do about an error returned by [`Cancellable::for_each`].
+///
+/// Returned by [`Cancellable::on_error`].
+#[derive(Debug)]
+pub enum ErrorDisposition<E> {
+    /// Ignore the error, and carry on with the next iteration.
+    Continue,
+    /// Ignore the error, but wait for the given duration before the next iteration.
+    ///
+    /// Like with [`LoopState::ContinueAfter`], the wait is cut short if the loop is cancelled.
+    RetryAfter(Duration),
+    /// Treat the error as fatal.
+    ///
+    /// The loop returns with the given error, unless it has a
+    /// [retry policy](ServiceBuilder::retry) that says to try again.
+    Abort(E),
 }
 
+/// How often [`Cancellable::run_with`] checks its token while waiting out a
+/// [`LoopState::ContinueAfter`], since tokens cannot wake a sleeping thread.
+const TOKEN_POLL: Duration = Duration::from_millis(10);
+
 /// A service that implements `Cancellable` can be told to stop accepting new work at any time, and
 /// will return at the first following opportunity.
 ///
@@ -70,8 +254,10 @@ pub enum LoopState {
 /// cancelled.
 ///
 /// For example, the implementation below shows how a classic server accept loop could be turned
-/// into a cancellable accept loop. If [`Handle::cancel`] is called, then at most one more
-/// connection will be accepted before the loop returns and [`Handle::wait`] would too.
+/// into a cancellable accept loop. Since it accepts through a
+/// [`CancellableListener`](net::CancellableListener), calling [`Handle::cancel`] wakes up a
+/// pending `accept` right away, so the loop returns (and [`Handle::wait`] would too) without
+/// waiting for one more connection to arrive.
 ///
 /// ```no_run
 /// # extern crate minion;
@@ -79,25 +265,37 @@ pub enum LoopState {
 /// # use std::{
 /// #     io::{self, prelude::*}, net, thread, time,
 /// # };
-/// struct Service(net::TcpListener);
+/// use minion::net::CancellableListener;
+///
+/// struct Service(CancellableListener);
 /// impl Cancellable for Service {
 ///     type Error = io::Error;
 ///     fn for_each(&mut self) -> Result<minion::LoopState, Self::Error> {
-///         let mut stream = self.0.accept()?.0;
+///         let mut stream = match self.0.accept() {
+///             Ok((stream, _)) => stream,
+///             // the loop was cancelled while waiting for a connection
+///             Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {
+///                 return Ok(minion::LoopState::Break)
+///             }
+///             Err(e) => return Err(e),
+///         };
 ///         write!(stream, "hello!\n")?;
 ///         Ok(minion::LoopState::Continue)
 ///     }
 /// }
 ///
 /// impl Service {
-///     fn new() -> io::Result<Self> {
-///         Ok(Service(net::TcpListener::bind("127.0.0.1:6556")?))
+///     fn new(exit: &Canceller) -> io::Result<Self> {
+///         let listener = net::TcpListener::bind("127.0.0.1:6556")?;
+///         Ok(Service(CancellableListener::new(listener, exit)?))
 ///     }
 /// }
 ///
 /// fn main() {
 /// # fn foo() -> io::Result<()> {
-///     Service::new()?.run()?;
+///     let exit = Canceller::new();
+///     let h = Service::new(&exit)?.builder().canceller(exit).spawn();
+///     h.wait()?;
 /// # Ok(())
 /// # }
 /// # foo().unwrap();
@@ -111,49 +309,327 @@ pub trait Cancellable {
     ///
     /// If it errors, the outer service loop will also return with that same error.
     /// This error can be accessed through `Handle::wait()`.
-    /// If it returns a `LoopState`, the service loop will continue or break accordingly.
+    /// If it returns a `LoopState`, the service loop will continue, break, or reset accordingly.
     /// If it panics, the panic will be propagated to the waiting thread.
     fn for_each(&mut self) -> Result<LoopState, Self::Error>;
 
+    /// Like [`Cancellable::for_each`], but with access to the [`Context`] of the service loop.
+    ///
+    /// The service loop always calls this method rather than [`Cancellable::for_each`] directly.
+    /// The default implementation ignores the context and calls [`Cancellable::for_each`].
+    /// Services that want to use the context should override this method instead, and can
+    /// implement [`Cancellable::for_each`] by passing in a [`Context::new`].
+    fn for_each_ctx(&mut self, ctx: &mut Context) -> Result<LoopState, Self::Error> {
+        let _ = ctx;
+        self.for_each()
+    }
+
+    /// This method is called instead of [`Cancellable::for_each`] once the service loop has been
+    /// asked to [drain](Canceller::drain).
+    ///
+    /// It should finish up work that has already been accepted without accepting any new work, and
+    /// `remaining` is the time left before the loop is cancelled outright. Return
+    /// [`LoopState::Continue`] to be called again (with less time remaining), or
+    /// [`LoopState::Break`] once fully drained, after which the loop exits as cancelled. Errors
+    /// are handled like for [`Cancellable::for_each`], by [`Cancellable::on_error`] and the
+    /// loop's [retry policy](ServiceBuilder::retry).
+    ///
+    /// The default implementation has nothing to drain, and returns [`LoopState::Break`]
+    /// immediately.
+    fn drain(&mut self, remaining: Duration) -> Result<LoopState, Self::Error> {
+        let _ = remaining;
+        Ok(LoopState::Break)
+    }
+
+    /// Returns an action that should be run as soon as the service loop is cancelled.
+    ///
+    /// This is called once, when the service is spawned, and the returned action is registered
+    /// with [`Canceller::on_cancel`]. It therefore runs on the cancelling thread, possibly while
+    /// [`Cancellable::for_each`] is still executing on the service thread, which makes it the
+    /// place to promptly release external leases or locks. Teardown that needs access to the
+    /// service itself belongs in [`Cancellable::on_exit`] instead, which runs on the service
+    /// thread after the loop has exited.
+    ///
+    /// The default implementation returns `None`.
+    fn release_on_cancel(&self) -> Option<Box<dyn FnOnce() + Send>> {
+        None
+    }
+
+    /// Returns a hook that is told when the service loop is about to be cancelled, and that may
+    /// ask for an extension to reach a safe point.
+    ///
+    /// Like [`Cancellable::release_on_cancel`], this is called once when the service is spawned,
+    /// and the hook runs on the thread that calls [`Canceller::cancel`]. If the hook returns a
+    /// duration, the loop is not cancelled right away, but [drained](Canceller::drain) for that
+    /// long instead (capped at [`ServiceBuilder::max_cancel_extension`]). The loop is then
+    /// cancelled outright once [`Cancellable::drain`] returns [`LoopState::Break`], or the
+    /// extension runs out. The hook is only consulted once, so cancelling the loop again during
+    /// the extension takes effect immediately.
+    ///
+    /// The default implementation returns `None`.
+    fn pre_cancel(&self) -> Option<Box<dyn FnOnce() -> Option<Duration> + Send>> {
+        None
+    }
+
+    /// This method is called with every error that [`Cancellable::for_each`] returns, and decides
+    /// what the loop does about it.
+    ///
+    /// Services whose errors are not all equally bad can use this to shrug off the ones that are
+    /// expected to go away on their own, such as a timeout talking to an upstream, while still
+    /// stopping the loop on the ones that are not.
+    ///
+    /// The default implementation returns [`ErrorDisposition::Abort`], so that every error ends
+    /// the loop (or is handled by its [retry policy](ServiceBuilder::retry)).
+    fn on_error(&mut self, err: Self::Error) -> ErrorDisposition<Self::Error> {
+        ErrorDisposition::Abort(err)
+    }
+
+    /// This method is called on the service thread before the loop's first iteration.
+    ///
+    /// It is the place to acquire resources that should live exactly as long as the loop, such as
+    /// connections that are only ever used by the service thread. If it errors, the loop returns
+    /// with that error without running any iterations, and [`Cancellable::on_exit`] is not called.
+    ///
+    /// The default implementation does nothing.
+    fn on_start(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// This method is called on the service thread once the loop has run its last iteration.
+    ///
+    /// It is called however the loop ends, whether it finished, was cancelled, failed, or
+    /// panicked, as long as [`Cancellable::on_start`] succeeded, which makes it the place for
+    /// teardown such as flushing buffers or closing connections cleanly. A panic is propagated
+    /// once `on_exit` returns.
+    ///
+    /// The default implementation does nothing.
+    fn on_exit(&mut self, reason: ExitReason) {
+        let _ = reason;
+    }
+
+    /// This method is called when [`Cancellable::for_each`] returns [`LoopState::Reset`].
+    ///
+    /// It should bring the service back to a state where it can accept work again, such as by
+    /// re-establishing a broken connection or discarding corrupted state, without the service
+    /// having to be torn down and spawned anew. If it errors, the service loop returns with that
+    /// error.
+    ///
+    /// The default implementation does nothing.
+    fn reset(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// This method is called before the next iteration once a reload has been requested through
+    /// [`Handle::reload`] or a [`Registry`].
+    ///
+    /// It should re-read whatever configuration the service depends on and apply it. If it
+    /// errors, the service loop returns with that error.
+    ///
+    /// The default implementation does nothing.
+    fn reload(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Returns the service's current effective configuration.
+    ///
+    /// This is called when the service loop starts and after every [reload](Cancellable::reload).
+    /// The result is published to the [`Registry`] the service is registered with (if any),
+    /// along with a [`ConfigChange`] describing what each reload changed, so operators can tell
+    /// what a reload actually did.
+    ///
+    /// The default implementation returns `None`.
+    #[cfg(feature = "serde")]
+    fn config(&self) -> Option<serde_json::Value> {
+        None
+    }
+
     /// Continuously execute [`Cancellable::for_each`] until it returns an error or a
     /// [`LoopState::Break`].
     fn run(&mut self) -> Result<(), Self::Error> {
+        with_hooks(self, |service| {
+            let mut ctx = Context::new();
+            loop {
+                ctx.start_iteration();
+                match service.for_each_ctx(&mut ctx) {
+                    Ok(LoopState::Continue) | Ok(LoopState::Idle) => {}
+                    Ok(LoopState::ContinueAfter(dur)) => thread::sleep(dur),
+                    Ok(LoopState::Break) => return Ok(true),
+                    Ok(LoopState::Reset) => service.reset()?,
+                    Err(e) => match service.on_error(e) {
+                        ErrorDisposition::Continue => {}
+                        ErrorDisposition::RetryAfter(dur) => thread::sleep(dur),
+                        ErrorDisposition::Abort(e) => return Err(e),
+                    },
+                }
+            }
+        })
+    }
+
+    /// Execute [`Cancellable::for_each`] on the current thread until the service runs out of
+    /// work, and then return.
+    ///
+    /// This is meant for tests, which can use it to deterministically drive a service through
+    /// "process everything that is currently queued" without sleeping and hoping it caught up.
+    /// The service counts as out of work once an iteration returns [`LoopState::Idle`] or
+    /// [`LoopState::ContinueAfter`], reports itself [idle](Context::set_activity), or fails with
+    /// an error that [`Cancellable::on_error`] says to retry later. Nothing is waited out.
+    ///
+    /// Returns `true` if the service [finished](LoopState::Break) instead. Unlike
+    /// [`Cancellable::run`], this does not call [`Cancellable::on_start`] or
+    /// [`Cancellable::on_exit`], so it can be called again and again to drive the same service
+    /// through several rounds of work.
+    ///
+    /// ```
+    /// # use minion::*;
+    /// # use std::sync::mpsc;
+    /// struct Summer(mpsc::Receiver<u64>, u64);
+    /// impl Cancellable for Summer {
+    ///     type Error = ();
+    ///     fn for_each(&mut self) -> Result<LoopState, ()> {
+    ///         match self.0.try_recv() {
+    ///             Ok(n) => self.1 += n,
+    ///             Err(mpsc::TryRecvError::Empty) => return Ok(LoopState::Idle),
+    ///             Err(mpsc::TryRecvError::Disconnected) => return Ok(LoopState::Break),
+    ///         }
+    ///         Ok(LoopState::Continue)
+    ///     }
+    /// }
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// let mut summer = Summer(rx, 0);
+    /// tx.send(1).unwrap();
+    /// tx.send(2).unwrap();
+    /// assert_eq!(summer.run_until_idle(), Ok(false));
+    /// assert_eq!(summer.1, 3);
+    /// drop(tx);
+    /// assert_eq!(summer.run_until_idle(), Ok(true));
+    /// ```
+    fn run_until_idle(&mut self) -> Result<bool, Self::Error> {
+        let mut ctx = Context::new();
         loop {
-            match self.for_each() {
+            ctx.start_iteration();
+            match self.for_each_ctx(&mut ctx) {
+                Ok(LoopState::Continue) if ctx.activity() == Activity::Idle => return Ok(false),
                 Ok(LoopState::Continue) => {}
-                Ok(LoopState::Break) => break,
-                Err(e) => return Err(e),
+                Ok(LoopState::Idle) | Ok(LoopState::ContinueAfter(_)) => return Ok(false),
+                Ok(LoopState::Break) => return Ok(true),
+                Ok(LoopState::Reset) => self.reset()?,
+                Err(e) => match self.on_error(e) {
+                    ErrorDisposition::Continue => {}
+                    ErrorDisposition::RetryAfter(_) => return Ok(false),
+                    ErrorDisposition::Abort(e) => return Err(e),
+                },
             }
         }
-        Ok(())
+    }
+
+    /// Continuously execute [`Cancellable::for_each`] on the current thread until it returns an
+    /// error or a [`LoopState::Break`], or until `token` is cancelled.
+    ///
+    /// Like with [`Cancellable::spawn`], cancellation does not interrupt a currently executing
+    /// [`Cancellable::for_each`]; the token is checked before each iteration.
+    fn run_with<T: CancelToken + ?Sized>(&mut self, token: &T) -> Result<(), Self::Error>
+    where
+        Self: Sized,
+    {
+        with_hooks(self, |service| {
+            let mut ctx = Context::new();
+            while !token.is_cancelled() {
+                ctx.start_iteration();
+                let wait = match service.for_each_ctx(&mut ctx) {
+                    Ok(LoopState::Continue) | Ok(LoopState::Idle) => continue,
+                    Ok(LoopState::ContinueAfter(dur)) => dur,
+                    Ok(LoopState::Break) => return Ok(true),
+                    Ok(LoopState::Reset) => {
+                        service.reset()?;
+                        continue;
+                    }
+                    Err(e) => match service.on_error(e) {
+                        ErrorDisposition::Continue => continue,
+                        ErrorDisposition::RetryAfter(dur) => dur,
+                        ErrorDisposition::Abort(e) => return Err(e),
+                    },
+                };
+                let until = Instant::now() + wait;
+                while !token.is_cancelled() {
+                    let now = Instant::now();
+                    if now >= until {
+                        break;
+                    }
+                    thread::sleep(TOKEN_POLL.min(until - now));
+                }
+            }
+            Ok(false)
+        })
     }
 
     /// Continuously execute [`Cancellable::for_each`] in a new thread, and return a [`Handle`] to
     /// that loop so that it can be cancelled or waited for.
-    fn spawn(mut self) -> Handle<Self::Error>
+    fn spawn(self) -> Handle<Self::Error>
     where
         Self: Sized + Send + 'static,
         Self::Error: Send + 'static,
     {
-        let keep_running = Arc::new(AtomicBool::new(true));
-        let jh = {
-            let keep_running = keep_running.clone();
-            thread::spawn(move || {
-                while keep_running.load(Ordering::Relaxed) {
-                    match self.for_each() {
-                        Ok(LoopState::Continue) => {}
-                        Ok(LoopState::Break) => break,
-                        Err(e) => return Err(e),
-                    }
-                }
-                Ok(())
-            })
-        };
+        self.builder().spawn()
+    }
 
-        Handle {
-            canceller: Canceller { keep_running },
-            executor: jh,
-        }
+    /// Like [`Cancellable::spawn`], but with the given thread-level options applied.
+    ///
+    /// This is a lighter-weight alternative to [`Cancellable::builder`] for when all you need is
+    /// to name the thread, size its stack, or give it a deadline.
+    fn spawn_cfg(self, options: SpawnOptions) -> Handle<Self::Error>
+    where
+        Self: Sized + Send + 'static,
+        Self::Error: Send + 'static,
+    {
+        self.builder().options(options).spawn()
+    }
+
+    /// Like [`Cancellable::spawn`], but stop starting new iterations once `deadline` has passed.
+    ///
+    /// This suits time-boxed work such as batch jobs and soak tests. An iteration that is under
+    /// way when the deadline passes is allowed to finish, after which the loop exits cleanly.
+    /// See [`SpawnOptions::deadline`].
+    fn spawn_with_deadline(self, deadline: Instant) -> Handle<Self::Error>
+    where
+        Self: Sized + Send + 'static,
+        Self::Error: Send + 'static,
+    {
+        self.builder().deadline(deadline).spawn()
+    }
+
+    /// Like [`Cancellable::spawn_with_deadline`], with the deadline `dur` from now.
+    fn spawn_for(self, dur: Duration) -> Handle<Self::Error>
+    where
+        Self: Sized + Send + 'static,
+        Self::Error: Send + 'static,
+    {
+        self.builder().run_for(dur).spawn()
+    }
+
+    /// Like [`Cancellable::spawn`], but convert the service's error using `f` before it is
+    /// returned from the loop.
+    ///
+    /// See [`ServiceBuilder::spawn_map_err`].
+    fn spawn_map_err<F, E>(self, f: F) -> Handle<E>
+    where
+        Self: Sized + Send + 'static,
+        Self::Error: Send + 'static,
+        F: FnOnce(Self::Error) -> E + Send + 'static,
+        E: Send + 'static,
+    {
+        self.builder().spawn_map_err(f)
+    }
+
+    /// Start configuring how this service should be spawned.
+    ///
+    /// See [`ServiceBuilder`] for the available options. Calling [`ServiceBuilder::spawn`] without
+    /// setting any options is equivalent to calling [`Cancellable::spawn`].
+    fn builder(self) -> ServiceBuilder<Self>
+    where
+        Self: Sized,
+    {
+        ServiceBuilder::new(self)
     }
 }
 
@@ -163,137 +639,1272 @@ pub trait Cancellable {
 /// or to wait for the loop to terminate (through [`Handle::wait`]). You can also use
 /// [`Handle::canceller`] to get a [`Canceller`] handle, which lets you terminate the service loop
 /// elsewhere (e.g., while waiting).
-pub struct Handle<E> {
+///
+/// Service loops that [produce a value](Finish) when they exit return it from
+/// [`Handle::wait`] as `T`.
+pub struct Handle<E, T = ()> {
     canceller: Canceller,
-    executor: thread::JoinHandle<Result<(), E>>,
+    status: Arc<Status>,
+    executor: Box<dyn Executor<Result<T, E>>>,
 }
 
-/// A handle that allows the cancellation of a running service loop.
-#[derive(Clone)]
-pub struct Canceller {
-    keep_running: Arc<AtomicBool>,
+/// How a service loop ended.
+///
+/// Service loops that [produce a value](Finish) carry it in [`ExitStatus::Exited`].
+pub enum ExitStatus<E, T = ()> {
+    /// The loop exited cleanly, either because it was cancelled or because
+    /// [`Cancellable::for_each`] returned [`LoopState::Break`].
+    Exited(T),
+    /// [`Cancellable::for_each`] returned an error.
+    Failed(E),
+    /// The service loop panicked with the given payload.
+    Panicked(Box<dyn Any + Send + 'static>),
 }
 
-impl<E> Handle<E> {
-    /// Get another handle for cancelling the service loop.
-    ///
-    /// This can be handy if you want one thread to wait for the service loop to exit, while
-    /// another watches for exit signals.
-    pub fn canceller(&self) -> Canceller {
-        Canceller {
-            keep_running: self.keep_running.clone(),
-        }
+/// Why a service loop that exited cleanly did so, as returned by [`Handle::cancel_and_wait`].
+///
+/// Either way, it carries the loop's result, which is only of interest for service loops that
+/// [produce a value](Finish).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shutdown<T = ()> {
+    /// The loop stopped because it was cancelled, or because it reached its
+    /// [deadline](SpawnOptions::deadline).
+    Cancelled(T),
+    /// The loop stopped on its own, because the service returned [`LoopState::Break`].
+    Finished(T),
+}
+
+/// Why a service loop ended, as passed to [`Cancellable::on_exit`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    /// The service returned [`LoopState::Break`].
+    Finished,
+    /// The loop was cancelled, or reached its [deadline](SpawnOptions::deadline).
+    Cancelled,
+    /// The service returned an error.
+    Failed,
+    /// The service panicked.
+    Panicked,
+    /// The service is being replaced through [`Handle::restart_in_place`].
+    Restarted,
+}
+
+impl<E, T> ExitStatus<E, T> {
+    /// Returns `true` if the loop exited cleanly.
+    pub fn is_success(&self) -> bool {
+        matches!(*self, ExitStatus::Exited(_))
     }
 
-    /// Block the current thread waiting for the service loop to exit, and return its result.
+    /// Convert this status into the result that [`Handle::wait`] would have returned.
     ///
-    /// If the service loop returns an error, this method will return it in the `Err` value.
-    /// If the service loop panics, this method will also panic with the same error. 
-    pub fn wait(self) -> Result<(), E> {
-        match self.executor.join() {
-            Ok(r) => r,
-            Err(e) => {
-                // propagate the panic
-                std::panic::panic_any(e)
-            }
+    /// If the service loop panicked, this method panics with the same payload.
+    pub fn into_result(self) -> Result<T, E> {
+        match self {
+            ExitStatus::Exited(t) => Ok(t),
+            ExitStatus::Failed(e) => Err(e),
+            ExitStatus::Panicked(e) => propagate(Err(e)),
         }
     }
 }
 
-use std::ops::Deref;
-impl<E> Deref for Handle<E> {
-    type Target = Canceller;
-    fn deref(&self) -> &Self::Target {
-        &self.canceller
+impl<E, T> From<thread::Result<Result<T, E>>> for ExitStatus<E, T> {
+    fn from(r: thread::Result<Result<T, E>>) -> Self {
+        match r {
+            Ok(Ok(t)) => ExitStatus::Exited(t),
+            Ok(Err(e)) => ExitStatus::Failed(e),
+            Err(e) => ExitStatus::Panicked(e),
+        }
     }
 }
 
-impl Canceller {
-    /// Cancel the currently running service loop. This method does not block; it sends a signal 
-    /// that the service loop should cease execution and returns immediately.
-    ///
-    /// Note that this will *not* interrupt a currently executing [`Cancellable::for_each`].
-    /// Instead, the next time [`Cancellable::for_each`] *would* be called, the service loop will
-    /// return.
-    pub fn cancel(&self) {
-        self.keep_running.store(false, Ordering::Relaxed);
+impl<E: fmt::Debug, T: fmt::Debug> fmt::Debug for ExitStatus<E, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ExitStatus::Exited(ref t) => f.debug_tuple("Exited").field(t).finish(),
+            ExitStatus::Failed(ref e) => f.debug_tuple("Failed").field(e).finish(),
+            ExitStatus::Panicked(_) => f.write_str("Panicked(..)"),
+        }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::{
-        io::{self, prelude::*}, net, thread,
-    };
-
-    struct Service(net::TcpListener);
+/// Why a service loop did not exit cleanly, as returned by [`Handle::wait_catch`].
+pub enum WaitError<E> {
+    /// [`Cancellable::for_each`] returned an error.
+    Failed(E),
+    /// The service loop panicked with the given payload.
+    Panicked(Box<dyn Any + Send + 'static>),
+}
 
-    impl Cancellable for Service {
-        type Error = io::Error;
-        fn for_each(&mut self) -> Result<LoopState, Self::Error> {
-            let mut stream = match self.0.accept() {
-                Ok((stream, _)) => stream,
-                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {
-                    return Ok(LoopState::Continue)
-                }
-                Err(e) => return Err(e),
-            };
-            write!(stream, "hello!")?;
-            Ok(LoopState::Continue)
+impl<E> WaitError<E> {
+    /// The panic message, if the loop panicked with a string payload (as `panic!` does).
+    pub fn panic_message(&self) -> Option<&str> {
+        match *self {
+            WaitError::Failed(_) => None,
+            WaitError::Panicked(ref e) => panic_message(&**e),
         }
     }
+}
 
-    impl Service {
-        fn new() -> Self {
-            Service(net::TcpListener::bind("127.0.0.1:0").unwrap())
-        }
+/// The message of a panic with a string payload.
+fn panic_message(panic: &(dyn Any + Send)) -> Option<&str> {
+    panic
+        .downcast_ref::<&'static str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+}
 
-        fn port(&self) -> u16 {
-            self.0.local_addr().unwrap().port()
+impl<E: fmt::Debug> fmt::Debug for WaitError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            WaitError::Failed(ref e) => f.debug_tuple("Failed").field(e).finish(),
+            WaitError::Panicked(_) => match self.panic_message() {
+                Some(msg) => f.debug_tuple("Panicked").field(&msg).finish(),
+                None => f.write_str("Panicked(..)"),
+            },
         }
     }
+}
 
-    fn connect_assert(port: u16) -> Option<io::Error> {
-        match net::TcpStream::connect(("127.0.0.1", port)) {
-            Ok(mut c) => {
-                let mut r = String::new();
-                if let Err(e) = c.read_to_string(&mut r) {
-                    return Some(e);
-                }
-                assert_eq!(r, "hello!");
-                None
+impl<E: fmt::Display> fmt::Display for WaitError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            WaitError::Failed(ref e) => write!(f, "service loop failed: {}", e),
+            WaitError::Panicked(_) => match self.panic_message() {
+                Some(msg) => write!(f, "service loop panicked: {}", msg),
+                None => f.write_str("service loop panicked"),
             },
-            Err(e) => {
-                Some(e)
-            }
         }
     }
+}
 
-    #[test]
-    fn it_runs() {
-        let mut s = Service::new();
-        let port = s.port();
-        thread::spawn(move || {
-            s.run().unwrap();
-        });
+impl<E: fmt::Debug + fmt::Display> std::error::Error for WaitError<E> {}
 
-        assert!(connect_assert(port).is_none());
-        assert!(connect_assert(port).is_none());
-    }
+/// State shared between a [`Handle`] and the thread executing its service loop.
+#[derive(Default)]
+struct Status {
+    state: Mutex<State>,
+    cvar: Condvar,
+    counters: stats::Counters,
+    reload: AtomicBool,
+    /// The type of service the loop runs, if it supports [`Handle::restart_in_place`].
+    service: Mutex<Option<std::any::TypeId>>,
+    /// A pending replacement for the service, as a boxed `FnOnce() -> S`.
+    restart: Mutex<Option<Box<dyn Any + Send>>>,
+    errors: Mutex<errors::ErrorLog>,
+    #[cfg(feature = "serde")]
+    config: Mutex<config::ConfigLog>,
+}
 
-    #[test]
-    fn it_cancels() {
-        let s = Service::new();
-        let port = s.port();
-        let h = s.spawn();
+#[derive(Default)]
+struct State {
+    ready: bool,
+    exited: bool,
+    failed: bool,
+    finished: bool,
+    watchers: Vec<(u64, Box<dyn FnOnce(bool) + Send>)>,
+    next_watcher: u64,
+}
 
-        assert!(connect_assert(port).is_none());
-        assert!(connect_assert(port).is_none());
+impl Status {
+    /// Mark the service loop as ready to accept work.
+    fn set_ready(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.ready = true;
+        self.cvar.notify_all();
+    }
 
-        h.cancel();
+    /// Mark the service loop as no longer able to accept work, as when a readiness probe fails.
+    fn set_unready(&self) {
+        self.state.lock().unwrap().ready = false;
+    }
 
-        let mut succeeded = 0;
+    fn is_ready(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.ready && !state.exited
+    }
+
+    fn has_exited(&self) -> bool {
+        self.state.lock().unwrap().exited
+    }
+
+    /// Record that the service asked for the loop to end by returning [`LoopState::Break`].
+    fn set_finished(&self) {
+        self.state.lock().unwrap().finished = true;
+    }
+
+    fn has_finished(&self) -> bool {
+        self.state.lock().unwrap().finished
+    }
+
+    fn request_reload(&self) {
+        self.reload.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns `true` if a reload has been requested since the last call.
+    fn take_reload(&self) -> bool {
+        self.reload.swap(false, Ordering::Relaxed)
+    }
+
+    /// Take the factory for the service's replacement, if a restart has been requested.
+    fn take_restart<S: 'static>(&self) -> Option<Box<dyn FnOnce() -> S + Send>> {
+        let factory = self.restart.lock().unwrap().take()?;
+        let factory = factory.downcast().expect("restart checked the service type");
+        Some(*factory)
+    }
+
+    /// Block until the service loop is ready or has exited, or until `timeout` elapses.
+    ///
+    /// Returns `true` if the loop became ready and has not exited.
+    fn wait_ready(&self, timeout: Option<Duration>) -> bool {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let mut state = self.state.lock().unwrap();
+        while !state.ready && !state.exited {
+            state = match deadline {
+                None => self.cvar.wait(state).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return false;
+                    }
+                    self.cvar.wait_timeout(state, deadline - now).unwrap().0
+                }
+            };
+        }
+        state.ready && !state.exited
+    }
+
+    /// Block until the service loop has exited, or until `timeout` elapses.
+    ///
+    /// Returns `true` if the loop has exited.
+    fn wait_exit(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap();
+        while !state.exited {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            state = self.cvar.wait_timeout(state, deadline - now).unwrap().0;
+        }
+        true
+    }
+
+    /// Mark the service loop as exited, and notify anyone watching for that.
+    ///
+    /// `failed` should be `true` if the loop errored or panicked.
+    fn exit(&self, failed: bool) {
+        let watchers = {
+            let mut state = self.state.lock().unwrap();
+            state.exited = true;
+            state.failed = failed;
+            self.cvar.notify_all();
+            std::mem::take(&mut state.watchers)
+        };
+        for (_, w) in watchers {
+            w(failed);
+        }
+    }
+
+    /// Call `f` once the service loop has exited (or immediately, if it already has).
+    ///
+    /// `f` is told whether the loop errored or panicked.
+    fn on_exit<F: FnOnce(bool) + Send + 'static>(&self, f: F) {
+        self.watch_exit(f);
+    }
+
+    /// Like [`Status::on_exit`], but returns an id that can later be passed to
+    /// [`Status::unwatch_exit`], or `None` if `f` has already been called.
+    fn watch_exit<F: FnOnce(bool) + Send + 'static>(&self, f: F) -> Option<u64> {
+        let mut state = self.state.lock().unwrap();
+        if state.exited {
+            let failed = state.failed;
+            drop(state);
+            f(failed);
+            None
+        } else {
+            let id = state.next_watcher;
+            state.next_watcher += 1;
+            state.watchers.push((id, Box::new(f)));
+            Some(id)
+        }
+    }
+
+    /// Forget the watcher registered under `id`, if it has not been called yet.
+    fn unwatch_exit(&self, id: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.watchers.retain(|&(i, _)| i != id);
+    }
+}
+
+/// Marks a service loop as exited when dropped, even if the loop panics.
+struct ExitGuard {
+    status: Arc<Status>,
+    failed: bool,
+}
+
+impl ExitGuard {
+    fn set_failed(&mut self, failed: bool) {
+        self.failed = failed;
+    }
+}
+
+impl Drop for ExitGuard {
+    fn drop(&mut self) {
+        self.status.exit(self.failed || thread::panicking());
+    }
+}
+
+/// The mechanism through which a [`Handle`] waits for its service loop to exit.
+///
+/// This lets a service loop be driven by something other than a dedicated thread (a pooled
+/// worker, a future, etc.) while still being managed through the same [`Handle`] API.
+trait Executor<T>: Send {
+    /// Block until the service loop exits, and return its result.
+    ///
+    /// If the service loop panicked, the panic payload is returned in the `Err` variant.
+    fn join(self: Box<Self>) -> thread::Result<T>;
+
+    /// Give up the thread that is running the service loop, if the loop has a dedicated one.
+    ///
+    /// The default implementation returns `None`.
+    fn into_thread(self: Box<Self>) -> Option<thread::JoinHandle<T>> {
+        None
+    }
+}
+
+impl<T: Send> Executor<T> for thread::JoinHandle<T> {
+    fn join(self: Box<Self>) -> thread::Result<T> {
+        (*self).join()
+    }
+
+    fn into_thread(self: Box<Self>) -> Option<thread::JoinHandle<T>> {
+        Some(*self)
+    }
+}
+
+/// Stands in for the executor of a service loop whose result was already collected by
+/// [`Handle::try_wait`].
+struct Joined;
+
+impl<T> Executor<T> for Joined {
+    fn join(self: Box<Self>) -> thread::Result<T> {
+        panic!("service loop result was already taken by Handle::try_wait");
+    }
+}
+
+/// A handle that allows the cancellation of a running service loop.
+///
+/// Two cancellers compare equal (and hash the same) if they are clones of one another, and so
+/// cancel the same service loops.
+#[derive(Clone)]
+pub struct Canceller {
+    signal: Arc<Signal>,
+}
+
+impl PartialEq for Canceller {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.signal, &other.signal)
+    }
+}
+
+impl Eq for Canceller {}
+
+impl Default for Canceller {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Canceller {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Canceller")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+impl std::hash::Hash for Canceller {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        Arc::as_ptr(&self.signal).hash(state)
+    }
+}
+
+struct Signal {
+    keep_running: AtomicBool,
+    lock: Mutex<Control>,
+    #[cfg(not(feature = "parker"))]
+    cvar: Condvar,
+    /// A flag shared with other processes that also cancels the loop when set.
+    #[cfg(all(unix, feature = "shm"))]
+    shared: Option<SharedFlag>,
+}
+
+/// Cancellation state beyond the fast-path `keep_running` flag.
+#[derive(Default)]
+struct Control {
+    /// If set, the loop should drain until this deadline rather than accept new work.
+    drain: Option<Instant>,
+    /// If set, the loop should not start any new iterations until it is resumed.
+    paused: bool,
+    /// Callbacks to run when the loop is cancelled, each with the id it was registered under.
+    on_cancel: Vec<(u64, Box<dyn FnOnce() + Send>)>,
+    /// The id to give the next callback registered with [`Canceller::register_on_cancel`].
+    next_callback: u64,
+    /// Whether [`Canceller::cancel`] has been called yet.
+    cancel_requested: bool,
+    /// [Child](Canceller::child) cancellers to cancel along with this one.
+    children: Vec<Weak<Signal>>,
+    /// Hooks to consult before the loop is cancelled, each of which may ask for an extension.
+    pre_cancel: Vec<Box<dyn FnOnce() -> Option<Duration> + Send>>,
+    /// If set, the loop is cancelled unless its lease is renewed before this time.
+    lease: Option<Instant>,
+    /// Threads currently blocked in [`Canceller::block`].
+    #[cfg(feature = "parker")]
+    parked: Vec<(thread::ThreadId, crossbeam_utils::sync::Unparker)>,
+}
+
+#[cfg(feature = "parker")]
+thread_local! {
+    static PARKER: crossbeam_utils::sync::Parker = crossbeam_utils::sync::Parker::new();
+}
+
+impl<E, T> Handle<E, T> {
+    /// Run `f` on a new thread built from `tb`, and return a handle to it.
+    ///
+    /// `f` is given the loop's [`Status`] so that it can report on its progress.
+    fn spawn<F>(tb: thread::Builder, canceller: Canceller, f: F) -> Self
+    where
+        F: FnOnce(&Status) -> Result<T, E> + Send + 'static,
+        E: Send + 'static,
+        T: Send + 'static,
+    {
+        let status = Arc::new(Status::default());
+        let guard = ExitGuard {
+            status: status.clone(),
+            failed: false,
+        };
+        let jh = tb
+            .spawn(move || {
+                let mut guard = guard;
+                let r = f(&guard.status);
+                guard.set_failed(r.is_err());
+                r
+            })
+            .expect("failed to spawn service thread");
+        Handle {
+            canceller,
+            status,
+            executor: Box::new(jh),
+        }
+    }
+
+    /// Call `f` once the service loop has exited (or immediately, if it already has).
+    ///
+    /// `f` is told whether the loop errored or panicked.
+    fn on_exit<F: FnOnce(bool) + Send + 'static>(&self, f: F) {
+        self.status.on_exit(f)
+    }
+
+    /// Get another handle for cancelling the service loop.
+    ///
+    /// This can be handy if you want one thread to wait for the service loop to exit, while
+    /// another watches for exit signals.
+    pub fn canceller(&self) -> Canceller {
+        self.canceller.clone()
+    }
+
+    /// Returns `true` if the service loop has started accepting work and has not yet exited.
+    ///
+    /// A service loop becomes ready just before its first call to [`Cancellable::for_each`], or,
+    /// if it has a [readiness probe](ServiceBuilder::readiness_probe), once that probe passes.
+    pub fn is_ready(&self) -> bool {
+        self.status.is_ready()
+    }
+
+    /// Block until the service loop is [ready](Handle::is_ready), or until `timeout` elapses.
+    ///
+    /// Returns `false` if the timeout elapsed, or if the loop exited without becoming ready.
+    pub fn wait_ready(&self, timeout: Duration) -> bool {
+        self.status.wait_ready(Some(timeout))
+    }
+
+    /// Ask the service loop to [reload](Cancellable::reload) before its next iteration.
+    ///
+    /// Like [`Canceller::cancel`], this does not block or interrupt a running iteration.
+    pub fn reload(&self) {
+        self.status.request_reload();
+    }
+
+    /// Replace the service with the one returned by `factory`, without ending the service loop.
+    ///
+    /// Before its next iteration, the loop calls [`Cancellable::on_exit`] on the current service
+    /// with [`ExitReason::Restarted`], and then calls `factory` and carries on with the service
+    /// it returns, starting with its [`Cancellable::on_start`]. The loop stays on the same
+    /// thread, keeps its canceller, and keeps accumulating the same [statistics](Handle::stats),
+    /// which makes this a cheap way to "reconnect and continue". Hooks that were set up when the
+    /// loop was spawned, such as [`Cancellable::release_on_cancel`], stay those of the original
+    /// service.
+    ///
+    /// Like [`Handle::reload`], this does not block or interrupt a running iteration. If a
+    /// restart is already pending, `factory` replaces it. If the new service's `on_start` fails,
+    /// the loop exits with that error.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the loop was not spawned from a [`ServiceBuilder`] for a service of type `S`.
+    pub fn restart_in_place<S, F>(&self, factory: F)
+    where
+        S: Cancellable + 'static,
+        F: FnOnce() -> S + Send + 'static,
+    {
+        assert_eq!(
+            *self.status.service.lock().unwrap(),
+            Some(std::any::TypeId::of::<S>()),
+            "can only restart a service with one of the same type"
+        );
+        let factory: Box<dyn FnOnce() -> S + Send> = Box::new(factory);
+        *self.status.restart.lock().unwrap() = Some(Box::new(factory));
+    }
+
+    /// Whether the service loop's most recent iteration found work to do.
+    ///
+    /// See [`Context::set_activity`] and [`LoopState::Idle`].
+    pub fn activity(&self) -> Activity {
+        self.status.counters.activity()
+    }
+
+    /// Get a snapshot of the service loop's execution statistics.
+    pub fn stats(&self) -> Stats {
+        self.status.counters.snapshot()
+    }
+
+    /// The most recent errors returned by the service's iterations, oldest first.
+    ///
+    /// This includes errors that a [retry policy](ServiceBuilder::retry) absorbed, and remains
+    /// available after the loop has exited. Errors are only kept for services spawned with
+    /// [`ServiceBuilder::keep_errors`].
+    pub fn recent_errors(&self) -> Vec<RecentError> {
+        self.status.errors.lock().unwrap().recent()
+    }
+
+    /// Block the current thread waiting for the service loop to exit, and return its result.
+    ///
+    /// If the service loop returns an error, this method will return it in the `Err` value.
+    /// If the service loop panics, this method will also panic with the same error. 
+    pub fn wait(self) -> Result<T, E> {
+        propagate(self.executor.join())
+    }
+
+    /// Block the current thread waiting for the service loop to exit, and return its result.
+    ///
+    /// Unlike [`Handle::wait`], this method does not panic if the service loop panicked, and
+    /// instead returns the panic payload in [`WaitError::Panicked`]. This lets a supervisor log
+    /// the panic and restart the service.
+    pub fn wait_catch(self) -> Result<T, WaitError<E>> {
+        catch(self.executor.join())
+    }
+
+    /// Like [`Handle::wait`], but give up after `timeout`.
+    ///
+    /// If the service loop has not exited by then, the handle is given back in the `Err` value,
+    /// so that the caller can decide how to escalate (say, by logging and exiting the process)
+    /// when a service refuses to stop, or simply wait some more.
+    pub fn wait_timeout(self, timeout: Duration) -> Result<Result<T, E>, Self> {
+        if self.status.wait_exit(timeout) {
+            Ok(self.wait())
+        } else {
+            Err(self)
+        }
+    }
+
+    /// Block the current thread until the first of `handles` exits, and return its index along
+    /// with its result.
+    ///
+    /// The handle that exited is removed from `handles`, so the remaining ones can be waited on
+    /// again, or cancelled once any one of them dying means the rest should stop too:
+    ///
+    /// ```
+    /// # use minion::*;
+    /// # struct Backend(bool);
+    /// # impl Cancellable for Backend {
+    /// #     type Error = &'static str;
+    /// #     fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+    /// #         if self.0 { Err("lost connection") } else { Ok(LoopState::Continue) }
+    /// #     }
+    /// # }
+    /// let mut backends = vec![Backend(false).spawn(), Backend(true).spawn()];
+    /// let (i, result) = Handle::wait_any(&mut backends);
+    /// assert_eq!((i, result), (1, Err("lost connection")));
+    /// for h in backends {
+    ///     h.cancel();
+    ///     h.wait().unwrap();
+    /// }
+    /// ```
+    ///
+    /// If the service loop that exited panicked, this method will also panic with the same error.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `handles` is empty, or if the result of one of them has already been
+    /// [collected](Handle::try_wait).
+    pub fn wait_any(handles: &mut Vec<Self>) -> (usize, Result<T, E>) {
+        assert!(!handles.is_empty(), "waiting for any of no service loops");
+        let (tx, rx) = std::sync::mpsc::channel();
+        let watchers: Vec<_> = handles
+            .iter()
+            .enumerate()
+            .map(|(i, h)| {
+                let tx = tx.clone();
+                h.status.watch_exit(move |_| {
+                    let _ = tx.send(i);
+                })
+            })
+            .collect();
+        let i = rx.recv().expect("every service loop notifies on exit");
+        // callers may well wait again on the rest, so don't leave our watchers behind
+        for (h, id) in handles.iter().zip(watchers) {
+            if let Some(id) = id {
+                h.status.unwatch_exit(id);
+            }
+        }
+        (i, handles.remove(i).wait())
+    }
+
+    /// Collect the service loop's result if it has exited, without blocking.
+    ///
+    /// Returns `None` while the loop is still running, so a single thread can keep polling many
+    /// handles. Once it returns `Some`, the result has been handed out, and the handle should
+    /// only be used to inspect the loop's status; waiting on it again will panic.
+    ///
+    /// If the service loop panicked, this method will also panic with the same error.
+    pub fn try_wait(&mut self) -> Option<Result<T, E>> {
+        if !self.status.has_exited() {
+            return None;
+        }
+        let executor = std::mem::replace(&mut self.executor, Box::new(Joined));
+        Some(propagate(executor.join()))
+    }
+
+    /// Take apart this handle, returning the thread that runs the service loop and the loop's
+    /// [`Canceller`].
+    ///
+    /// This is an escape hatch for when you need thread APIs that `minion` does not wrap, such as
+    /// platform-specific extensions on the [`JoinHandle`](thread::JoinHandle). The loop keeps
+    /// running, and can still be cancelled through the returned [`Canceller`]. There is no thread
+    /// to return if the loop is not driven by a dedicated thread of its own, or if its result was
+    /// already collected with [`Handle::try_wait`].
+    pub fn into_parts(self) -> (Option<thread::JoinHandle<Result<T, E>>>, Canceller) {
+        (self.executor.into_thread(), self.canceller)
+    }
+
+    /// Block the current thread waiting for the service loop to exit, and return how it exited.
+    ///
+    /// Unlike [`Handle::wait`], this method does not panic if the service loop panicked.
+    pub fn join(self) -> ExitStatus<E, T> {
+        ExitStatus::from(self.executor.join())
+    }
+
+    /// Cancel the service loop, wait for it to exit, and report why it exited.
+    ///
+    /// A loop that returned [`LoopState::Break`] before it observed the cancellation is reported
+    /// as [`Shutdown::Finished`]. Errors and panics are returned just like from [`Handle::wait`].
+    pub fn cancel_and_wait(self) -> Result<Shutdown<T>, E> {
+        self.canceller.cancel();
+        let status = self.status.clone();
+        let t = self.wait()?;
+        if status.has_finished() {
+            Ok(Shutdown::Finished(t))
+        } else {
+            Ok(Shutdown::Cancelled(t))
+        }
+    }
+}
+
+fn catch<T, E>(r: thread::Result<Result<T, E>>) -> Result<T, WaitError<E>> {
+    match r {
+        Ok(r) => r.map_err(WaitError::Failed),
+        Err(e) => Err(WaitError::Panicked(e)),
+    }
+}
+
+/// Run a service loop between the service's [`Cancellable::on_start`] and
+/// [`Cancellable::on_exit`] hooks.
+///
+/// `body` runs the loop, and returns whether it stopped because the service finished.
+fn with_hooks<S, F>(service: &mut S, body: F) -> Result<(), S::Error>
+where
+    S: Cancellable + ?Sized,
+    F: FnOnce(&mut S) -> Result<bool, S::Error>,
+{
+    service.on_start()?;
+    let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| body(&mut *service)));
+    service.on_exit(match r {
+        Ok(Ok(true)) => ExitReason::Finished,
+        Ok(Ok(false)) => ExitReason::Cancelled,
+        Ok(Err(_)) => ExitReason::Failed,
+        Err(_) => ExitReason::Panicked,
+    });
+    propagate(r).map(|_| ())
+}
+
+fn propagate<T>(r: thread::Result<T>) -> T {
+    match r {
+        Ok(r) => r,
+        Err(e) => {
+            // propagate the panic, keeping the original payload
+            std::panic::resume_unwind(e)
+        }
+    }
+}
+
+use std::ops::Deref;
+impl<E, T> Deref for Handle<E, T> {
+    type Target = Canceller;
+    fn deref(&self) -> &Self::Target {
+        &self.canceller
+    }
+}
+
+impl Canceller {
+    /// Cancel the currently running service loop. This method does not block; it sends a signal 
+    /// that the service loop should cease execution and returns immediately.
+    ///
+    /// Note that this will *not* interrupt a currently executing [`Cancellable::for_each`].
+    /// Instead, the next time [`Cancellable::for_each`] *would* be called, the service loop will
+    /// return.
+    ///
+    /// Returns `true` if this was the first call to `cancel` for this loop, and `false` if the
+    /// loop had already been cancelled. When several shutdown paths race to cancel the same loop,
+    /// exactly one of them sees `true`, which makes it a convenient place to hang one-time side
+    /// effects such as logging the shutdown. A loop that is given an
+    /// [extension](Cancellable::pre_cancel) counts as cancelled from the first call on.
+    pub fn cancel(&self) -> bool {
+        let (first, hooks) = {
+            let mut control = self.signal.lock.lock().unwrap();
+            let first = !std::mem::replace(&mut control.cancel_requested, true);
+            (first, std::mem::take(&mut control.pre_cancel))
+        };
+        let extension = hooks.into_iter().filter_map(|hook| hook()).max();
+        if let Some(extension) = extension.filter(|e| *e > Duration::from_secs(0)) {
+            // the loop gets to drain for a bit, and is then cancelled for real
+            self.drain(extension);
+            return first;
+        }
+
+        #[cfg(all(unix, feature = "shm"))]
+        if let Some(ref shared) = self.signal.shared {
+            shared.set();
+        }
+        let (callbacks, children) = {
+            let mut control = self.signal.lock.lock().unwrap();
+            if !self.signal.keep_running.swap(false, Ordering::Relaxed) {
+                // already cancelled
+                return first;
+            }
+            self.wake(&mut control);
+            (std::mem::take(&mut control.on_cancel), std::mem::take(&mut control.children))
+        };
+        for (_, f) in callbacks {
+            f();
+        }
+        for signal in children.iter().filter_map(Weak::upgrade) {
+            Canceller { signal }.cancel();
+        }
+        first
+    }
+
+    /// [Cancel](Canceller::cancel) the service loop once `delay` has passed. This method does not
+    /// block.
+    ///
+    /// All deferred cancellations share a single timer thread, which is started the first time
+    /// one is needed, so this is cheap even when used for every connection or request. A pending
+    /// cancellation does not keep the canceller alive, and does nothing if the loop has already
+    /// been cancelled by the time it is due.
+    ///
+    /// ```
+    /// # use minion::*;
+    /// # use std::time::Duration;
+    /// # struct Service;
+    /// # impl Cancellable for Service {
+    /// #     type Error = ();
+    /// #     fn for_each(&mut self) -> Result<LoopState, ()> { Ok(LoopState::Continue) }
+    /// # }
+    /// let h = Service.spawn();
+    /// h.cancel_after(Duration::from_millis(10));
+    /// h.wait().unwrap();
+    /// ```
+    pub fn cancel_after(&self, delay: Duration) {
+        let signal = Arc::downgrade(&self.signal);
+        timer::schedule(Instant::now() + delay, move || {
+            if let Some(signal) = signal.upgrade() {
+                Canceller { signal }.cancel();
+            }
+        });
+    }
+
+    /// Create a canceller that is cancelled along with this one, but that can also be cancelled
+    /// on its own without affecting this one.
+    ///
+    /// This suits sub-loops that must not outlive the loop that started them, but that should
+    /// also be individually stoppable, such as a loop per connection under an accept loop.
+    /// Children can have children of their own. If this canceller has already been cancelled,
+    /// the child starts out cancelled. Only [cancellation](Canceller::cancel) is passed on to
+    /// children; [draining](Canceller::drain) and [pausing](Canceller::pause) are not.
+    ///
+    /// ```
+    /// # use minion::*;
+    /// let listener = Canceller::new();
+    /// let a = listener.child();
+    /// let b = listener.child();
+    ///
+    /// a.cancel();
+    /// assert!(!listener.is_cancelled() && !b.is_cancelled());
+    /// listener.cancel();
+    /// assert!(b.is_cancelled());
+    /// ```
+    pub fn child(&self) -> Canceller {
+        let child = Canceller::new();
+        self.adopt(&child);
+        child
+    }
+
+    /// Link this canceller with `other`, so that cancelling either one also cancels the other.
+    ///
+    /// This suits cooperating loops that are useless on their own, such as the reader and the
+    /// writer half of a connection: whichever of them stops first (and is cancelled, say by an
+    /// [`on_exit`](Cancellable::on_exit) hook) takes the other one down with it. If either
+    /// canceller has already been cancelled, the other one is cancelled right away. As with
+    /// [children](Canceller::child), only cancellation is passed on, and the link does not keep
+    /// either canceller alive.
+    ///
+    /// ```
+    /// # use minion::*;
+    /// let reader = Canceller::new();
+    /// let writer = Canceller::new();
+    /// reader.link(&writer);
+    ///
+    /// writer.cancel();
+    /// assert!(reader.is_cancelled());
+    /// ```
+    pub fn link(&self, other: &Canceller) {
+        if self != other {
+            self.adopt(other);
+            other.adopt(self);
+        }
+    }
+
+    /// Make `child` be cancelled whenever this canceller is.
+    fn adopt(&self, child: &Canceller) {
+        let mut control = self.signal.lock.lock().unwrap();
+        if self.keep_running() {
+            // children that have since gone away need not be remembered
+            control.children.retain(|c| c.strong_count() > 0);
+            control.children.push(Arc::downgrade(&child.signal));
+        } else {
+            drop(control);
+            child.cancel();
+        }
+    }
+
+    /// Returns `true` if the service loop has been cancelled.
+    ///
+    /// Long-running [`Cancellable::for_each`] bodies can check this between units of work to
+    /// bail out of an iteration early, rather than finishing it before the loop notices. See also
+    /// [`CancelToken::check`].
+    pub fn is_cancelled(&self) -> bool {
+        !self.keep_running()
+    }
+
+    /// Call `f` as soon as the service loop is cancelled.
+    ///
+    /// `f` runs on whichever thread calls [`Canceller::cancel`], while the service loop may still
+    /// be in the middle of an iteration. This makes it suitable for promptly releasing external
+    /// resources such as leases or locks. If the loop has already been cancelled, `f` is called
+    /// immediately.
+    pub fn on_cancel<F: FnOnce() + Send + 'static>(&self, f: F) {
+        self.register_on_cancel(f);
+    }
+
+    /// Like [`Canceller::on_cancel`], but returns an id that can later be passed to
+    /// [`Canceller::deregister_on_cancel`], or `None` if `f` has already been called.
+    fn register_on_cancel<F: FnOnce() + Send + 'static>(&self, f: F) -> Option<u64> {
+        let mut control = self.signal.lock.lock().unwrap();
+        if self.keep_running() {
+            let id = control.next_callback;
+            control.next_callback += 1;
+            control.on_cancel.push((id, Box::new(f)));
+            Some(id)
+        } else {
+            drop(control);
+            f();
+            None
+        }
+    }
+
+    /// Forget the callback registered under `id`, if it has not been called yet.
+    fn deregister_on_cancel(&self, id: u64) {
+        let mut control = self.signal.lock.lock().unwrap();
+        control.on_cancel.retain(|&(i, _)| i != id);
+    }
+
+    /// Ask the currently running service loop to drain, and then cancel it once `grace` has
+    /// passed. This method does not block.
+    ///
+    /// Once draining, the service loop stops calling [`Cancellable::for_each`], and instead
+    /// repeatedly calls [`Cancellable::drain`] with the time remaining until the deadline. The loop
+    /// returns as soon as `drain` returns [`LoopState::Break`]; if it is still draining when the
+    /// deadline passes, the loop is cancelled as though [`Canceller::cancel`] had been called.
+    ///
+    /// Calling `drain` on a loop that is already draining only ever moves the deadline earlier.
+    pub fn drain(&self, grace: Duration) {
+        let deadline = Instant::now() + grace;
+        let mut control = self.signal.lock.lock().unwrap();
+        match control.drain {
+            Some(d) if d <= deadline => {}
+            _ => control.drain = Some(deadline),
+        }
+        self.wake(&mut control);
+    }
+
+    /// Keep the service loop alive for another `ttl`, after which it cancels itself unless the
+    /// lease is renewed again.
+    ///
+    /// A loop only has a lease once `renew` has been called for the first time. From then on,
+    /// whoever controls the loop has to keep renewing it, so that a worker whose coordinator has
+    /// crashed (and so stopped renewing) shuts itself down rather than running on as an orphan.
+    /// An expired lease is noticed before the next iteration starts, or right away if the loop is
+    /// waiting on a [throttle](ServiceBuilder::throttle), and is handled like a call to
+    /// [`Canceller::cancel`].
+    pub fn renew(&self, ttl: Duration) {
+        let mut control = self.signal.lock.lock().unwrap();
+        control.lease = Some(Instant::now() + ttl);
+        // wake anyone sleeping, so they pick up the new expiry
+        self.wake(&mut control);
+    }
+
+    /// Hold off on starting new iterations of the service loop until it is
+    /// [resumed](Canceller::resume).
+    ///
+    /// Like [`Canceller::cancel`], this does not interrupt a currently executing
+    /// [`Cancellable::for_each`]; the loop parks once that iteration is done. A paused loop can
+    /// still be cancelled or drained, and still exits once its
+    /// [deadline](ServiceBuilder::deadline) passes. This suits quiescing a service for a while,
+    /// say during maintenance, without tearing it down:
+    ///
+    /// ```
+    /// # use minion::*;
+    /// # struct Ingest;
+    /// # impl Cancellable for Ingest {
+    /// #     type Error = ();
+    /// #     fn for_each(&mut self) -> Result<LoopState, ()> { Ok(LoopState::Continue) }
+    /// # }
+    /// let h = Ingest.spawn();
+    /// h.pause();
+    /// // ... maintenance ...
+    /// h.resume();
+    /// h.cancel();
+    /// h.wait().unwrap();
+    /// ```
+    pub fn pause(&self) {
+        self.signal.lock.lock().unwrap().paused = true;
+    }
+
+    /// Let a [paused](Canceller::pause) service loop carry on.
+    pub fn resume(&self) {
+        let mut control = self.signal.lock.lock().unwrap();
+        control.paused = false;
+        self.wake(&mut control);
+    }
+
+    /// Returns `true` if the service loop has been [paused](Canceller::pause).
+    pub fn is_paused(&self) -> bool {
+        self.signal.lock.lock().unwrap().paused
+    }
+
+    /// Cancel the loop if its lease has expired.
+    ///
+    /// Returns `true` if the lease had expired.
+    fn check_lease(&self) -> bool {
+        let expired = match self.signal.lock.lock().unwrap().lease {
+            Some(lease) => Instant::now() >= lease,
+            None => false,
+        };
+        if expired {
+            self.cancel();
+        }
+        expired
+    }
+
+    /// Consult `hook` the first time the loop is about to be cancelled.
+    ///
+    /// If the hook asks for an extension, the loop is [drained](Canceller::drain) for that long
+    /// instead of being cancelled immediately.
+    fn pre_cancel<F>(&self, hook: F)
+    where
+        F: FnOnce() -> Option<Duration> + Send + 'static,
+    {
+        self.signal.lock.lock().unwrap().pre_cancel.push(Box::new(hook));
+    }
+
+    /// The deadline for draining the service loop, if it has been asked to drain.
+    fn drain_deadline(&self) -> Option<Instant> {
+        self.signal.lock.lock().unwrap().drain
+    }
+
+    /// Create a canceller that is not yet attached to any service loop.
+    ///
+    /// Pass it to [`ServiceBuilder::canceller`] to have service loops stop when it is cancelled.
+    /// This is useful when something needs to observe the canceller before the service that it
+    /// cancels has been spawned.
+    pub fn new() -> Self {
+        Canceller {
+            signal: Arc::new(Signal {
+                keep_running: AtomicBool::new(true),
+                lock: Mutex::new(Control::default()),
+                #[cfg(not(feature = "parker"))]
+                cvar: Condvar::new(),
+                #[cfg(all(unix, feature = "shm"))]
+                shared: None,
+            }),
+        }
+    }
+
+    fn keep_running(&self) -> bool {
+        #[cfg(all(unix, feature = "shm"))]
+        if let Some(ref shared) = self.signal.shared {
+            if shared.is_set() {
+                return false;
+            }
+        }
+        self.signal.keep_running.load(Ordering::Relaxed)
+    }
+
+    /// Wake up any thread currently blocked in [`Canceller::sleep`] or [`Canceller::wait_for`],
+    /// without cancelling the service loop.
+    fn notify(&self) {
+        let mut control = self.signal.lock.lock().unwrap();
+        self.wake(&mut control);
+    }
+
+    /// Wake up every thread blocked in [`Canceller::block`].
+    fn wake(&self, control: &mut Control) {
+        #[cfg(feature = "parker")]
+        for (_, unparker) in control.parked.drain(..) {
+            unparker.unpark();
+        }
+        #[cfg(not(feature = "parker"))]
+        {
+            let _ = control;
+            self.signal.cvar.notify_all();
+        }
+    }
+
+    /// Release `guard` and block until [`Canceller::wake`] is called or `deadline` passes, then
+    /// re-acquire the lock.
+    ///
+    /// Like waiting on a condition variable, this may also return spuriously. With the `parker`
+    /// feature, blocked threads are parked and unparked directly instead, which reaches idle
+    /// service loops with lower latency.
+    fn block<'a>(
+        &'a self,
+        guard: MutexGuard<'a, Control>,
+        deadline: Option<Instant>,
+    ) -> MutexGuard<'a, Control> {
+        // other processes cannot wake us, so check back on the shared flag periodically
+        #[cfg(all(unix, feature = "shm"))]
+        let deadline = match self.signal.shared {
+            Some(_) => {
+                let poll = Instant::now() + shm::POLL_INTERVAL;
+                Some(deadline.map_or(poll, |d| d.min(poll)))
+            }
+            None => deadline,
+        };
+        #[cfg(feature = "parker")]
+        {
+            let me = thread::current().id();
+            PARKER.with(|parker| {
+                let mut guard = guard;
+                guard.parked.push((me, parker.unparker().clone()));
+                drop(guard);
+                match deadline {
+                    None => parker.park(),
+                    Some(deadline) => parker.park_deadline(deadline),
+                }
+            });
+            let mut guard = self.signal.lock.lock().unwrap();
+            guard.parked.retain(|&(id, _)| id != me);
+            guard
+        }
+        #[cfg(not(feature = "parker"))]
+        match deadline {
+            None => self.signal.cvar.wait(guard).unwrap(),
+            Some(deadline) => {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                self.signal.cvar.wait_timeout(guard, timeout).unwrap().0
+            }
+        }
+    }
+
+    /// Block until `ready` returns `true`, re-checking it every time [`Canceller::notify`] is
+    /// called.
+    ///
+    /// Returns `false` if the service loop was cancelled before `ready` returned `true`.
+    fn wait_for<F: FnMut() -> bool>(&self, mut ready: F) -> bool {
+        let mut guard = self.signal.lock.lock().unwrap();
+        while self.keep_running() {
+            if ready() {
+                return true;
+            }
+            guard = self.block(guard, None);
+        }
+        false
+    }
+
+    /// Block until the service loop is cancelled, or until `timeout` (if any) elapses.
+    ///
+    /// Returns `true` if the loop was cancelled.
+    fn wait_cancelled(&self, timeout: Option<Duration>) -> bool {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let mut guard = self.signal.lock.lock().unwrap();
+        while self.keep_running() {
+            if deadline.map(|d| Instant::now() >= d).unwrap_or(false) {
+                return false;
+            }
+            guard = self.block(guard, deadline);
+        }
+        true
+    }
+
+    /// Block for as long as the service loop is paused, but no later than `until`.
+    ///
+    /// Returns `false` if the loop was cancelled or asked to drain while paused, or if `until`
+    /// passed before it was resumed.
+    fn wait_resumed(&self, until: Option<Instant>) -> bool {
+        let mut guard = self.signal.lock.lock().unwrap();
+        while self.keep_running() && guard.drain.is_none() {
+            if !guard.paused {
+                return true;
+            }
+            let now = Instant::now();
+            if until.is_some_and(|until| now >= until) {
+                return false;
+            }
+            match guard.lease {
+                Some(lease) if now >= lease => {
+                    drop(guard);
+                    self.cancel();
+                    return false;
+                }
+                Some(lease) => {
+                    let wake = until.map_or(lease, |until| until.min(lease));
+                    guard = self.block(guard, Some(wake));
+                }
+                None => guard = self.block(guard, until),
+            }
+        }
+        false
+    }
+
+    /// Sleep for up to `dur`, waking up early if the service loop is cancelled or asked to drain.
+    ///
+    /// Returns `true` if the full duration elapsed without the loop being cancelled or drained.
+    fn sleep(&self, dur: Duration) -> bool {
+        let deadline = Instant::now() + dur;
+        let mut guard = self.signal.lock.lock().unwrap();
+        while self.keep_running() && guard.drain.is_none() {
+            let now = Instant::now();
+            let wake = match guard.lease {
+                Some(lease) if now >= lease => {
+                    drop(guard);
+                    self.cancel();
+                    return false;
+                }
+                Some(lease) => lease.min(deadline),
+                None => deadline,
+            };
+            if now >= deadline {
+                return true;
+            }
+            guard = self.block(guard, Some(wake));
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{self, prelude::*}, net, thread,
+    };
+
+    struct Service(net::TcpListener);
+
+    impl Cancellable for Service {
+        type Error = io::Error;
+        fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+            let mut stream = match self.0.accept() {
+                Ok((stream, _)) => stream,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {
+                    return Ok(LoopState::Continue)
+                }
+                Err(e) => return Err(e),
+            };
+            write!(stream, "hello!")?;
+            Ok(LoopState::Continue)
+        }
+    }
+
+    impl Service {
+        fn new() -> Self {
+            Service(net::TcpListener::bind("127.0.0.1:0").unwrap())
+        }
+
+        fn port(&self) -> u16 {
+            self.0.local_addr().unwrap().port()
+        }
+    }
+
+    fn connect_assert(port: u16) -> Option<io::Error> {
+        match net::TcpStream::connect(("127.0.0.1", port)) {
+            Ok(mut c) => {
+                let mut r = String::new();
+                if let Err(e) = c.read_to_string(&mut r) {
+                    return Some(e);
+                }
+                assert_eq!(r, "hello!");
+                None
+            },
+            Err(e) => {
+                Some(e)
+            }
+        }
+    }
+
+    #[test]
+    fn it_runs() {
+        let mut s = Service::new();
+        let port = s.port();
+        thread::spawn(move || {
+            s.run().unwrap();
+        });
+
+        assert!(connect_assert(port).is_none());
+        assert!(connect_assert(port).is_none());
+    }
+
+    #[test]
+    fn it_cancels() {
+        let s = Service::new();
+        let port = s.port();
+        let h = s.spawn();
+
+        assert!(connect_assert(port).is_none());
+        assert!(connect_assert(port).is_none());
+
+        h.cancel();
+
+        let mut succeeded = 0;
         // cancel will ensure that for_each is not call *again*
         // it will *not* terminate the currently running for_each
         // note that it *may* terminate early if accept() gets interrupted
@@ -305,4 +1916,319 @@ mod tests {
         // instead of calling for_each again, the loop should now have exited
         h.wait().unwrap();
     }
+
+    #[test]
+    fn wait_timeout_gives_the_handle_back() {
+        struct Stubborn;
+        impl Cancellable for Stubborn {
+            type Error = ();
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                thread::sleep(Duration::from_millis(200));
+                Ok(LoopState::Continue)
+            }
+        }
+
+        let h = Stubborn.spawn();
+        assert!(h.wait_ready(Duration::from_secs(1)));
+        // make sure the loop is stuck in its first iteration
+        thread::sleep(Duration::from_millis(20));
+        h.cancel();
+        let h = match h.wait_timeout(Duration::from_millis(10)) {
+            Ok(_) => panic!("loop exited in the middle of an iteration"),
+            Err(h) => h,
+        };
+        assert!(h.wait_timeout(Duration::from_secs(5)).ok().unwrap().is_ok());
+    }
+
+    #[test]
+    fn cancel_and_wait_tells_why() {
+        struct Forever;
+        impl Cancellable for Forever {
+            type Error = ();
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                thread::sleep(Duration::from_millis(1));
+                Ok(LoopState::Continue)
+            }
+        }
+        struct Once;
+        impl Cancellable for Once {
+            type Error = ();
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                Ok(LoopState::Break)
+            }
+        }
+
+        let h = Forever.spawn();
+        assert!(h.wait_ready(Duration::from_secs(1)));
+        assert_eq!(h.cancel_and_wait(), Ok(Shutdown::Cancelled(())));
+
+        let h = Once.spawn();
+        while !h.status.has_exited() {
+            thread::yield_now();
+        }
+        assert_eq!(h.cancel_and_wait(), Ok(Shutdown::Finished(())));
+    }
+
+    #[test]
+    fn wait_catch_keeps_the_panic() {
+        struct Panicky;
+        impl Cancellable for Panicky {
+            type Error = ();
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                panic!("boom");
+            }
+        }
+
+        match Panicky.spawn().wait_catch() {
+            Err(e @ WaitError::Panicked(_)) => assert_eq!(e.panic_message(), Some("boom")),
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        // the payload survives being propagated by wait too
+        let h = Panicky.spawn();
+        let e = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| h.wait())).unwrap_err();
+        assert_eq!(e.downcast_ref::<&str>(), Some(&"boom"));
+    }
+
+    #[test]
+    fn try_wait_polls() {
+        struct Forever;
+        impl Cancellable for Forever {
+            type Error = ();
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                thread::sleep(Duration::from_millis(1));
+                Ok(LoopState::Continue)
+            }
+        }
+
+        let mut h = Forever.spawn();
+        assert!(h.wait_ready(Duration::from_secs(1)));
+        assert!(h.try_wait().is_none());
+        h.cancel();
+        let r = loop {
+            if let Some(r) = h.try_wait() {
+                break r;
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+        assert!(r.is_ok());
+        assert!(h.is_cancelled());
+    }
+
+    #[test]
+    fn is_cancelled_is_inherent() {
+        let c = Canceller::new();
+        assert!(!Canceller::is_cancelled(&c));
+        c.cancel();
+        assert!(Canceller::is_cancelled(&c));
+    }
+
+    #[test]
+    fn only_the_first_cancel_counts() {
+        let c = Canceller::new();
+        let firsts: Vec<_> = (0..4)
+            .map(|_| {
+                let c = c.clone();
+                thread::spawn(move || c.cancel())
+            })
+            .collect();
+        let firsts = firsts.into_iter().map(|t| t.join().unwrap()).filter(|&first| first).count();
+        assert_eq!(firsts, 1);
+        assert!(!c.cancel());
+
+        // an extension defers the cancellation, but the first call still counts
+        let c = Canceller::new();
+        c.pre_cancel(|| Some(Duration::from_secs(1)));
+        assert!(c.cancel());
+        assert!(!c.is_cancelled());
+        assert!(!c.cancel());
+        assert!(c.is_cancelled());
+    }
+
+    #[test]
+    fn children_die_with_their_parent() {
+        let parent = Canceller::new();
+        let child = parent.child();
+        let grandchild = child.child();
+        for _ in 0..100 {
+            // short-lived children are forgotten once dropped
+            drop(parent.child());
+        }
+        assert!(parent.signal.lock.lock().unwrap().children.len() <= 2);
+
+        struct Idle;
+        impl Cancellable for Idle {
+            type Error = ();
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                Ok(LoopState::ContinueAfter(Duration::from_secs(60)))
+            }
+        }
+        let h = Idle.builder().canceller(grandchild.clone()).spawn();
+        child.child().cancel();
+        assert!(!grandchild.is_cancelled());
+        parent.cancel();
+        h.wait().unwrap();
+        assert!(child.is_cancelled() && grandchild.is_cancelled());
+        assert!(parent.child().is_cancelled());
+    }
+
+    #[test]
+    fn run_until_idle_stops_when_out_of_work() {
+        struct Batches(Vec<usize>);
+        impl Cancellable for Batches {
+            type Error = ();
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                self.for_each_ctx(&mut Context::new())
+            }
+            fn for_each_ctx(&mut self, ctx: &mut Context) -> Result<LoopState, Self::Error> {
+                match self.0.pop() {
+                    Some(0) => return Err(()),
+                    Some(_) => {}
+                    None => ctx.set_activity(Activity::Idle),
+                }
+                Ok(LoopState::Continue)
+            }
+            fn on_error(&mut self, _: ()) -> ErrorDisposition<()> {
+                ErrorDisposition::RetryAfter(Duration::from_secs(60))
+            }
+        }
+
+        let mut batches = Batches(vec![1, 2, 0, 3, 4]);
+        assert_eq!(batches.run_until_idle(), Ok(false));
+        assert_eq!(batches.0, [1, 2]);
+        assert_eq!(batches.run_until_idle(), Ok(false));
+        assert!(batches.0.is_empty());
+    }
+
+    #[test]
+    fn deferred_cancellations_share_a_timer() {
+        let ms = Duration::from_millis;
+        let late = Canceller::new();
+        late.cancel_after(ms(10_000));
+        let early: Vec<_> = (0..10).map(|_| Canceller::new()).collect();
+        for (i, c) in early.iter().enumerate() {
+            c.cancel_after(ms(10 * (10 - i as u64)));
+        }
+        let start = Instant::now();
+        for c in &early {
+            while !c.is_cancelled() {
+                assert!(start.elapsed() < ms(5_000));
+                thread::sleep(ms(1));
+            }
+        }
+        assert!(!late.is_cancelled());
+        assert!(start.elapsed() >= ms(90));
+    }
+
+    #[test]
+    fn linked_cancellers_go_down_together() {
+        let (a, b, c) = (Canceller::new(), Canceller::new(), Canceller::new());
+        a.link(&b);
+        b.link(&c);
+        a.link(&a);
+        c.cancel();
+        assert!(a.is_cancelled() && b.is_cancelled());
+
+        let (a, b) = (Canceller::new(), Canceller::new());
+        a.cancel();
+        a.link(&b);
+        assert!(b.is_cancelled());
+    }
+
+    #[test]
+    // Canceller hashes by identity, so its interior mutability does not affect the key
+    #[allow(clippy::mutable_key_type)]
+    fn cancellers_compare_by_identity() {
+        use std::collections::HashSet;
+
+        let a = Canceller::new();
+        let b = Canceller::new();
+        assert!(a == a.clone());
+        assert!(a != b);
+
+        let set: HashSet<_> = vec![a.clone(), b, a].into_iter().collect();
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn hooks_bracket_the_loop() {
+        struct Hooked(Arc<Mutex<Vec<String>>>, bool);
+        impl Cancellable for Hooked {
+            type Error = ();
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                if self.1 {
+                    panic!("boom");
+                }
+                thread::sleep(Duration::from_millis(1));
+                Ok(LoopState::Continue)
+            }
+            fn on_start(&mut self) -> Result<(), Self::Error> {
+                self.0.lock().unwrap().push("start".to_string());
+                Ok(())
+            }
+            fn on_exit(&mut self, reason: ExitReason) {
+                self.0.lock().unwrap().push(format!("{:?}", reason));
+            }
+        }
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let h = Hooked(log.clone(), false).spawn();
+        h.cancel();
+        h.wait().unwrap();
+        assert_eq!(*log.lock().unwrap(), ["start", "Cancelled"]);
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        assert!(matches!(Hooked(log.clone(), true).spawn().join(), ExitStatus::Panicked(_)));
+        assert_eq!(*log.lock().unwrap(), ["start", "Panicked"]);
+    }
+
+    #[test]
+    fn on_error_decides_what_is_fatal() {
+        struct Picky(usize);
+        impl Cancellable for Picky {
+            type Error = &'static str;
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                self.0 += 1;
+                match self.0 {
+                    1 => Err("transient"),
+                    2 => Err("slow down"),
+                    _ => Err("fatal"),
+                }
+            }
+            fn on_error(&mut self, err: Self::Error) -> ErrorDisposition<Self::Error> {
+                match err {
+                    "transient" => ErrorDisposition::Continue,
+                    "slow down" => ErrorDisposition::RetryAfter(Duration::from_millis(1)),
+                    _ => ErrorDisposition::Abort(err),
+                }
+            }
+        }
+
+        let mut s = Picky(0);
+        assert_eq!(s.run(), Err("fatal"));
+        assert_eq!(s.0, 3);
+        assert_eq!(Picky(0).spawn().wait(), Err("fatal"));
+    }
+
+    #[test]
+    fn wait_any_cleans_up_after_itself() {
+        struct Quick(bool);
+        impl Cancellable for Quick {
+            type Error = ();
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                thread::sleep(Duration::from_millis(1));
+                Ok(if self.0 { LoopState::Break } else { LoopState::Continue })
+            }
+        }
+
+        let mut handles = vec![Quick(false).spawn()];
+        for _ in 0..10 {
+            handles.push(Quick(true).spawn());
+            let (i, r) = Handle::wait_any(&mut handles);
+            assert_eq!((i, r.is_ok()), (1, true));
+        }
+        assert!(handles[0].status.state.lock().unwrap().watchers.is_empty());
+        handles[0].cancel();
+        handles.remove(0).wait().unwrap();
+    }
 }
@@ -0,0 +1,196 @@
+use super::Canceller;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+#[cfg(unix)]
+use std::{
+    io::Write,
+    os::unix::{
+        io::{AsRawFd, RawFd},
+        net::UnixStream,
+    },
+    sync::OnceLock,
+};
+
+/// A way to wait for a service loop to be cancelled.
+///
+/// Obtained through [`Canceller::listener`], a `CancelListener` lets code integrate with
+/// cancellation in whichever style fits best:
+///
+///  - by blocking, with [`CancelListener::wait`] or [`CancelListener::wait_timeout`];
+///  - from async code, since `CancelListener` is a [`Future`] that resolves once cancelled;
+///  - on Unix, by polling a file descriptor (see [`AsRawFd`]) that becomes readable once
+///    cancelled, alongside other descriptors in `poll`, `epoll`, and friends.
+///
+/// ```
+/// # use minion::*;
+/// # use std::{thread, time::Duration};
+/// # struct Service;
+/// # impl Cancellable for Service {
+/// #     type Error = ();
+/// #     fn for_each(&mut self) -> Result<LoopState, ()> { Ok(LoopState::Continue) }
+/// # }
+/// let h = Service.spawn();
+/// let listener = h.listener();
+/// assert!(!listener.wait_timeout(Duration::from_millis(1)));
+/// h.cancel();
+/// listener.wait();
+/// # h.wait().unwrap();
+/// ```
+pub struct CancelListener {
+    canceller: Canceller,
+    /// The waker to wake once cancelled, and the id of the callback that wakes it.
+    waker: Option<(SharedWaker, Option<u64>)>,
+    /// The descriptor handed out by `as_raw_fd`, and the id of the callback that writes to it.
+    #[cfg(unix)]
+    fd: OnceLock<(UnixStream, Option<u64>)>,
+}
+
+type SharedWaker = Arc<Mutex<Option<Waker>>>;
+
+impl Canceller {
+    /// Get a [`CancelListener`] for waiting until the service loop is cancelled.
+    pub fn listener(&self) -> CancelListener {
+        CancelListener {
+            canceller: self.clone(),
+            waker: None,
+            #[cfg(unix)]
+            fd: OnceLock::new(),
+        }
+    }
+}
+
+impl CancelListener {
+    /// Block the current thread until the service loop is cancelled.
+    pub fn wait(&self) {
+        self.canceller.wait_cancelled(None);
+    }
+
+    /// Block the current thread until the service loop is cancelled, or until `timeout` elapses.
+    ///
+    /// Returns `true` if the service loop was cancelled.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        self.canceller.wait_cancelled(Some(timeout))
+    }
+}
+
+impl Future for CancelListener {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if !self.canceller.keep_running() {
+            return Poll::Ready(());
+        }
+
+        if let Some((ref waker, _)) = self.waker {
+            *waker.lock().unwrap() = Some(cx.waker().clone());
+        } else {
+            let waker = Arc::new(Mutex::new(Some(cx.waker().clone())));
+            let w = waker.clone();
+            let id = self.canceller.register_on_cancel(move || {
+                if let Some(w) = w.lock().unwrap().take() {
+                    w.wake();
+                }
+            });
+            self.waker = Some((waker, id));
+        }
+
+        // we may have been cancelled before the waker was stored
+        if self.canceller.keep_running() {
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    }
+}
+
+#[cfg(unix)]
+impl AsRawFd for CancelListener {
+    /// Returns a file descriptor that becomes readable once the service loop is cancelled.
+    ///
+    /// The descriptor is created on first use, and remains owned by the `CancelListener`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the underlying socket pair could not be created.
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+            .get_or_init(|| {
+                let (rx, mut tx) =
+                    UnixStream::pair().expect("failed to create cancellation socket pair");
+                let id = self.canceller.register_on_cancel(move || {
+                    let _ = tx.write_all(&[1]);
+                });
+                (rx, id)
+            })
+            .0
+            .as_raw_fd()
+    }
+}
+
+impl Drop for CancelListener {
+    fn drop(&mut self) {
+        // the canceller may well outlive us, and should not keep our callbacks around
+        let waker = self.waker.as_ref().and_then(|&(_, id)| id);
+        #[cfg(unix)]
+        let fd = self.fd.get().and_then(|&(_, id)| id);
+        #[cfg(not(unix))]
+        let fd = None;
+        for id in waker.into_iter().chain(fd) {
+            self.canceller.deregister_on_cancel(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn it_resolves_as_a_future() {
+        let c = Canceller::new();
+        let mut l = c.listener();
+        let c2 = c.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            c2.cancel();
+        });
+        let h = crate::from_future(async move {
+            (&mut l).await;
+            Ok::<_, ()>(())
+        });
+        h.wait().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fd_becomes_readable() {
+        use std::io::Read;
+        use std::os::unix::io::FromRawFd;
+
+        let c = Canceller::new();
+        let l = c.listener();
+        let fd = l.as_raw_fd();
+        c.cancel();
+        // borrow the fd without taking ownership of it
+        let mut s = std::mem::ManuallyDrop::new(unsafe { UnixStream::from_raw_fd(fd) });
+        let mut buf = [0];
+        s.read_exact(&mut buf).unwrap();
+    }
+
+    #[test]
+    fn dropped_listeners_deregister() {
+        let c = Canceller::new();
+        for _ in 0..3 {
+            let mut l = c.listener();
+            #[cfg(unix)]
+            l.as_raw_fd();
+            let _ = Pin::new(&mut l).poll(&mut Context::from_waker(Waker::noop()));
+        }
+        assert!(c.signal.lock.lock().unwrap().on_cancel.is_empty());
+    }
+}
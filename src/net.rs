@@ -0,0 +1,727 @@
+//! Helpers for building network services.
+
+use crate::{CancelListener, CancelToken, Cancelled, Canceller, LoopState};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io::{self, prelude::*};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+/// How often [`per_conn`] checks for cancellation while the connection is quiet.
+const CONN_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often sockets are checked for readiness where they cannot be polled alongside a
+/// [`CancelListener`].
+#[cfg(not(unix))]
+const READY_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+#[cfg(all(unix, feature = "reuseport"))]
+pub use self::reuseport::spawn_reuseport;
+
+/// A socket whose blocking reads and writes can be given a timeout.
+pub trait TimeoutSocket {
+    /// See [`TcpStream::set_read_timeout`].
+    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()>;
+    /// See [`TcpStream::set_write_timeout`].
+    fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()>;
+}
+
+impl TimeoutSocket for TcpStream {
+    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, dur)
+    }
+    fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, dur)
+    }
+}
+
+impl TimeoutSocket for UdpSocket {
+    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        UdpSocket::set_read_timeout(self, dur)
+    }
+    fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        UdpSocket::set_write_timeout(self, dur)
+    }
+}
+
+/// Make blocking reads and writes on `socket` give up after `interval`, so that code using the
+/// socket gets a chance to check for cancellation at least that often.
+///
+/// Combine this with [`until_cancelled`] to keep retrying an operation for as long as the
+/// service loop has not been cancelled.
+pub fn set_cancel_interval<S>(socket: &S, interval: Duration) -> io::Result<()>
+where
+    S: TimeoutSocket + ?Sized,
+{
+    socket.set_read_timeout(Some(interval))?;
+    socket.set_write_timeout(Some(interval))
+}
+
+/// Run the socket operation `op` until it completes, retrying it whenever it times out, unless
+/// `token` has been cancelled.
+///
+/// Once `token` is cancelled, this returns an error of kind [`io::ErrorKind::Interrupted`]. If
+/// `socket` had its timeouts set with [`set_cancel_interval`], this means that cancellation is
+/// observed within that interval, even by handlers that are blocked on a quiet connection deep
+/// inside [`Cancellable::for_each`](crate::Cancellable::for_each).
+///
+/// Only retry operations that have no effect when they time out, such as a single `read` or
+/// `write`. In particular, `write_all` may have written part of its buffer by the time it times
+/// out, and retrying it would then send that part twice, so write in a loop that keeps track of
+/// how much has been written instead:
+///
+/// ```no_run
+/// # use minion::*;
+/// use minion::net::{set_cancel_interval, until_cancelled};
+/// use std::io::prelude::*;
+/// use std::time::Duration;
+///
+/// fn handle(mut stream: std::net::TcpStream, exit: &Canceller) -> std::io::Result<()> {
+///     set_cancel_interval(&stream, Duration::from_millis(100))?;
+///     let mut buf = [0; 1024];
+///     loop {
+///         let n = until_cancelled(exit, || stream.read(&mut buf))?;
+///         if n == 0 {
+///             return Ok(());
+///         }
+///         let mut rest = &buf[..n];
+///         while !rest.is_empty() {
+///             match until_cancelled(exit, || stream.write(rest))? {
+///                 0 => return Err(std::io::ErrorKind::WriteZero.into()),
+///                 written => rest = &rest[written..],
+///             }
+///         }
+///     }
+/// }
+/// ```
+pub fn until_cancelled<T, C, F>(token: &C, mut op: F) -> io::Result<T>
+where
+    C: CancelToken + ?Sized,
+    F: FnMut() -> io::Result<T>,
+{
+    loop {
+        if token.is_cancelled() {
+            return Err(cancelled());
+        }
+        match op() {
+            // timeouts show up as WouldBlock on unix, and as TimedOut on windows
+            Err(ref e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {}
+            r => return r,
+        }
+    }
+}
+
+fn cancelled() -> io::Error {
+    Cancelled.into()
+}
+
+/// Resolve `addr` and connect to it, giving up once `canceller` is cancelled or `timeout`
+/// elapses.
+///
+/// A plain [`TcpStream::connect`] to a host that has gone away can block for minutes, which
+/// holds up shutdown of any service loop that is stuck in one. This instead connects in
+/// non-blocking mode, and waits for the connection by polling the socket together with the
+/// descriptor of a [`CancelListener`], so it returns an error of kind
+/// [`io::ErrorKind::Interrupted`] as soon as `canceller` is cancelled, or of kind
+/// [`io::ErrorKind::TimedOut`] once `timeout` has passed. Nothing is left running once it
+/// returns. The returned stream is in blocking mode.
+///
+/// If `addr` resolves to several addresses, they are tried in order until one of them accepts
+/// the connection, all within the same `timeout`. Name lookups cannot be interrupted, and so
+/// happen on the calling thread before the timeout starts; pass a [`SocketAddr`] to skip them.
+///
+/// ```no_run
+/// # use minion::*;
+/// use minion::net::connect_cancellable;
+/// use std::io::prelude::*;
+/// use std::time::Duration;
+///
+/// fn poll(upstream: std::net::SocketAddr, exit: &Canceller) -> std::io::Result<Vec<u8>> {
+///     let mut stream = connect_cancellable(upstream, exit, Duration::from_secs(30))?;
+///     let mut status = Vec::new();
+///     stream.read_to_end(&mut status)?;
+///     Ok(status)
+/// }
+/// ```
+pub fn connect_cancellable<A>(
+    addr: A,
+    canceller: &Canceller,
+    timeout: Duration,
+) -> io::Result<TcpStream>
+where
+    A: ToSocketAddrs,
+{
+    canceller.check()?;
+    let addrs = addr.to_socket_addrs()?;
+    let deadline = Instant::now() + timeout;
+    let cancelled = canceller.listener();
+    let mut last = None;
+    for addr in addrs {
+        match connect_to(addr, canceller, &cancelled, deadline) {
+            Ok(stream) => return Ok(stream),
+            Err(e) if matches!(e.kind(), io::ErrorKind::Interrupted | io::ErrorKind::TimedOut) => {
+                return Err(e)
+            }
+            Err(e) => last = Some(e),
+        }
+    }
+    Err(last.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "no addresses to connect to")
+    }))
+}
+
+fn connect_to(
+    addr: SocketAddr,
+    canceller: &Canceller,
+    cancelled: &CancelListener,
+    deadline: Instant,
+) -> io::Result<TcpStream> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_nonblocking(true)?;
+    match socket.connect(&addr.into()) {
+        Ok(()) => {}
+        Err(ref e) if in_progress(e) => loop {
+            canceller.check()?;
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "connect timed out"));
+            }
+            wait_for(&socket, Ready::Write, cancelled, Some(deadline))?;
+            if let Some(e) = socket.take_error()? {
+                return Err(e);
+            }
+            match socket.peer_addr() {
+                Ok(_) => break,
+                // still connecting
+                Err(ref e) if e.kind() == io::ErrorKind::NotConnected => {}
+                Err(e) => return Err(e),
+            }
+        },
+        Err(e) => return Err(e),
+    }
+    socket.set_nonblocking(false)?;
+    Ok(socket.into())
+}
+
+/// Returns `true` if `e` says that a non-blocking connect is still in progress.
+fn in_progress(e: &io::Error) -> bool {
+    #[cfg(unix)]
+    return e.raw_os_error() == Some(libc::EINPROGRESS);
+    #[cfg(not(unix))]
+    return e.kind() == io::ErrorKind::WouldBlock;
+}
+
+/// Run a cancellable read loop for a single connection.
+///
+/// Every chunk of data read from `stream` is passed to `handler` along with the stream itself,
+/// so that it can write its response. The loop ends when the peer closes the connection, when
+/// `handler` returns [`LoopState::Break`], or when `token` is cancelled, and the connection is
+/// then shut down. Returning [`LoopState::Idle`] or [`LoopState::Continue`] from `handler`
+/// keeps reading, and [`LoopState::Reset`] is treated the same way. Reads give up at a short
+/// interval to check for cancellation (see [`set_cancel_interval`]), so an idle connection does
+/// not hold up shutdown. Any I/O error other than those timeouts ends the loop with that error.
+///
+/// ```no_run
+/// # use minion::*;
+/// # use std::{io::{self, prelude::*}, net, thread};
+/// struct Echo(net::TcpListener, Canceller);
+/// impl Cancellable for Echo {
+///     type Error = io::Error;
+///     fn for_each(&mut self) -> Result<LoopState, io::Error> {
+///         let stream = self.0.accept()?.0;
+///         let exit = self.1.clone();
+///         thread::spawn(move || {
+///             minion::per_conn(stream, &exit, |frame, stream| {
+///                 stream.write_all(frame)?;
+///                 Ok(LoopState::Continue)
+///             })
+///         });
+///         Ok(LoopState::Continue)
+///     }
+/// }
+/// ```
+pub fn per_conn<C, F>(mut stream: TcpStream, token: &C, mut handler: F) -> io::Result<()>
+where
+    C: CancelToken + ?Sized,
+    F: FnMut(&[u8], &mut TcpStream) -> io::Result<LoopState>,
+{
+    set_cancel_interval(&stream, CONN_CHECK_INTERVAL)?;
+    let mut buf = [0; 8192];
+    let result = loop {
+        let n = match until_cancelled(token, || stream.read(&mut buf)) {
+            Ok(0) => break Ok(()),
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted && token.is_cancelled() => {
+                break Ok(());
+            }
+            Err(e) => break Err(e),
+        };
+        match handler(&buf[..n], &mut stream) {
+            Ok(LoopState::Break) => break Ok(()),
+            Ok(_) => {}
+            Err(e) => break Err(e),
+        }
+    };
+    // the peer may well have gone away already
+    let _ = stream.shutdown(Shutdown::Both);
+    result
+}
+
+/// A [`TcpListener`] whose [`accept`](CancellableListener::accept) returns as soon as the service
+/// loop is cancelled, rather than only once the next connection arrives.
+///
+/// The listener is put in non-blocking mode, and `accept` waits by polling it together with the
+/// descriptor of a [`CancelListener`] for the loop, so it wakes up for whichever of the two comes
+/// first, without a helper thread and without checking back at an interval. A loop that is asked
+/// to [drain](Canceller::drain) is woken up once it is cancelled at the end of its grace period.
+/// On platforms other than Unix, where there is no such descriptor, a pending `accept` still
+/// wakes up right away when cancelled, but checks for new connections every few milliseconds.
+///
+/// ```no_run
+/// # use minion::*;
+/// # use std::{io::{self, prelude::*}, net};
+/// use minion::net::CancellableListener;
+///
+/// struct Acceptor(CancellableListener);
+/// impl Cancellable for Acceptor {
+///     type Error = io::Error;
+///     fn for_each(&mut self) -> Result<LoopState, io::Error> {
+///         match self.0.accept() {
+///             Ok((mut stream, _)) => write!(stream, "hello!\n")?,
+///             Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {
+///                 return Ok(LoopState::Break)
+///             }
+///             Err(e) => return Err(e),
+///         }
+///         Ok(LoopState::Continue)
+///     }
+/// }
+///
+/// let exit = Canceller::new();
+/// let listener = net::TcpListener::bind("127.0.0.1:6556")?;
+/// let listener = CancellableListener::new(listener, &exit)?;
+/// let h = Acceptor(listener).builder().canceller(exit).spawn();
+/// h.cancel();
+/// h.wait()?;
+/// # Ok::<_, io::Error>(())
+/// ```
+pub struct CancellableListener {
+    listener: TcpListener,
+    canceller: Canceller,
+    cancelled: CancelListener,
+}
+
+impl CancellableListener {
+    /// Wrap `listener` so that accepting from it gives up once `canceller` is cancelled.
+    ///
+    /// `canceller` should be the one that the accepting service loop is spawned with (see
+    /// [`ServiceBuilder::canceller`](crate::ServiceBuilder::canceller)). The listener is put in
+    /// non-blocking mode.
+    pub fn new(listener: TcpListener, canceller: &Canceller) -> io::Result<Self> {
+        listener.set_nonblocking(true)?;
+        Ok(CancellableListener {
+            listener,
+            canceller: canceller.clone(),
+            cancelled: canceller.listener(),
+        })
+    }
+
+    /// Wait for a new connection, or for the service loop to be cancelled.
+    ///
+    /// In the latter case (and for every call after it), this returns [`Cancelled`] as an error
+    /// of kind [`io::ErrorKind::Interrupted`], so that [`Cancellable::for_each`] can return and
+    /// let the loop wind down. The accepted stream is in blocking mode.
+    ///
+    /// [`Cancelled`]: crate::Cancelled
+    /// [`Cancellable::for_each`]: crate::Cancellable::for_each
+    pub fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        loop {
+            self.canceller.check()?;
+            match self.listener.accept() {
+                Ok((stream, addr)) => {
+                    // some platforms have accepted sockets inherit non-blocking mode
+                    stream.set_nonblocking(false)?;
+                    return Ok((stream, addr));
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    wait_for(&self.listener, Ready::Read, &self.cancelled, None)?;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// The address the listener is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Get back the wrapped listener, in blocking mode.
+    pub fn into_inner(self) -> io::Result<TcpListener> {
+        self.listener.set_nonblocking(false)?;
+        Ok(self.listener)
+    }
+}
+
+/// What [`wait_for`] waits for a socket to become.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ready {
+    Read,
+    Write,
+}
+
+/// Block until `socket` is [ready](Ready), until the service loop behind `cancelled` is
+/// cancelled, or until `deadline` passes, whichever comes first.
+///
+/// This may also return for no reason at all, so callers should check all three again.
+#[cfg(unix)]
+fn wait_for<S: AsRawFd>(
+    socket: &S,
+    ready: Ready,
+    cancelled: &CancelListener,
+    deadline: Option<Instant>,
+) -> io::Result<()> {
+    let events = match ready {
+        Ready::Read => libc::POLLIN,
+        Ready::Write => libc::POLLOUT,
+    };
+    let mut fds = [
+        libc::pollfd {
+            fd: socket.as_raw_fd(),
+            events,
+            revents: 0,
+        },
+        libc::pollfd {
+            fd: cancelled.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        },
+    ];
+    let timeout = match deadline {
+        // rounded up, so that we do not wake up just short of the deadline
+        Some(d) => {
+            let left = d.saturating_duration_since(Instant::now()).as_nanos();
+            left.div_ceil(1_000_000).min(libc::c_int::MAX as u128) as libc::c_int
+        }
+        None => -1,
+    };
+    if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) } == -1 {
+        let e = io::Error::last_os_error();
+        // interrupted by a signal, which the caller treats like any other spurious wake-up
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Block until the service loop behind `cancelled` is cancelled, `deadline` passes, or it is time
+/// to check on the socket again.
+#[cfg(not(unix))]
+fn wait_for<S>(
+    _: &S,
+    _: Ready,
+    cancelled: &CancelListener,
+    deadline: Option<Instant>,
+) -> io::Result<()> {
+    let mut nap = READY_CHECK_INTERVAL;
+    if let Some(d) = deadline {
+        nap = nap.min(d.saturating_duration_since(Instant::now()));
+    }
+    cancelled.wait_timeout(nap);
+    Ok(())
+}
+
+#[cfg(all(unix, feature = "reuseport"))]
+mod reuseport {
+    use super::CancellableListener;
+    use crate::{Cancellable, Canceller, GroupHandle};
+    use socket2::{Domain, Protocol, Socket, Type};
+    use std::io;
+    use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+
+    fn bind(addr: SocketAddr) -> io::Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        socket.set_reuse_address(true)?;
+        socket.set_reuse_port(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(1024)?;
+        Ok(socket.into())
+    }
+
+    /// Spawn `n` accept loops that all listen on `addr`, letting the kernel balance incoming
+    /// connections between them.
+    ///
+    /// Each loop gets its own listening socket bound with `SO_REUSEPORT`, and is constructed by
+    /// passing that socket to `factory`. All the loops share a single [`Canceller`], so
+    /// cancelling any member of the returned group cancels them all. Each socket is handed over
+    /// as a [`CancellableListener`] for that canceller, so loops that are waiting in
+    /// [`accept`](CancellableListener::accept) wake up as soon as they are cancelled.
+    ///
+    /// If `addr` has port 0, the first listener is bound to an arbitrary free port, and the
+    /// remaining listeners are bound to that same port.
+    ///
+    /// ```no_run
+    /// # use minion::*;
+    /// # use std::{io::{self, prelude::*}, net};
+    /// use minion::net::CancellableListener;
+    ///
+    /// struct Acceptor(CancellableListener);
+    /// impl Cancellable for Acceptor {
+    ///     type Error = io::Error;
+    ///     fn for_each(&mut self) -> Result<LoopState, io::Error> {
+    ///         let mut stream = match self.0.accept() {
+    ///             Ok((stream, _)) => stream,
+    ///             Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {
+    ///                 return Ok(LoopState::Break)
+    ///             }
+    ///             Err(e) => return Err(e),
+    ///         };
+    ///         write!(stream, "hello!\n")?;
+    ///         Ok(LoopState::Continue)
+    ///     }
+    /// }
+    ///
+    /// let acceptors = minion::net::spawn_reuseport("0.0.0.0:6556", 4, Acceptor).unwrap();
+    /// acceptors.wait_all(Aggregation::FailFast).unwrap();
+    /// ```
+    pub fn spawn_reuseport<A, F, S>(
+        addr: A,
+        n: usize,
+        mut factory: F,
+    ) -> io::Result<GroupHandle<S::Error>>
+    where
+        A: ToSocketAddrs,
+        F: FnMut(CancellableListener) -> S,
+        S: Cancellable + Send + 'static,
+        S::Error: Send + 'static,
+    {
+        let mut addr = addr.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "no address to bind to")
+        })?;
+
+        let mut listeners = Vec::with_capacity(n);
+        for _ in 0..n {
+            let l = bind(addr)?;
+            addr = l.local_addr()?;
+            listeners.push(l);
+        }
+
+        let canceller = Canceller::new();
+        let mut group = GroupHandle::new();
+        for (i, l) in listeners.into_iter().enumerate() {
+            let name = format!("acceptor-{}", i);
+            let h = factory(CancellableListener::new(l, &canceller)?)
+                .builder()
+                .name(name.clone())
+                .canceller(canceller.clone())
+                .spawn();
+            group.push(name, h);
+        }
+        Ok(group)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::{Aggregation, LoopState};
+        use std::net::TcpStream;
+        use std::sync::{mpsc, Arc};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::thread;
+        use std::time::{Duration, Instant};
+
+        struct Acceptor(CancellableListener, Arc<AtomicUsize>);
+
+        impl Cancellable for Acceptor {
+            type Error = io::Error;
+            fn for_each(&mut self) -> Result<LoopState, io::Error> {
+                match self.0.accept() {
+                    Ok(_) => {
+                        self.1.fetch_add(1, Ordering::SeqCst);
+                        Ok(LoopState::Continue)
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => Ok(LoopState::Break),
+                    Err(e) => Err(e),
+                }
+            }
+        }
+
+        #[test]
+        fn it_binds_the_same_port() {
+            let (tx, rx) = mpsc::channel();
+            let accepted = Arc::new(AtomicUsize::new(0));
+            let group = spawn_reuseport("127.0.0.1:0", 3, |l| {
+                tx.send(l.local_addr().unwrap()).unwrap();
+                Acceptor(l, accepted.clone())
+            })
+            .unwrap();
+            let addrs: Vec<_> = rx.try_iter().collect();
+            assert_eq!(addrs.len(), 3);
+            assert!(addrs.iter().all(|&a| a == addrs[0]));
+
+            for _ in 0..6 {
+                TcpStream::connect(addrs[0]).unwrap();
+            }
+            let start = Instant::now();
+            while accepted.load(Ordering::SeqCst) < 6 {
+                assert!(start.elapsed() < Duration::from_secs(5));
+                thread::sleep(Duration::from_millis(1));
+            }
+
+            // every acceptor is now blocked waiting for a connection that never comes
+            thread::sleep(Duration::from_millis(20));
+            let start = Instant::now();
+            group.cancel_all();
+            group.wait_all(Aggregation::FailFast).unwrap();
+            assert!(start.elapsed() < Duration::from_secs(1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cancellable, Cancelled, Canceller, ExitReason, LoopState};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Instant;
+
+    #[test]
+    fn accept_wakes_on_cancel() {
+        struct Acceptor(CancellableListener, usize);
+        impl Cancellable for Acceptor {
+            type Error = io::Error;
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                match self.0.accept() {
+                    Ok(_) => self.1 += 1,
+                    Err(e) => {
+                        assert!(e.get_ref().unwrap().is::<Cancelled>());
+                        return Ok(LoopState::Break);
+                    }
+                }
+                Ok(LoopState::Continue)
+            }
+            fn on_exit(&mut self, _: ExitReason) {
+                assert_eq!(self.1, 1);
+            }
+        }
+
+        let exit = Canceller::new();
+        let l = CancellableListener::new(TcpListener::bind("0.0.0.0:0").unwrap(), &exit).unwrap();
+        let port = l.local_addr().unwrap().port();
+        let h = Acceptor(l, 0).builder().canceller(exit).spawn();
+        TcpStream::connect(("127.0.0.1", port)).unwrap();
+
+        // no further connection ever arrives, yet the loop still exits promptly
+        thread::sleep(Duration::from_millis(50));
+        let start = Instant::now();
+        h.cancel();
+        assert!(start.elapsed() < Duration::from_millis(100));
+        h.wait().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn per_conn_echoes_until_cancelled() {
+        let l = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(l.local_addr().unwrap()).unwrap();
+        let stream = l.accept().unwrap().0;
+
+        let exit = Canceller::new();
+        let token = exit.clone();
+        let t = thread::spawn(move || {
+            per_conn(stream, &token, |frame, stream| {
+                stream.write_all(frame)?;
+                Ok(LoopState::Continue)
+            })
+        });
+
+        client.write_all(b"ping").unwrap();
+        let mut buf = [0; 4];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+
+        exit.cancel();
+        t.join().unwrap().unwrap();
+        // the handler shut down the connection on its way out
+        assert_eq!(client.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn quiet_connections_observe_cancellation() {
+        struct Handler(TcpStream, Canceller);
+        impl Cancellable for Handler {
+            type Error = io::Error;
+            fn for_each(&mut self) -> Result<LoopState, io::Error> {
+                let Handler(ref mut stream, ref exit) = *self;
+                let mut buf = [0; 16];
+                match until_cancelled(exit, || stream.read(&mut buf)) {
+                    Ok(_) => Ok(LoopState::Continue),
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => Ok(LoopState::Break),
+                    Err(e) => Err(e),
+                }
+            }
+        }
+
+        let l = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(l.local_addr().unwrap()).unwrap();
+        let stream = l.accept().unwrap().0;
+        set_cancel_interval(&stream, Duration::from_millis(10)).unwrap();
+
+        let canceller = Canceller::new();
+        let h = Handler(stream, canceller.clone())
+            .builder()
+            .canceller(canceller)
+            .spawn();
+        thread::sleep(Duration::from_millis(20));
+
+        // the client never sends anything, but the handler still exits promptly
+        let start = Instant::now();
+        h.cancel();
+        h.wait().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn connect_gives_up_on_cancel() {
+        let l = TcpListener::bind("127.0.0.1:0").unwrap();
+        let exit = Canceller::new();
+        let timeout = Duration::from_secs(10);
+        connect_cancellable(l.local_addr().unwrap(), &exit, timeout).unwrap();
+
+        // once its backlog is full, the listener ignores further connection attempts
+        let full = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+        full.bind(&SocketAddr::from(([127, 0, 0, 1], 0)).into()).unwrap();
+        full.listen(0).unwrap();
+        let addr = full.local_addr().unwrap().as_socket().unwrap();
+        let mut backlog = Vec::new();
+        let stuck = loop {
+            let c = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+            c.set_nonblocking(true).unwrap();
+            let _ = c.connect(&addr.into());
+            thread::sleep(Duration::from_millis(10));
+            if c.peer_addr().is_err() {
+                break c;
+            }
+            backlog.push(c);
+            assert!(backlog.len() < 16, "listener backlog never filled up");
+        };
+        drop(stuck);
+
+        let c = exit.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            c.cancel();
+        });
+        let start = Instant::now();
+        let e = connect_cancellable(addr, &exit, timeout).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::Interrupted);
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        let e = connect_cancellable(addr, &Canceller::new(), Duration::from_millis(20));
+        assert_eq!(e.unwrap_err().kind(), io::ErrorKind::TimedOut);
+    }
+}
@@ -0,0 +1,113 @@
+use super::{Cancellable, LoopState};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// A service that watches a directory, and handles every file that appears or changes in it.
+///
+/// Each iteration lists the directory, and passes the path of every file that is new or has been
+/// modified since the previous iteration to the handler. Iterations that find nothing new are
+/// [idle](LoopState::Idle). Subdirectories are not descended into. Spawn the scanner with a
+/// [throttle](crate::ServiceBuilder::throttle) to decide how often it looks:
+///
+/// ```no_run
+/// # use minion::*;
+/// # use std::time::Duration;
+/// let h = DirScanner::new("/var/spool/incoming", |path| {
+///     println!("new file: {}", path.display());
+///     Ok::<_, std::io::Error>(())
+/// })
+/// .builder()
+/// .throttle(Duration::from_secs(5))
+/// .spawn();
+/// # h.cancel();
+/// ```
+pub struct DirScanner<F> {
+    dir: PathBuf,
+    seen: HashMap<PathBuf, SystemTime>,
+    handler: F,
+}
+
+impl<F, E> DirScanner<F>
+where
+    F: FnMut(&Path) -> Result<(), E>,
+    E: From<io::Error>,
+{
+    /// Create a scanner that passes files in `dir` to `handler`.
+    ///
+    /// Files that are already in the directory are handled on the first iteration.
+    pub fn new<P: Into<PathBuf>>(dir: P, handler: F) -> Self {
+        DirScanner {
+            dir: dir.into(),
+            seen: HashMap::new(),
+            handler,
+        }
+    }
+}
+
+impl<F, E> Cancellable for DirScanner<F>
+where
+    F: FnMut(&Path) -> Result<(), E>,
+    E: From<io::Error>,
+{
+    type Error = E;
+
+    fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+        let mut present = HashMap::with_capacity(self.seen.len());
+        let mut changed = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let meta = entry.metadata()?;
+            if !meta.is_file() {
+                continue;
+            }
+            let path = entry.path();
+            let modified = meta.modified()?;
+            if self.seen.get(&path) != Some(&modified) {
+                changed.push(path.clone());
+            }
+            present.insert(path, modified);
+        }
+        // forget files that have been removed, so they are handled again if they reappear
+        self.seen = present;
+
+        if changed.is_empty() {
+            return Ok(LoopState::Idle);
+        }
+        changed.sort();
+        for path in changed {
+            (self.handler)(&path)?;
+        }
+        Ok(LoopState::Continue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use std::io;
+    use std::path::PathBuf;
+
+    #[test]
+    fn dir_scanner_sees_new_files() {
+        let dir = std::env::temp_dir().join(format!("minion-scan-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a"), "a").unwrap();
+
+        let mut seen = Vec::new();
+        let mut scanner = DirScanner::new(&dir, |p: &std::path::Path| {
+            seen.push(p.file_name().unwrap().to_owned());
+            Ok::<_, io::Error>(())
+        });
+        assert!(matches!(scanner.for_each().unwrap(), LoopState::Continue));
+        assert!(matches!(scanner.for_each().unwrap(), LoopState::Idle));
+        std::fs::write(dir.join("b"), "b").unwrap();
+        assert!(matches!(scanner.for_each().unwrap(), LoopState::Continue));
+        drop(scanner);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(seen, [PathBuf::from("a"), PathBuf::from("b")]);
+    }
+}
@@ -0,0 +1,147 @@
+use std::time::{Duration, Instant};
+
+/// A service that can tell whether it is currently able to accept work.
+///
+/// A service spawned with a
+/// [`ServiceBuilder::readiness_probe`](crate::ServiceBuilder::readiness_probe) is not
+/// [ready](crate::Handle::is_ready) until its probe has passed, and stops being ready whenever the
+/// probe keeps failing (say, because a backend it depends on is unreachable). Unlike a failing
+/// [`LivenessProbe`], a failing readiness probe does not stop the service loop.
+pub trait ReadinessProbe {
+    /// Returns `true` if the service is able to accept work.
+    fn ready(&mut self) -> bool;
+}
+
+/// A service that can tell whether it is still making progress.
+///
+/// A service spawned with a
+/// [`ServiceBuilder::liveness_probe`](crate::ServiceBuilder::liveness_probe) is taken down once
+/// its probe keeps failing, so that whoever supervises it can start it over.
+pub trait LivenessProbe {
+    /// Returns `true` if the service is healthy.
+    fn alive(&mut self) -> bool;
+}
+
+/// When to run a probe, and how many results in a row it takes to change the verdict.
+///
+/// The defaults follow those of Kubernetes probes: no initial delay, a ten second period, three
+/// failures to be considered failing, and a single success to be considered passing again.
+///
+/// ```
+/// # use minion::*;
+/// # use std::time::Duration;
+/// let schedule = ProbeSchedule::new()
+///     .initial_delay(Duration::from_secs(5))
+///     .period(Duration::from_secs(1))
+///     .failure_threshold(5);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct ProbeSchedule {
+    initial_delay: Duration,
+    period: Duration,
+    failure_threshold: u32,
+    success_threshold: u32,
+}
+
+impl Default for ProbeSchedule {
+    fn default() -> Self {
+        ProbeSchedule {
+            initial_delay: Duration::from_secs(0),
+            period: Duration::from_secs(10),
+            failure_threshold: 3,
+            success_threshold: 1,
+        }
+    }
+}
+
+impl ProbeSchedule {
+    /// Create a schedule where everything is left at its default.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wait for `delay` after the service loop starts before running the probe for the first
+    /// time.
+    pub fn initial_delay(mut self, delay: Duration) -> Self {
+        self.initial_delay = delay;
+        self
+    }
+
+    /// Run the probe once every `period`.
+    pub fn period(mut self, period: Duration) -> Self {
+        self.period = period;
+        self
+    }
+
+    /// Consider the probe failing after `n` failures in a row.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is zero.
+    pub fn failure_threshold(mut self, n: u32) -> Self {
+        assert!(n > 0, "a probe must fail at least once to be failing");
+        self.failure_threshold = n;
+        self
+    }
+
+    /// Consider the probe passing again after `n` successes in a row.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is zero.
+    pub fn success_threshold(mut self, n: u32) -> Self {
+        assert!(n > 0, "a probe must succeed at least once to be passing");
+        self.success_threshold = n;
+        self
+    }
+}
+
+/// Runs one probe against a service according to its schedule.
+pub(crate) struct Prober<S> {
+    check: fn(&mut S) -> bool,
+    schedule: ProbeSchedule,
+    next: Option<Instant>,
+    passing: bool,
+    streak: u32,
+}
+
+impl<S> Prober<S> {
+    /// Probe with `check`, starting out with the verdict `passing`.
+    pub(crate) fn new(check: fn(&mut S) -> bool, schedule: ProbeSchedule, passing: bool) -> Self {
+        Prober {
+            check,
+            schedule,
+            next: None,
+            passing,
+            streak: 0,
+        }
+    }
+
+    /// Run the probe if it is due, and return the new verdict if it changed.
+    pub(crate) fn poll(&mut self, service: &mut S) -> Option<bool> {
+        let now = Instant::now();
+        let initial_delay = self.schedule.initial_delay;
+        let next = *self.next.get_or_insert_with(|| now + initial_delay);
+        if now < next {
+            return None;
+        }
+        self.next = Some(now + self.schedule.period);
+
+        if (self.check)(service) == self.passing {
+            self.streak = 0;
+            return None;
+        }
+        self.streak += 1;
+        let threshold = if self.passing {
+            self.schedule.failure_threshold
+        } else {
+            self.schedule.success_threshold
+        };
+        if self.streak < threshold {
+            return None;
+        }
+        self.streak = 0;
+        self.passing = !self.passing;
+        Some(self.passing)
+    }
+}
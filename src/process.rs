@@ -0,0 +1,143 @@
+//! Supervision of external OS processes through the regular [`Handle`] API.
+//!
+//! [`spawn`] starts a child process and returns a [`Handle`] to it, so that out-of-process
+//! workers can be cancelled, waited for, and [grouped](crate::GroupHandle::push) just like
+//! in-process service loops:
+//!
+//! ```no_run
+//! use std::process::Command;
+//! use std::time::Duration;
+//!
+//! let h = minion::process::spawn(&mut Command::new("my-worker"), Duration::from_secs(5)).unwrap();
+//! // sends SIGTERM now, and SIGKILL if the worker is still around five seconds from now
+//! h.cancel();
+//! h.wait().unwrap();
+//! ```
+
+use crate::{Canceller, Handle};
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Child, Command};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often the supervising thread checks whether the child process has exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The ways in which a supervised process can fail.
+#[derive(Debug)]
+pub enum ProcessError {
+    /// Waiting for or signalling the process failed.
+    Io(io::Error),
+    /// The process exited with the given non-zero exit code.
+    Exited(i32),
+    /// The process was terminated by the given signal without having been cancelled.
+    Signaled(i32),
+}
+
+impl From<io::Error> for ProcessError {
+    fn from(e: io::Error) -> Self {
+        ProcessError::Io(e)
+    }
+}
+
+/// Start `cmd` as a child process, and return a [`Handle`] that manages it.
+///
+/// Cancelling the handle sends the process `SIGTERM`. If it has not exited once `grace` has
+/// passed after that, it is sent `SIGKILL`. Waiting on the handle waits for the process to exit,
+/// and translates its exit status: exiting with code 0, or being terminated by either signal
+/// after cancellation, counts as success, while anything else is reported as a
+/// [`ProcessError`].
+///
+/// The process is reaped by a dedicated supervising thread, which checks on it at a short, fixed
+/// interval. The returned handle becomes [ready](Handle::is_ready) as soon as the process has
+/// started.
+///
+/// Returns an error if the process could not be started.
+pub fn spawn(cmd: &mut Command, grace: Duration) -> io::Result<Handle<ProcessError>> {
+    let child = cmd.spawn()?;
+    let canceller = Canceller::new();
+    let cancelled = canceller.clone();
+    let tb = thread::Builder::new().name(format!("minion-process-{}", child.id()));
+    Ok(Handle::spawn(tb, canceller, move |status| {
+        status.set_ready();
+        supervise(child, &cancelled, grace)
+    }))
+}
+
+fn supervise(mut child: Child, cancelled: &Canceller, grace: Duration) -> Result<(), ProcessError> {
+    let mut kill_at = None;
+    let mut killed = false;
+    loop {
+        // the child is only ever reaped here, so its pid cannot have been reused when we signal it
+        if let Some(status) = child.try_wait()? {
+            if let Some(code) = status.code() {
+                return if code == 0 {
+                    Ok(())
+                } else {
+                    Err(ProcessError::Exited(code))
+                };
+            }
+            let signal = status.signal().unwrap_or(0);
+            if kill_at.is_some() && (signal == libc::SIGTERM || signal == libc::SIGKILL) {
+                return Ok(());
+            }
+            return Err(ProcessError::Signaled(signal));
+        }
+
+        match kill_at {
+            None if !cancelled.keep_running() => {
+                if unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) } != 0 {
+                    return Err(io::Error::last_os_error().into());
+                }
+                kill_at = Some(Instant::now() + grace);
+            }
+            Some(at) if !killed && Instant::now() >= at => {
+                child.kill()?;
+                killed = true;
+            }
+            _ => {}
+        }
+
+        if kill_at.is_none() {
+            cancelled.wait_cancelled(Some(POLL_INTERVAL));
+        } else {
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Stdio;
+
+    #[test]
+    fn it_translates_exit_codes() {
+        let h = spawn(&mut Command::new("true"), Duration::from_secs(1)).unwrap();
+        h.wait().unwrap();
+
+        let h = spawn(Command::new("sh").args(["-c", "exit 3"]), Duration::from_secs(1)).unwrap();
+        match h.wait() {
+            Err(ProcessError::Exited(3)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn cancel_escalates_to_sigkill() {
+        // ignore SIGTERM, so that only SIGKILL will do
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "trap '' TERM; while true; do sleep 0.01; done"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        let h = spawn(&mut cmd, Duration::from_millis(100)).unwrap();
+        assert!(h.wait_ready(Duration::from_secs(1)));
+        thread::sleep(Duration::from_millis(50));
+
+        let start = Instant::now();
+        h.cancel();
+        h.wait().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,286 @@
+use super::{Canceller, Handle, Status};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often [`Registry::quiesce`] re-checks the registered services.
+const QUIESCE_POLL: Duration = Duration::from_millis(10);
+
+/// A directory of running service loops, through which they can be inspected and controlled by
+/// name.
+///
+/// Services are added to a registry by spawning them with
+/// [`ServiceBuilder::register`](crate::ServiceBuilder::register), and are removed again once they
+/// exit. Most programs use the process-wide [`Registry::global`], but separate registries can be
+/// created for parts of a program (or tests) that should be managed independently.
+///
+/// ```
+/// # use minion::*;
+/// # struct Service;
+/// # impl Cancellable for Service {
+/// #     type Error = ();
+/// #     fn for_each(&mut self) -> Result<LoopState, ()> { Ok(LoopState::Continue) }
+/// # }
+/// let registry = Registry::new();
+/// let h = Service.builder().name("poller").register(&registry).spawn();
+/// assert_eq!(registry.names(), ["poller"]);
+///
+/// // e.g., on SIGHUP
+/// registry.reload_all();
+///
+/// h.cancel();
+/// h.wait().unwrap();
+/// assert!(registry.names().is_empty());
+/// ```
+#[derive(Clone, Default)]
+pub struct Registry {
+    entries: Arc<Mutex<Vec<Entry>>>,
+}
+
+struct Entry {
+    name: String,
+    canceller: Canceller,
+    status: Arc<Status>,
+}
+
+impl Registry {
+    /// Create a new, empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The process-wide registry.
+    pub fn global() -> &'static Registry {
+        static GLOBAL: OnceLock<Registry> = OnceLock::new();
+        GLOBAL.get_or_init(Registry::new)
+    }
+
+    pub(crate) fn add<E, T>(&self, name: String, handle: &Handle<E, T>) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|e| !e.status.has_exited());
+        entries.push(Entry {
+            name,
+            canceller: handle.canceller(),
+            status: handle.status.clone(),
+        });
+    }
+
+    /// Call `f` with every registered service that has not yet exited.
+    pub(crate) fn each<F: FnMut(&str, &Canceller, &Status)>(&self, mut f: F) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|e| !e.status.has_exited());
+        for e in entries.iter() {
+            f(&e.name, &e.canceller, &e.status);
+        }
+    }
+
+    /// Find the running service registered under `name`.
+    fn find<T, F: FnOnce(&Canceller, &Status) -> T>(&self, name: &str, f: F) -> Option<T> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .iter()
+            .find(|e| e.name == name && !e.status.has_exited())
+            .map(|e| f(&e.canceller, &e.status))
+    }
+
+    /// The names of all running services in the registry, in the order they were registered.
+    pub fn names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        self.each(|name, _, _| names.push(name.to_string()));
+        names
+    }
+
+    /// Get a [`Canceller`] for the running service registered under `name`.
+    pub fn canceller(&self, name: &str) -> Option<Canceller> {
+        self.find(name, |c, _| c.clone())
+    }
+
+    /// Returns `true` if a running service is registered under `name` and is
+    /// [ready](crate::Handle::is_ready).
+    pub fn is_ready(&self, name: &str) -> bool {
+        self.find(name, |_, s| s.is_ready()).unwrap_or(false)
+    }
+
+    /// Cancel every running service in the registry.
+    pub fn cancel_all(&self) {
+        self.each(|_, c, _| {
+            c.cancel();
+        });
+    }
+
+    /// Ask the service registered under `name` to [reload](crate::Cancellable::reload).
+    ///
+    /// Returns `false` if no running service is registered under that name.
+    pub fn reload(&self, name: &str) -> bool {
+        self.find(name, |_, s| s.request_reload()).is_some()
+    }
+
+    /// Ask every running service in the registry to [reload](crate::Cancellable::reload).
+    pub fn reload_all(&self) {
+        self.each(|_, _, s| s.request_reload());
+    }
+
+    /// Block until every running service in the registry is quiescent, or until `timeout`
+    /// elapses.
+    ///
+    /// A service is quiescent once its most recent iteration was
+    /// [idle](crate::Activity::Idle) and it has reported an empty
+    /// [queue](crate::Context::set_queue_depth). Services that never report either are never
+    /// quiescent, so only services that do should be registered where this is used.
+    ///
+    /// Returns `true` if all services were quiescent at the same time. Note that nothing stops
+    /// new work from arriving right after this returns.
+    pub fn quiesce(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let mut quiet = true;
+            self.each(|_, _, s| quiet &= s.counters.is_quiescent());
+            if quiet {
+                return true;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            thread::sleep(QUIESCE_POLL.min(deadline - now));
+        }
+    }
+
+    /// The most recent errors returned by the iterations of the running service registered under
+    /// `name`, oldest first.
+    ///
+    /// See [`Handle::recent_errors`](crate::Handle::recent_errors).
+    pub fn recent_errors(&self, name: &str) -> Vec<crate::RecentError> {
+        self.find(name, |_, s| s.errors.lock().unwrap().recent())
+            .unwrap_or_default()
+    }
+
+    /// The most recently published configuration of the service registered under `name`.
+    ///
+    /// See [`Cancellable::config`](crate::Cancellable::config).
+    #[cfg(feature = "serde")]
+    pub fn config(&self, name: &str) -> Option<serde_json::Value> {
+        self.find(name, |_, s| s.config.lock().unwrap().current())?
+    }
+
+    /// What changed in the configuration of the service registered under `name` on each of its
+    /// recent reloads, oldest first.
+    #[cfg(feature = "serde")]
+    pub fn config_changes(&self, name: &str) -> Vec<crate::ConfigChange> {
+        self.find(name, |_, s| s.config.lock().unwrap().changes())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    struct Reloadable {
+        reloads: Arc<AtomicUsize>,
+        size: usize,
+    }
+
+    impl Cancellable for Reloadable {
+        type Error = ();
+        fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+            std::thread::sleep(Duration::from_millis(1));
+            Ok(LoopState::Continue)
+        }
+        fn reload(&mut self) -> Result<(), Self::Error> {
+            self.reloads.fetch_add(1, Ordering::SeqCst);
+            self.size *= 2;
+            Ok(())
+        }
+        #[cfg(feature = "serde")]
+        fn config(&self) -> Option<serde_json::Value> {
+            Some(serde_json::json!({ "name": "r", "pool": { "size": self.size } }))
+        }
+    }
+
+    fn spawn(registry: &Registry) -> (Handle<()>, Arc<AtomicUsize>) {
+        let reloads = Arc::new(AtomicUsize::new(0));
+        let h = Reloadable {
+            reloads: reloads.clone(),
+            size: 1,
+        }
+        .builder()
+        .name("r")
+        .register(registry)
+        .spawn();
+        assert!(h.wait_ready(Duration::from_secs(1)));
+        (h, reloads)
+    }
+
+    #[test]
+    fn it_reloads_by_name() {
+        let registry = Registry::new();
+        let (h, reloads) = spawn(&registry);
+        assert!(registry.reload("r"));
+        assert!(!registry.reload("missing"));
+        while reloads.load(Ordering::SeqCst) == 0 {
+            std::thread::yield_now();
+        }
+
+        registry.cancel_all();
+        h.wait().unwrap();
+        assert!(registry.canceller("r").is_none());
+    }
+
+    #[test]
+    fn it_waits_for_quiescence() {
+        use std::sync::mpsc;
+
+        let registry = Registry::new();
+        let (tx, rx) = mpsc::channel();
+        for n in 0..100 {
+            tx.send(n).unwrap();
+        }
+        let h = Consumer::new(rx, |_: usize| {
+            std::thread::sleep(Duration::from_millis(1));
+            Ok::<_, ()>(())
+        })
+        .builder()
+        .name("consumer")
+        .register(&registry)
+        .spawn();
+
+        assert!(!registry.quiesce(Duration::from_millis(10)));
+        assert!(registry.quiesce(Duration::from_secs(5)));
+
+        drop(tx);
+        h.wait().unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn it_records_config_changes() {
+        let registry = Registry::new();
+        let (h, reloads) = spawn(&registry);
+        assert_eq!(registry.config("r").unwrap()["pool"]["size"], 1);
+
+        registry.reload_all();
+        while reloads.load(Ordering::SeqCst) == 0 {
+            std::thread::yield_now();
+        }
+        // the change is recorded right after the reload itself
+        while registry.config_changes("r").is_empty() {
+            std::thread::yield_now();
+        }
+        let changes = registry.config_changes("r");
+        assert_eq!(
+            changes[0].fields,
+            [FieldChange {
+                path: "pool.size".to_string(),
+                old: Some(1.into()),
+                new: Some(2.into()),
+            }]
+        );
+
+        h.cancel();
+        h.wait().unwrap();
+    }
+}
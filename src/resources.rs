@@ -0,0 +1,105 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+/// A map of shared resources, such as database pools and HTTP clients, keyed by their type.
+///
+/// Give a map to the services that need it with
+/// [`ServiceBuilder::resources`](crate::ServiceBuilder::resources) (or have a
+/// [`Supervisor`](crate::Supervisor) do so for all of its services), and they can then look up
+/// what they need through [`Context::resource`](crate::Context::resource), rather than every
+/// factory having to capture its own handles to everything. Cloning a map gives another handle
+/// on the same resources, so resources inserted later are seen by services that are already
+/// running.
+///
+/// ```
+/// # use minion::*;
+/// struct DbPool;
+/// impl DbPool {
+///     fn query(&self) {}
+/// }
+///
+/// struct Indexer;
+/// impl Cancellable for Indexer {
+///     type Error = &'static str;
+///     fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+///         self.for_each_ctx(&mut Context::new())
+///     }
+///     fn for_each_ctx(&mut self, ctx: &mut Context) -> Result<LoopState, Self::Error> {
+///         let db = ctx.resource::<DbPool>().ok_or("no database")?;
+///         db.query();
+///         Ok(LoopState::Break)
+///     }
+/// }
+///
+/// let resources = Resources::new();
+/// resources.insert(DbPool);
+/// Indexer.builder().resources(&resources).spawn().wait().unwrap();
+/// ```
+#[derive(Clone, Default)]
+pub struct Resources {
+    map: Arc<RwLock<HashMap<TypeId, Arc<dyn Any + Send + Sync>>>>,
+}
+
+impl Resources {
+    /// Create a new, empty map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `resource`, replacing (and returning) any resource of the same type.
+    pub fn insert<T: Any + Send + Sync>(&self, resource: T) -> Option<Arc<T>> {
+        let old = self
+            .map
+            .write()
+            .unwrap()
+            .insert(TypeId::of::<T>(), Arc::new(resource));
+        old.map(|r| r.downcast().expect("resources are keyed by their type"))
+    }
+
+    /// The resource of type `T`, if there is one.
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        let r = self.map.read().unwrap().get(&TypeId::of::<T>())?.clone();
+        Some(r.downcast().expect("resources are keyed by their type"))
+    }
+
+    /// Returns `true` if there is a resource of type `T`.
+    pub fn contains<T: Any + Send + Sync>(&self) -> bool {
+        self.map.read().unwrap().contains_key(&TypeId::of::<T>())
+    }
+
+    /// Remove the resource of type `T`, if there is one.
+    ///
+    /// Services that already looked up the resource keep their handle to it.
+    pub fn remove<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        let old = self.map.write().unwrap().remove(&TypeId::of::<T>())?;
+        Some(old.downcast().expect("resources are keyed by their type"))
+    }
+}
+
+impl fmt::Debug for Resources {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Resources")
+            .field("len", &self.map.read().unwrap().len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn it_shares_resources_by_type() {
+        let resources = Resources::new();
+        assert!(resources.insert(String::from("db")).is_none());
+        let other = resources.clone();
+        assert_eq!(other.insert(String::from("db2")).as_deref().unwrap(), "db");
+        assert_eq!(*resources.get::<String>().unwrap(), "db2");
+        assert!(resources.get::<u32>().is_none());
+        assert!(!resources.contains::<u32>());
+        assert_eq!(*other.remove::<String>().unwrap(), "db2");
+        assert!(!resources.contains::<String>());
+    }
+}
@@ -0,0 +1,195 @@
+use super::{Cancellable, LoopState, Registry};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// A service that rotates a log file once it grows too large or too old.
+///
+/// Rotating renames `app.log` to `app.log.1`, `app.log.1` to `app.log.2`, and so on, and removes
+/// the oldest file beyond the number that should be [kept](LogRotator::keep). Whatever writes to
+/// the log then has to reopen it; services that do so in [`Cancellable::reload`] can be
+/// [notified](LogRotator::notify) through a [`Registry`]. Reloading the rotator itself forces a
+/// rotation, much like sending `SIGHUP` to `logrotate`.
+///
+/// Spawn the rotator with a [throttle](crate::ServiceBuilder::throttle) to decide how often it
+/// checks the log:
+///
+/// ```no_run
+/// # use minion::*;
+/// # use std::time::Duration;
+/// let registry = Registry::new();
+/// let h = LogRotator::new("/var/log/app.log")
+///     .max_size(64 << 20)
+///     .max_age(Duration::from_secs(24 * 60 * 60))
+///     .keep(7)
+///     .notify(&registry, "writer")
+///     .builder()
+///     .name("log-rotator")
+///     .register(&registry)
+///     .throttle(Duration::from_secs(60))
+///     .spawn();
+/// # h.cancel();
+/// ```
+pub struct LogRotator {
+    path: PathBuf,
+    max_size: Option<u64>,
+    max_age: Option<Duration>,
+    keep: usize,
+    notify: Vec<(Registry, String)>,
+    rotated: Instant,
+    forced: bool,
+}
+
+impl LogRotator {
+    /// Create a rotator for the log file at `path`.
+    ///
+    /// By default the log is never rotated unless the rotator is reloaded, and five old logs are
+    /// kept.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        LogRotator {
+            path: path.into(),
+            max_size: None,
+            max_age: None,
+            keep: 5,
+            notify: Vec::new(),
+            rotated: Instant::now(),
+            forced: false,
+        }
+    }
+
+    /// Rotate the log once it is at least `bytes` large.
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Rotate the log once `age` has passed since it was last rotated (or since the rotator
+    /// started).
+    pub fn max_age(mut self, age: Duration) -> Self {
+        self.max_age = Some(age);
+        self
+    }
+
+    /// Keep `n` rotated logs around. Older ones are removed.
+    pub fn keep(mut self, n: usize) -> Self {
+        self.keep = n;
+        self
+    }
+
+    /// [Reload](Registry::reload) the service registered in `registry` under `name` after every
+    /// rotation, so that it can reopen the log.
+    pub fn notify(mut self, registry: &Registry, name: &str) -> Self {
+        self.notify.push((registry.clone(), name.to_string()));
+        self
+    }
+
+    fn numbered(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        path.into()
+    }
+
+    fn due(&self) -> io::Result<bool> {
+        if self.forced {
+            return Ok(true);
+        }
+        if let Some(max) = self.max_age {
+            if self.rotated.elapsed() >= max {
+                return Ok(true);
+            }
+        }
+        if let Some(max) = self.max_size {
+            match fs::metadata(&self.path) {
+                Ok(meta) => return Ok(meta.len() >= max),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(false)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.forced = false;
+        self.rotated = Instant::now();
+        if !self.path.exists() {
+            return Ok(());
+        }
+
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            remove_if_exists(&self.numbered(self.keep))?;
+            for n in (1..self.keep).rev() {
+                let from = self.numbered(n);
+                if from.exists() {
+                    fs::rename(from, self.numbered(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.numbered(1))?;
+        }
+
+        for (registry, name) in &self.notify {
+            registry.reload(name);
+        }
+        Ok(())
+    }
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        r => r,
+    }
+}
+
+impl Cancellable for LogRotator {
+    type Error = io::Error;
+
+    fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+        if !self.due()? {
+            return Ok(LoopState::Idle);
+        }
+        self.rotate()?;
+        Ok(LoopState::Continue)
+    }
+
+    fn reload(&mut self) -> Result<(), Self::Error> {
+        self.forced = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use std::fs;
+
+    #[test]
+    fn it_rotates_by_size() {
+        let dir = std::env::temp_dir().join(format!("minion-rotate-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("app.log");
+
+        let mut rotator = LogRotator::new(&log).max_size(4).keep(2);
+        for content in &["1111", "22", "2222", "3333", "4444"] {
+            fs::write(&log, content).unwrap();
+            rotator.for_each().unwrap();
+        }
+
+        // "22" was too small to rotate, and only the two newest logs are kept
+        assert!(!log.exists());
+        assert_eq!(fs::read_to_string(dir.join("app.log.1")).unwrap(), "4444");
+        assert_eq!(fs::read_to_string(dir.join("app.log.2")).unwrap(), "3333");
+        assert!(!dir.join("app.log.3").exists());
+
+        // reloading forces a rotation regardless of size
+        fs::write(&log, "5").unwrap();
+        rotator.reload().unwrap();
+        assert!(matches!(rotator.for_each().unwrap(), LoopState::Continue));
+        assert_eq!(fs::read_to_string(dir.join("app.log.1")).unwrap(), "5");
+        assert!(matches!(rotator.for_each().unwrap(), LoopState::Idle));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,184 @@
+use super::{Aggregation, Cancellable, GroupHandle, MemberStatus, ServiceBuilder};
+use std::any::Any;
+use std::fmt;
+
+/// An error returned by one of the services in a [`ServiceGroup`], whatever its type.
+///
+/// The original error can be recovered with [`ServiceError::downcast`] if its type is known, and
+/// is otherwise shown using its [`Debug`](fmt::Debug) implementation.
+pub struct ServiceError {
+    message: String,
+    error: Box<dyn Any + Send>,
+}
+
+impl ServiceError {
+    fn new<E: fmt::Debug + Send + 'static>(error: E) -> Self {
+        ServiceError {
+            message: format!("{:?}", error),
+            error: Box::new(error),
+        }
+    }
+
+    /// The original error, if it is an `E`.
+    pub fn downcast_ref<E: 'static>(&self) -> Option<&E> {
+        self.error.downcast_ref()
+    }
+
+    /// Take out the original error, if it is an `E`.
+    pub fn downcast<E: 'static>(self) -> Result<E, Self> {
+        match self.error.downcast() {
+            Ok(e) => Ok(*e),
+            Err(error) => Err(ServiceError {
+                message: self.message,
+                error,
+            }),
+        }
+    }
+}
+
+impl fmt::Debug for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ServiceError {}
+
+/// A collection of named service loops with different error types that are managed together.
+///
+/// This is a [`GroupHandle`] for services that do not share an error type: each service's errors
+/// are wrapped in a [`ServiceError`] as it is added.
+///
+/// ```
+/// # use minion::*;
+/// # struct Poller;
+/// # impl Cancellable for Poller {
+/// #     type Error = std::io::Error;
+/// #     fn for_each(&mut self) -> Result<LoopState, Self::Error> { Ok(LoopState::Continue) }
+/// # }
+/// # struct Flusher;
+/// # impl Cancellable for Flusher {
+/// #     type Error = String;
+/// #     fn for_each(&mut self) -> Result<LoopState, Self::Error> { Ok(LoopState::Continue) }
+/// # }
+/// let mut services = ServiceGroup::new();
+/// services.add("poller", Poller);
+/// services.add("flusher", Flusher);
+///
+/// services.cancel_all();
+/// for (name, status) in services.wait_all() {
+///     assert!(status.is_success(), "{} failed", name);
+/// }
+/// ```
+#[derive(Default)]
+pub struct ServiceGroup {
+    group: GroupHandle<ServiceError>,
+}
+
+impl ServiceGroup {
+    /// Create a new, empty group.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spawn `service` and add it to the group under the given name.
+    pub fn add<N, S>(&mut self, name: N, service: S)
+    where
+        N: Into<String>,
+        S: Cancellable + Send + 'static,
+        S::Error: fmt::Debug + Send + 'static,
+    {
+        self.add_builder(name, service.builder());
+    }
+
+    /// Spawn the service configured by `builder` and add it to the group under the given name.
+    ///
+    /// The name replaces any name already given to `builder`.
+    pub fn add_builder<N, S>(&mut self, name: N, builder: ServiceBuilder<S>)
+    where
+        N: Into<String>,
+        S: Cancellable + Send + 'static,
+        S::Error: fmt::Debug + Send + 'static,
+    {
+        let name = name.into();
+        let handle = builder.name(name.clone()).spawn_map_err(ServiceError::new);
+        self.group.push(name, handle);
+    }
+
+    /// The number of service loops in the group.
+    pub fn len(&self) -> usize {
+        self.group.len()
+    }
+
+    /// Returns `true` if the group has no members.
+    pub fn is_empty(&self) -> bool {
+        self.group.is_empty()
+    }
+
+    /// The names of the group's members, in the order they were added.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.group.names()
+    }
+
+    /// Cancel every service loop in the group.
+    pub fn cancel_all(&self) {
+        self.group.cancel_all();
+    }
+
+    /// Block the current thread until every member has exited, and return each member's name
+    /// and exit status, in the order the members were added.
+    ///
+    /// See [`GroupHandle::wait_all`].
+    pub fn wait_all(self) -> Vec<MemberStatus<ServiceError>> {
+        match self.group.wait_all(Aggregation::JoinAll) {
+            Ok(statuses) => statuses,
+            Err(_) => unreachable!("joining all members never fails"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    struct Failing<E>(Option<E>);
+    impl<E> Cancellable for Failing<E> {
+        type Error = E;
+        fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+            match self.0.take() {
+                Some(e) => Err(e),
+                None => Ok(LoopState::Break),
+            }
+        }
+    }
+
+    #[test]
+    fn it_mixes_error_types() {
+        let mut services = ServiceGroup::new();
+        services.add("io", Failing(Some(std::io::Error::other("disk"))));
+        services.add("str", Failing(Some("oops")));
+        services.add("fine", Failing::<()>(None));
+        assert_eq!(services.names().collect::<Vec<_>>(), ["io", "str", "fine"]);
+
+        let mut statuses = services.wait_all().into_iter();
+        match statuses.next().unwrap() {
+            (name, ExitStatus::Failed(e)) => {
+                assert_eq!(name, "io");
+                assert!(e.downcast_ref::<&str>().is_none());
+                assert_eq!(e.downcast::<std::io::Error>().unwrap().to_string(), "disk");
+            }
+            r => panic!("unexpected status {:?}", r),
+        }
+        match statuses.next().unwrap() {
+            (_, ExitStatus::Failed(e)) => assert_eq!(e.to_string(), "\"oops\""),
+            r => panic!("unexpected status {:?}", r),
+        }
+        assert!(statuses.next().unwrap().1.is_success());
+    }
+}
@@ -0,0 +1,167 @@
+use super::Canceller;
+use std::fs::OpenOptions;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::ptr::{self, NonNull};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How often a loop blocked on a shared [`Canceller`] checks whether another process has
+/// cancelled it.
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A cancellation flag that lives in memory shared between processes.
+///
+/// Turn it into a [`Canceller`] with [`Canceller::shared`], and give each process that should be
+/// able to observe or trigger the cancellation its own flag mapped to the same memory. That memory
+/// is either inherited across `fork` (for [`SharedFlag::anonymous`]) or backed by a file that all
+/// the processes open (for [`SharedFlag::open`]).
+///
+/// ```no_run
+/// # use minion::*;
+/// # struct Service;
+/// # impl Cancellable for Service {
+/// #     type Error = ();
+/// #     fn for_each(&mut self) -> Result<LoopState, ()> { Ok(LoopState::Continue) }
+/// # }
+/// // in the child
+/// let exit = Canceller::shared(SharedFlag::open("/dev/shm/my-service")?);
+/// let h = Service.builder().canceller(exit).spawn();
+///
+/// // in the parent
+/// let exit = Canceller::shared(SharedFlag::open("/dev/shm/my-service")?);
+/// exit.cancel();
+/// # Ok::<_, std::io::Error>(())
+/// ```
+pub struct SharedFlag {
+    flag: NonNull<AtomicU32>,
+}
+
+// the flag is only ever accessed atomically
+unsafe impl Send for SharedFlag {}
+unsafe impl Sync for SharedFlag {}
+
+const LEN: usize = std::mem::size_of::<AtomicU32>();
+
+impl SharedFlag {
+    /// Map a new, unset flag into anonymous shared memory.
+    ///
+    /// The mapping is inherited by child processes created with `fork`, which lets a parent
+    /// cancel loops that its forked children run.
+    pub fn anonymous() -> io::Result<Self> {
+        Self::map(-1, libc::MAP_ANONYMOUS)
+    }
+
+    /// Map the flag stored in the file at `path`, creating the file (with the flag unset) if it
+    /// does not yet exist.
+    ///
+    /// Every process that opens the same file shares the same flag. On Linux, placing the file
+    /// under `/dev/shm` keeps it in memory.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if file.metadata()?.len() < LEN as u64 {
+            file.set_len(LEN as u64)?;
+        }
+        // the mapping stays valid after the file is closed
+        Self::map(file.as_raw_fd(), 0)
+    }
+
+    fn map(fd: libc::c_int, flags: libc::c_int) -> io::Result<Self> {
+        let addr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                LEN,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | flags,
+                fd,
+                0,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(SharedFlag {
+            flag: NonNull::new(addr as *mut AtomicU32).expect("mmap returned null"),
+        })
+    }
+
+    fn flag(&self) -> &AtomicU32 {
+        unsafe { self.flag.as_ref() }
+    }
+
+    /// Set the flag, in every process that shares it.
+    pub fn set(&self) {
+        self.flag().store(1, Ordering::SeqCst);
+    }
+
+    /// Returns `true` if any process has set the flag.
+    pub fn is_set(&self) -> bool {
+        self.flag().load(Ordering::SeqCst) != 0
+    }
+}
+
+impl Drop for SharedFlag {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.flag.as_ptr() as *mut libc::c_void, LEN) };
+    }
+}
+
+impl Canceller {
+    /// Create a canceller that is also cancelled whenever `flag` is set, including by other
+    /// processes.
+    ///
+    /// Cancelling the returned canceller sets `flag` too, so every process sharing the flag can
+    /// cancel the loops of every other process. A service loop blocked on the canceller (while
+    /// throttled, for example) notices a cancellation from another process within a few
+    /// milliseconds. Callbacks registered with [`Canceller::on_cancel`] only run in the process
+    /// that called [`Canceller::cancel`].
+    pub fn shared(flag: SharedFlag) -> Self {
+        let mut canceller = Canceller::new();
+        Arc::get_mut(&mut canceller.signal)
+            .expect("new canceller is not yet shared")
+            .shared = Some(flag);
+        canceller
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use std::time::{Duration, Instant};
+
+    struct Idle;
+    impl Cancellable for Idle {
+        type Error = ();
+        fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+            Ok(LoopState::Continue)
+        }
+    }
+
+    #[test]
+    fn it_cancels_through_the_shared_flag() {
+        let path = std::env::temp_dir().join(format!("minion-shm-{}", std::process::id()));
+        // two separate mappings of the same file, as two processes would have
+        let parent = Canceller::shared(SharedFlag::open(&path).unwrap());
+        let child = Canceller::shared(SharedFlag::open(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        let h = Idle
+            .builder()
+            .canceller(child)
+            .throttle(Duration::from_secs(60))
+            .spawn();
+        assert!(h.wait_ready(Duration::from_secs(1)));
+
+        let start = Instant::now();
+        parent.cancel();
+        h.wait().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(60));
+    }
+}
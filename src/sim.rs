@@ -0,0 +1,355 @@
+//! Deterministic, virtual-time execution of several services at once.
+//!
+//! A [`Simulation`] runs all of its services on the calling thread, one iteration at a time, and
+//! never actually sleeps. Instead, it keeps a virtual clock that jumps straight to the next point
+//! in time at which some service has work to do. Throttling, deadlines, and scheduled
+//! cancellations are all evaluated against that virtual clock, so tests of how several services
+//! interact complete in milliseconds and behave identically on every run.
+//!
+//! Services that want to wait (rather than spin) should do so through the simulation's
+//! [`Clock`]:
+//!
+//! ```
+//! # use minion::*;
+//! use minion::sim::{Clock, Simulation};
+//! use std::time::Duration;
+//!
+//! struct Poller(Clock, usize);
+//! impl Cancellable for Poller {
+//!     type Error = ();
+//!     fn for_each(&mut self) -> Result<LoopState, ()> {
+//!         self.1 += 1;
+//!         self.0.sleep(Duration::from_secs(10));
+//!         Ok(LoopState::Continue)
+//!     }
+//! }
+//!
+//! let mut sim = Simulation::new();
+//! let clock = sim.clock();
+//! sim.add("poller", Poller(clock.clone(), 0));
+//! sim.cancel_at("poller", Duration::from_secs(60));
+//! for (name, result) in sim.run() {
+//!     assert_eq!(name, "poller");
+//!     result.unwrap();
+//! }
+//! // a minute of polling took no real time at all
+//! assert_eq!(clock.now(), Duration::from_secs(60));
+//! ```
+
+use super::{Cancellable, Canceller, Context, ErrorDisposition, ExitReason, LoopState};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A virtual clock shared between a [`Simulation`] and the services it runs.
+#[derive(Clone, Default)]
+pub struct Clock {
+    state: Arc<Mutex<ClockState>>,
+}
+
+#[derive(Default)]
+struct ClockState {
+    now: Duration,
+    slept: Duration,
+}
+
+impl Clock {
+    /// The current virtual time, measured from the start of the simulation.
+    pub fn now(&self) -> Duration {
+        self.state.lock().unwrap().now
+    }
+
+    /// Sleep for `dur` of virtual time.
+    ///
+    /// This returns immediately. The simulation instead delays the calling service's next
+    /// iteration until `dur` has passed on the virtual clock.
+    pub fn sleep(&self, dur: Duration) {
+        self.state.lock().unwrap().slept += dur;
+    }
+
+    fn set(&self, now: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.now = now;
+        state.slept = Duration::from_secs(0);
+    }
+
+    fn slept(&self) -> Duration {
+        self.state.lock().unwrap().slept
+    }
+}
+
+/// Simulation-specific settings for a single service.
+///
+/// Returned by [`Simulation::add`].
+pub struct SimService<E> {
+    name: String,
+    service: Box<dyn Cancellable<Error = E>>,
+    canceller: Canceller,
+    ctx: Context,
+    throttle: Option<Duration>,
+    deadline: Option<Duration>,
+    cancel_at: Option<Duration>,
+    ready_at: Duration,
+    started: bool,
+    result: Option<Result<(), E>>,
+}
+
+impl<E> SimService<E> {
+    /// Start at most one iteration of this service every `interval` of virtual time.
+    ///
+    /// See [`ServiceBuilder::throttle`](crate::ServiceBuilder::throttle).
+    pub fn throttle(&mut self, interval: Duration) -> &mut Self {
+        self.throttle = Some(interval);
+        self
+    }
+
+    /// Stop accepting new work once the virtual clock reaches `at`.
+    ///
+    /// See [`SpawnOptions::deadline`](crate::SpawnOptions::deadline).
+    pub fn deadline(&mut self, at: Duration) -> &mut Self {
+        self.deadline = Some(at);
+        self
+    }
+
+    /// Delay this service's first iteration until the virtual clock reaches `at`.
+    pub fn start_at(&mut self, at: Duration) -> &mut Self {
+        self.ready_at = at;
+        self
+    }
+
+    fn exit(&mut self, result: Result<(), E>, reason: ExitReason) {
+        if self.started {
+            self.service.on_exit(reason);
+        }
+        self.result = Some(result);
+    }
+}
+
+/// A set of services executed deterministically against a virtual [`Clock`].
+///
+/// See the [module-level documentation](self) for details.
+pub struct Simulation<E> {
+    clock: Clock,
+    services: Vec<SimService<E>>,
+}
+
+impl<E> Default for Simulation<E> {
+    fn default() -> Self {
+        Simulation {
+            clock: Clock::default(),
+            services: Vec::new(),
+        }
+    }
+}
+
+impl<E> Simulation<E> {
+    /// Create a new, empty simulation whose virtual clock starts at zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get a handle to the simulation's virtual clock.
+    pub fn clock(&self) -> Clock {
+        self.clock.clone()
+    }
+
+    /// Add a service to the simulation under the given name.
+    ///
+    /// Services are iterated in the order they were added whenever several are ready at the same
+    /// virtual time.
+    pub fn add<S>(&mut self, name: &str, service: S) -> &mut SimService<E>
+    where
+        S: Cancellable<Error = E> + 'static,
+    {
+        let canceller = Canceller::new();
+        let mut ctx = Context::new();
+        ctx.set_canceller(canceller.clone());
+        self.services.push(SimService {
+            name: name.to_string(),
+            service: Box::new(service),
+            canceller,
+            ctx,
+            throttle: None,
+            deadline: None,
+            cancel_at: None,
+            ready_at: Duration::from_secs(0),
+            started: false,
+            result: None,
+        });
+        self.services.last_mut().unwrap()
+    }
+
+    /// Get a [`Canceller`] for the named service.
+    ///
+    /// Cancelling it (for example from within another simulated service) takes effect before the
+    /// named service's next iteration, just like for a spawned service.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no service with the given name has been added.
+    pub fn canceller(&self, name: &str) -> Canceller {
+        self.service(name).canceller.clone()
+    }
+
+    /// Cancel the named service once the virtual clock reaches `at`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no service with the given name has been added.
+    pub fn cancel_at(&mut self, name: &str, at: Duration) {
+        let s = self.services.iter_mut().find(|s| s.name == name);
+        s.expect("no such service").cancel_at = Some(at);
+    }
+
+    fn service(&self, name: &str) -> &SimService<E> {
+        let s = self.services.iter().find(|s| s.name == name);
+        s.expect("no such service")
+    }
+
+    /// Run the simulation until every service has exited.
+    ///
+    /// Returns each service's name and result, in the order the services were added.
+    pub fn run(self) -> Vec<(String, Result<(), E>)> {
+        self.run_until(None)
+    }
+
+    /// Run the simulation until every service has exited, or until the virtual clock would pass
+    /// `limit`. Any services still running at that point are treated as cancelled.
+    ///
+    /// Returns each service's name and result, in the order the services were added.
+    pub fn run_for(self, limit: Duration) -> Vec<(String, Result<(), E>)> {
+        self.run_until(Some(limit))
+    }
+
+    fn run_until(mut self, limit: Option<Duration>) -> Vec<(String, Result<(), E>)> {
+        loop {
+            let next = self
+                .services
+                .iter()
+                .enumerate()
+                .filter(|&(_, s)| s.result.is_none())
+                .min_by_key(|&(i, s)| (s.ready_at, i))
+                .map(|(i, _)| i);
+            let i = match next {
+                Some(i) => i,
+                None => break,
+            };
+
+            let s = &mut self.services[i];
+            let now = s.ready_at;
+            if limit.map(|l| now > l).unwrap_or(false) {
+                break;
+            }
+            self.clock.set(now);
+
+            if s.cancel_at.map(|t| now >= t).unwrap_or(false) {
+                s.canceller.cancel();
+            }
+            if !s.canceller.keep_running() || s.deadline.map(|d| now >= d).unwrap_or(false) {
+                s.exit(Ok(()), ExitReason::Cancelled);
+                continue;
+            }
+            if !s.started {
+                if let Err(e) = s.service.on_start() {
+                    s.result = Some(Err(e));
+                    continue;
+                }
+                s.started = true;
+            }
+
+            s.ctx.start_iteration();
+            match s.service.for_each_ctx(&mut s.ctx) {
+                Ok(LoopState::Continue) | Ok(LoopState::Idle) => {}
+                Ok(LoopState::ContinueAfter(dur)) => self.clock.sleep(dur),
+                Ok(LoopState::Break) => s.exit(Ok(()), ExitReason::Finished),
+                Ok(LoopState::Reset) => {
+                    if let Err(e) = s.service.reset() {
+                        s.exit(Err(e), ExitReason::Failed);
+                    }
+                }
+                Err(e) => match s.service.on_error(e) {
+                    ErrorDisposition::Continue => {}
+                    ErrorDisposition::RetryAfter(dur) => self.clock.sleep(dur),
+                    ErrorDisposition::Abort(e) => s.exit(Err(e), ExitReason::Failed),
+                },
+            }
+
+            let mut ready_at = now + self.clock.slept();
+            if let Some(throttle) = s.throttle {
+                if now + throttle > ready_at {
+                    ready_at = now + throttle;
+                }
+            }
+            // a scheduled cancellation or deadline cuts any waiting short
+            for &t in s.cancel_at.iter().chain(s.deadline.iter()) {
+                if t > now && t < ready_at {
+                    ready_at = t;
+                }
+            }
+            s.ready_at = ready_at;
+        }
+
+        if let Some(limit) = limit {
+            self.clock.set(limit);
+        }
+        for s in &mut self.services {
+            if s.result.is_none() {
+                s.exit(Ok(()), ExitReason::Cancelled);
+            }
+        }
+        self.services
+            .into_iter()
+            .map(|s| (s.name, s.result.unwrap_or(Ok(()))))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Ticker(Clock, Vec<Duration>);
+
+    impl Cancellable for Ticker {
+        type Error = Vec<Duration>;
+        fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+            self.1.push(self.0.now());
+            if self.1.len() == 3 {
+                return Err(self.1.clone());
+            }
+            Ok(LoopState::Continue)
+        }
+    }
+
+    #[test]
+    fn it_interleaves_in_virtual_time() {
+        let mut sim = Simulation::new();
+        let clock = sim.clock();
+        sim.add("fast", Ticker(clock.clone(), Vec::new()))
+            .throttle(Duration::from_secs(1));
+        sim.add("slow", Ticker(clock.clone(), Vec::new()))
+            .throttle(Duration::from_secs(5))
+            .deadline(Duration::from_secs(7));
+
+        let results = sim.run();
+        assert_eq!(results[0].0, "fast");
+        assert_eq!(
+            results[0].1,
+            Err(vec![
+                Duration::from_secs(0),
+                Duration::from_secs(1),
+                Duration::from_secs(2)
+            ])
+        );
+        assert_eq!(results[1].1, Ok(()));
+        assert_eq!(clock.now(), Duration::from_secs(7));
+    }
+
+    #[test]
+    fn it_stops_at_the_limit() {
+        let mut sim = Simulation::new();
+        sim.add("spin", Ticker(sim.clock(), Vec::new()))
+            .start_at(Duration::from_secs(10));
+        let results = sim.run_for(Duration::from_secs(5));
+        assert_eq!(results[0].1, Ok(()));
+    }
+}
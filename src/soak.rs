@@ -0,0 +1,215 @@
+//! Randomized soak testing of how services respond to cancellation.
+//!
+//! A [`Soak`] repeatedly spawns a swarm of services on real threads, cancels each one at a random
+//! point in its life, and checks that every one of them exits, and has its thread joined, within a
+//! bound of being cancelled. It is meant for exercising a service's cancellation policy (its
+//! [`drain`](crate::Cancellable::drain), [`pre_cancel`](crate::Cancellable::pre_cancel), and
+//! [`retry`](crate::ServiceBuilder::retry) behavior, or just how long its iterations run) under
+//! many different interleavings:
+//!
+//! ```
+//! # use minion::*;
+//! use minion::soak::Soak;
+//! use std::time::Duration;
+//!
+//! struct Worker;
+//! impl Cancellable for Worker {
+//!     type Error = ();
+//!     fn for_each(&mut self) -> Result<LoopState, ()> {
+//!         std::thread::sleep(Duration::from_millis(1));
+//!         Ok(LoopState::Continue)
+//!     }
+//! }
+//!
+//! let report = Soak::new(|_| Worker.builder().throttle(Duration::from_millis(2)))
+//!     .services(8)
+//!     .rounds(3)
+//!     .join_within(Duration::from_secs(1))
+//!     .run();
+//! assert_eq!(report.spawned, 24);
+//! assert_eq!(report.failed + report.panicked, 0);
+//! ```
+//!
+//! Unlike a [`Simulation`](crate::sim::Simulation), a soak test runs in real time and is not
+//! deterministic; its value lies in trying many orderings rather than in reproducing one.
+
+use super::{Cancellable, ExitStatus, Jitter, ServiceBuilder};
+use std::time::{Duration, Instant};
+
+/// A soak test of the services produced by a factory.
+///
+/// See the [module-level documentation](self) for details.
+pub struct Soak<F> {
+    factory: F,
+    services: usize,
+    rounds: usize,
+    max_cancel_delay: Duration,
+    join_within: Duration,
+}
+
+/// What happened over the course of a [`Soak`] run.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct SoakReport {
+    /// The number of services spawned across all rounds.
+    pub spawned: usize,
+    /// How many of them returned an error.
+    pub failed: usize,
+    /// How many of them panicked.
+    pub panicked: usize,
+    /// The longest any service took to exit after it was cancelled.
+    pub slowest_exit: Duration,
+}
+
+impl<F, S> Soak<F>
+where
+    F: FnMut(usize) -> ServiceBuilder<S>,
+    S: Cancellable + Send + 'static,
+    S::Error: Send + 'static,
+{
+    /// Soak-test the services built by `factory`, which is given each service's index within its
+    /// round.
+    ///
+    /// By default, each of ten rounds spawns sixteen services, cancels each one within 10ms of
+    /// spawning it, and requires each one to exit within a second of being cancelled.
+    pub fn new(factory: F) -> Self {
+        Soak {
+            factory,
+            services: 16,
+            rounds: 10,
+            max_cancel_delay: Duration::from_millis(10),
+            join_within: Duration::from_secs(1),
+        }
+    }
+
+    /// Spawn `n` services in every round.
+    pub fn services(mut self, n: usize) -> Self {
+        self.services = n;
+        self
+    }
+
+    /// Run `n` rounds.
+    pub fn rounds(mut self, n: usize) -> Self {
+        self.rounds = n;
+        self
+    }
+
+    /// Cancel each service at a uniformly random point at most `delay` after it was spawned.
+    pub fn max_cancel_delay(mut self, delay: Duration) -> Self {
+        self.max_cancel_delay = delay;
+        self
+    }
+
+    /// Require every service to exit within `bound` of being cancelled.
+    pub fn join_within(mut self, bound: Duration) -> Self {
+        self.join_within = bound;
+        self
+    }
+
+    /// Run the soak test, and report on how the services exited.
+    ///
+    /// Errors and panics in the services are counted rather than propagated, since those may be
+    /// how a service is expected to respond to being cancelled.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a service does not exit within the [bound](Soak::join_within) of being
+    /// cancelled.
+    pub fn run(mut self) -> SoakReport {
+        let mut report = SoakReport::default();
+        for round in 0..self.rounds {
+            let start = Instant::now();
+            let mut handles: Vec<_> = (0..self.services)
+                .map(|i| {
+                    let cancel_at = start + Jitter::Full.apply(self.max_cancel_delay);
+                    (i, cancel_at, (self.factory)(i).spawn())
+                })
+                .collect();
+            report.spawned += handles.len();
+
+            handles.sort_by_key(|&(_, cancel_at, _)| cancel_at);
+            let mut cancelled = Vec::with_capacity(handles.len());
+            for (i, cancel_at, h) in handles {
+                let now = Instant::now();
+                if cancel_at > now {
+                    std::thread::sleep(cancel_at - now);
+                }
+                h.cancel();
+                cancelled.push((i, Instant::now(), h));
+            }
+
+            for (i, cancelled_at, h) in cancelled {
+                let bound = cancelled_at + self.join_within;
+                let exited = h
+                    .status
+                    .wait_exit(bound.saturating_duration_since(Instant::now()));
+                assert!(
+                    exited,
+                    "service {} in round {} did not exit within {:?} of being cancelled",
+                    i, round, self.join_within
+                );
+                report.slowest_exit = report.slowest_exit.max(cancelled_at.elapsed());
+                match h.join() {
+                    ExitStatus::Exited(()) => {}
+                    ExitStatus::Failed(_) => report.failed += 1,
+                    ExitStatus::Panicked(_) => report.panicked += 1,
+                }
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use super::Soak;
+    use std::time::Duration;
+
+    struct Stubborn(Duration);
+    impl Cancellable for Stubborn {
+        type Error = ();
+        fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+            std::thread::sleep(self.0);
+            Ok(LoopState::Continue)
+        }
+    }
+
+    #[test]
+    fn it_counts_exits() {
+        let report = Soak::new(|i| Stubborn(Duration::from_millis(i as u64 % 3)).builder())
+            .services(4)
+            .rounds(2)
+            .run();
+        assert_eq!(report.spawned, 8);
+        assert_eq!(report.failed, 0);
+        assert!(report.slowest_exit < Duration::from_secs(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "did not exit")]
+    fn it_catches_slow_exits() {
+        // asks for more time to wind down than the soak allows
+        struct Lingering;
+        impl Cancellable for Lingering {
+            type Error = ();
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                std::thread::sleep(Duration::from_millis(1));
+                Ok(LoopState::Continue)
+            }
+            fn drain(&mut self, _: Duration) -> Result<LoopState, Self::Error> {
+                self.for_each()
+            }
+            fn pre_cancel(&self) -> Option<Box<dyn FnOnce() -> Option<Duration> + Send>> {
+                Some(Box::new(|| Some(Duration::from_millis(200))))
+            }
+        }
+
+        Soak::new(|_| Lingering.builder())
+            .services(1)
+            .rounds(1)
+            .max_cancel_delay(Duration::from_millis(0))
+            .join_within(Duration::from_millis(10))
+            .run();
+    }
+}
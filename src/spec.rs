@@ -0,0 +1,204 @@
+use super::RestartPolicy;
+use serde_json::{Map, Value};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Operational settings for a service, as read from a configuration file.
+///
+/// A spec is a JSON object where every field is optional:
+///
+/// ```
+/// # use minion::*;
+/// # use std::time::Duration;
+/// let spec: ServiceSpec = r#"{
+///     "name": "poller",
+///     "restart": "on_error",
+///     "throttle_ms": 250,
+///     "deadline_ms": 60000,
+///     "start_after_ms": 1000,
+///     "stack_size": 65536
+/// }"#.parse().unwrap();
+/// assert_eq!(spec.restart, Some(RestartPolicy::OnError));
+/// assert_eq!(spec.throttle, Some(Duration::from_millis(250)));
+/// ```
+///
+/// Unknown fields are rejected, so that a misspelled setting does not silently go unapplied.
+/// Apply a spec with [`ServiceBuilder::spec`](crate::ServiceBuilder::spec), or have a
+/// [`Supervisor`](crate::Supervisor) apply it to every instance of a service with
+/// [`Supervisor::supervise_spec`](crate::Supervisor::supervise_spec).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServiceSpec {
+    /// The name of the service (`"name"`).
+    pub name: Option<String>,
+    /// When to restart the service if it is supervised (`"restart"`: one of `"never"`,
+    /// `"on_error"`, `"on_panic"`, or `"always"`).
+    pub restart: Option<RestartPolicy>,
+    /// The minimum time between iterations (`"throttle_ms"`).
+    pub throttle: Option<Duration>,
+    /// How long after spawning the service stops accepting new work (`"deadline_ms"`).
+    pub deadline: Option<Duration>,
+    /// How long to wait before the first iteration (`"start_after_ms"`).
+    pub start_after: Option<Duration>,
+    /// The stack size of the service thread, in bytes (`"stack_size"`).
+    pub stack_size: Option<usize>,
+}
+
+/// The error returned when a [`ServiceSpec`] cannot be read.
+#[derive(Debug)]
+pub enum SpecError {
+    /// The spec was not valid JSON.
+    Json(serde_json::Error),
+    /// The spec was not a JSON object.
+    NotAnObject,
+    /// The spec contained a field that is not a setting.
+    UnknownField(String),
+    /// A setting did not have the expected type of value.
+    Invalid {
+        /// The setting's field.
+        field: String,
+        /// What kind of value the setting expects.
+        expected: &'static str,
+    },
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpecError::Json(e) => write!(f, "invalid service spec: {}", e),
+            SpecError::NotAnObject => write!(f, "service spec must be an object"),
+            SpecError::UnknownField(field) => write!(f, "unknown service spec field {}", field),
+            SpecError::Invalid { field, expected } => {
+                write!(f, "service spec field {} must be {}", field, expected)
+            }
+        }
+    }
+}
+
+impl Error for SpecError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SpecError::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl ServiceSpec {
+    /// Read a spec from an already parsed JSON value.
+    pub fn from_json(value: &Value) -> Result<Self, SpecError> {
+        let fields = value.as_object().ok_or(SpecError::NotAnObject)?;
+        let mut spec = ServiceSpec::default();
+        for (field, value) in fields {
+            let invalid = |expected| SpecError::Invalid {
+                field: field.clone(),
+                expected,
+            };
+            let millis = || {
+                value
+                    .as_u64()
+                    .map(Duration::from_millis)
+                    .ok_or_else(|| invalid("a number of milliseconds"))
+            };
+            match &**field {
+                "name" => {
+                    let name = value.as_str().ok_or_else(|| invalid("a string"))?;
+                    spec.name = Some(name.to_string());
+                }
+                "restart" => {
+                    spec.restart = Some(match value.as_str() {
+                        Some("never") => RestartPolicy::Never,
+                        Some("on_error") => RestartPolicy::OnError,
+                        Some("on_panic") => RestartPolicy::OnPanic,
+                        Some("always") => RestartPolicy::Always,
+                        _ => return Err(invalid("a restart policy")),
+                    });
+                }
+                "throttle_ms" => spec.throttle = Some(millis()?),
+                "deadline_ms" => spec.deadline = Some(millis()?),
+                "start_after_ms" => spec.start_after = Some(millis()?),
+                "stack_size" => {
+                    let size = value.as_u64().ok_or_else(|| invalid("a number of bytes"))?;
+                    spec.stack_size = Some(size as usize);
+                }
+                _ => return Err(SpecError::UnknownField(field.clone())),
+            }
+        }
+        Ok(spec)
+    }
+
+    /// Read every spec in a JSON object that maps service names to specs.
+    ///
+    /// Each spec is named after its key, unless it sets a name of its own.
+    pub fn from_json_map(value: &Value) -> Result<Vec<Self>, SpecError> {
+        let specs: &Map<String, Value> = value.as_object().ok_or(SpecError::NotAnObject)?;
+        specs
+            .iter()
+            .map(|(name, spec)| {
+                let mut spec = ServiceSpec::from_json(spec)?;
+                spec.name.get_or_insert_with(|| name.clone());
+                Ok(spec)
+            })
+            .collect()
+    }
+}
+
+impl FromStr for ServiceSpec {
+    type Err = SpecError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ServiceSpec::from_json(&serde_json::from_str(s).map_err(SpecError::Json)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use std::time::{Duration, Instant};
+
+    struct Ticker(usize);
+    impl Cancellable for Ticker {
+        type Error = ();
+        fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+            self.0 += 1;
+            if self.0 == 3 {
+                return Ok(LoopState::Break);
+            }
+            Ok(LoopState::Continue)
+        }
+    }
+
+    #[test]
+    fn it_reads_specs() {
+        let specs = serde_json::json!({
+            "a": { "restart": "always", "throttle_ms": 5 },
+            "b": { "name": "renamed" },
+        });
+        let mut specs = ServiceSpec::from_json_map(&specs).unwrap();
+        specs.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(specs[0].name.as_deref(), Some("a"));
+        assert_eq!(specs[0].restart, Some(RestartPolicy::Always));
+        assert_eq!(specs[1].name.as_deref(), Some("renamed"));
+
+        assert!(matches!(
+            r#"{"throtle_ms": 5}"#.parse::<ServiceSpec>(),
+            Err(SpecError::UnknownField(ref f)) if f == "throtle_ms"
+        ));
+        assert!(matches!(
+            r#"{"restart": "sometimes"}"#.parse::<ServiceSpec>(),
+            Err(SpecError::Invalid { .. })
+        ));
+    }
+
+    #[test]
+    fn specs_apply_to_the_builder() {
+        let spec: ServiceSpec = r#"{"name": "ticker", "throttle_ms": 20}"#.parse().unwrap();
+        let start = Instant::now();
+        let registry = Registry::new();
+        let h = Ticker(0).builder().spec(&spec).register(&registry).spawn();
+        assert!(registry.names().contains(&"ticker".to_string()));
+        h.wait().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(40));
+    }
+}
@@ -0,0 +1,163 @@
+use super::{
+    catch, propagate, Activity, Canceller, Executor, ExitStatus, Handle, Stats, Status, WaitError,
+};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The half of a [split](Handle::split) handle that waits for the service loop to exit.
+///
+/// Whoever owns the `Waiter` is responsible for collecting the loop's result, but cannot cancel
+/// or otherwise control the loop.
+pub struct Waiter<E, T = ()> {
+    executor: Box<dyn Executor<Result<T, E>>>,
+}
+
+/// The half of a [split](Handle::split) handle that controls the service loop and reports on its
+/// status.
+///
+/// Unlike [`Handle`], a `Controller` does not dereference to a [`Canceller`]; every way it can
+/// affect the loop is spelled out as a method. It can be cloned freely, and the loop is not
+/// affected when the last clone is dropped.
+#[derive(Clone)]
+pub struct Controller {
+    canceller: Canceller,
+    status: Arc<Status>,
+}
+
+impl<E, T> Handle<E, T> {
+    /// Split this handle into a part that waits for the loop, and a part that controls it.
+    ///
+    /// This is useful in larger applications where, say, a supervisor owns joining the loop
+    /// while an admin interface owns cancelling it, and each should only be able to do its
+    /// part.
+    ///
+    /// ```
+    /// # use minion::*;
+    /// # struct Service;
+    /// # impl Cancellable for Service {
+    /// #     type Error = ();
+    /// #     fn for_each(&mut self) -> Result<LoopState, ()> { Ok(LoopState::Continue) }
+    /// # }
+    /// let (waiter, controller) = Service.spawn().split();
+    /// std::thread::spawn(move || controller.cancel());
+    /// waiter.wait().unwrap();
+    /// ```
+    pub fn split(self) -> (Waiter<E, T>, Controller) {
+        (
+            Waiter {
+                executor: self.executor,
+            },
+            Controller {
+                canceller: self.canceller,
+                status: self.status,
+            },
+        )
+    }
+}
+
+impl<E, T> Waiter<E, T> {
+    /// Block the current thread waiting for the service loop to exit, and return its result.
+    ///
+    /// See [`Handle::wait`].
+    pub fn wait(self) -> Result<T, E> {
+        propagate(self.executor.join())
+    }
+
+    /// Block the current thread waiting for the service loop to exit, and return its result
+    /// without propagating any panic.
+    ///
+    /// See [`Handle::wait_catch`].
+    pub fn wait_catch(self) -> Result<T, WaitError<E>> {
+        catch(self.executor.join())
+    }
+
+    /// Block the current thread waiting for the service loop to exit, and return how it exited.
+    ///
+    /// See [`Handle::join`].
+    pub fn join(self) -> ExitStatus<E, T> {
+        ExitStatus::from(self.executor.join())
+    }
+}
+
+impl Controller {
+    /// Cancel the service loop. See [`Canceller::cancel`].
+    pub fn cancel(&self) -> bool {
+        self.canceller.cancel()
+    }
+
+    /// Ask the service loop to drain, and then cancel it once `grace` has passed. See
+    /// [`Canceller::drain`].
+    pub fn drain(&self, grace: Duration) {
+        self.canceller.drain(grace);
+    }
+
+    /// Returns `true` if the service loop has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.canceller.is_cancelled()
+    }
+
+    /// Get a [`Canceller`] for the service loop, for APIs that need one.
+    pub fn canceller(&self) -> Canceller {
+        self.canceller.clone()
+    }
+
+    /// Ask the service loop to [reload](crate::Cancellable::reload). See [`Handle::reload`].
+    pub fn reload(&self) {
+        self.status.request_reload();
+    }
+
+    /// Returns `true` if the service loop has started accepting work and has not yet exited.
+    pub fn is_ready(&self) -> bool {
+        self.status.is_ready()
+    }
+
+    /// Block until the service loop is ready, or until `timeout` elapses. See
+    /// [`Handle::wait_ready`].
+    pub fn wait_ready(&self, timeout: Duration) -> bool {
+        self.status.wait_ready(Some(timeout))
+    }
+
+    /// Returns `true` if the service loop has exited.
+    pub fn has_exited(&self) -> bool {
+        self.status.has_exited()
+    }
+
+    /// Whether the service loop's most recent iteration found work to do.
+    pub fn activity(&self) -> Activity {
+        self.status.counters.activity()
+    }
+
+    /// Get a snapshot of the service loop's execution statistics.
+    pub fn stats(&self) -> Stats {
+        self.status.counters.snapshot()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use std::time::Duration;
+
+    struct Forever;
+    impl Cancellable for Forever {
+        type Error = ();
+        fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+            std::thread::sleep(Duration::from_millis(1));
+            Ok(LoopState::Continue)
+        }
+    }
+
+    #[test]
+    fn halves_work_independently() {
+        let (waiter, controller) = Forever.spawn().split();
+        assert!(controller.wait_ready(Duration::from_secs(1)));
+
+        let waiting = std::thread::spawn(move || waiter.join());
+        let other = controller.clone();
+        drop(controller);
+        assert!(!other.has_exited());
+        other.cancel();
+        assert!(waiting.join().unwrap().is_success());
+        assert!(other.has_exited());
+    }
+}
@@ -0,0 +1,218 @@
+use super::Context;
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Whether a service loop currently has work to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Activity {
+    /// The last iteration found work to do.
+    #[default]
+    Busy,
+    /// The last iteration found no work to do.
+    Idle,
+}
+
+/// A snapshot of a service loop's execution statistics.
+///
+/// Obtained through [`Handle::stats`](crate::Handle::stats).
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct Stats {
+    /// The number of iterations that have completed, not counting warmup iterations.
+    pub iterations: u64,
+    /// How many of those iterations were [idle](Activity::Idle).
+    pub idle_iterations: u64,
+    /// How many of those iterations took longer than their
+    /// [budget](crate::ServiceBuilder::target_rate).
+    pub over_budget_iterations: u64,
+    /// The number of [warmup](crate::ServiceBuilder::warmup) iterations that have completed.
+    ///
+    /// These are not included in any of the other counts or timings.
+    pub warmup_iterations: u64,
+    /// The total time spent in the service's iterations.
+    pub iteration_time: Duration,
+    /// What the most recent iteration reported.
+    pub activity: Activity,
+    /// The number of work items the service most recently reported as waiting.
+    ///
+    /// See [`Context::set_queue_depth`].
+    pub queued: usize,
+    /// The slowest iteration so far, not counting warmup iterations.
+    pub slowest: Option<SlowIteration>,
+    /// When the most recent iteration (warmup or not) completed.
+    ///
+    /// A loop whose last iteration completed long ago is either stuck in an iteration or not
+    /// getting to run at all.
+    pub last_iteration: Option<Instant>,
+    /// The recent number of iterations per second, not counting warmup iterations.
+    ///
+    /// This is an exponential moving average, so it follows changes in the loop's pace smoothly,
+    /// and decays towards zero while the loop is stuck or not running. See
+    /// [`ServiceBuilder::rate_half_life`](crate::ServiceBuilder::rate_half_life).
+    pub iteration_rate: f64,
+    /// The recent number of iterations per second that returned an error, not counting warmup
+    /// iterations.
+    ///
+    /// Like [`Stats::iteration_rate`], this is an exponential moving average.
+    pub error_rate: f64,
+}
+
+/// The slowest iteration a service loop has run, as reported in [`Stats::slowest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SlowIteration {
+    /// How long the iteration took.
+    pub took: Duration,
+    /// What the iteration was working on, as set with [`Context::set_tag`].
+    pub tag: Option<String>,
+}
+
+/// A sampled iteration, as passed to the callback given to
+/// [`ServiceBuilder::sample`](crate::ServiceBuilder::sample).
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Sample<'a> {
+    /// The iteration's position among the loop's non-warmup iterations, starting at 1.
+    pub iteration: u64,
+    /// How long the iteration took.
+    pub took: Duration,
+    /// What the iteration reported as doing.
+    pub activity: Activity,
+    /// What the iteration was working on, as set with [`Context::set_tag`].
+    pub tag: Option<&'a str>,
+}
+
+/// How quickly [`Stats::iteration_rate`] and [`Stats::error_rate`] forget the past by default.
+pub(crate) const DEFAULT_RATE_HALF_LIFE: Duration = Duration::from_secs(10);
+
+/// Exponentially decaying counts of iterations and errors, from which recent rates are derived.
+///
+/// Every event adds one to its count, and counts halve every half-life. Scaled by the decay
+/// constant, a count is then a moving average of its events per second.
+struct Rates {
+    /// The decay constant, `ln 2 / half-life`, per second.
+    decay: f64,
+    iterations: f64,
+    errors: f64,
+    at: Option<Instant>,
+}
+
+impl Default for Rates {
+    fn default() -> Self {
+        Rates {
+            decay: std::f64::consts::LN_2 / DEFAULT_RATE_HALF_LIFE.as_secs_f64(),
+            iterations: 0.0,
+            errors: 0.0,
+            at: None,
+        }
+    }
+}
+
+impl Rates {
+    /// Decay the counts up to `now`.
+    fn advance(&mut self, now: Instant) {
+        if let Some(at) = self.at {
+            let factor = (-self.decay * now.saturating_duration_since(at).as_secs_f64()).exp();
+            self.iterations *= factor;
+            self.errors *= factor;
+        }
+        self.at = Some(now);
+    }
+
+    fn per_second(&self, count: f64, now: Instant) -> f64 {
+        let elapsed = self.at.map_or(0.0, |at| now.saturating_duration_since(at).as_secs_f64());
+        count * (-self.decay * elapsed).exp() * self.decay
+    }
+}
+
+/// The live counters behind [`Stats`], updated by the service thread after every iteration.
+#[derive(Default)]
+pub(crate) struct Counters {
+    iterations: AtomicU64,
+    idle_iterations: AtomicU64,
+    over_budget_iterations: AtomicU64,
+    warmup_iterations: AtomicU64,
+    iteration_nanos: AtomicU64,
+    idle: AtomicBool,
+    queued: AtomicUsize,
+    slowest: Mutex<Option<SlowIteration>>,
+    last_iteration: Mutex<Option<Instant>>,
+    rates: Mutex<Rates>,
+}
+
+impl Counters {
+    /// Make the iteration and error rates forget the past with the given half-life.
+    pub(crate) fn set_half_life(&self, half_life: Duration) {
+        self.rates.lock().unwrap().decay = std::f64::consts::LN_2 / half_life.as_secs_f64();
+    }
+
+    /// Record that an iteration completed after `took`, with what it reported through `ctx`, and
+    /// whether it returned an error.
+    pub(crate) fn record(&self, ctx: &Context, took: Duration, failed: bool) {
+        *self.last_iteration.lock().unwrap() = Some(Instant::now());
+        self.queued.store(ctx.queue_depth(), Ordering::Relaxed);
+        let idle = ctx.activity() == Activity::Idle;
+        self.idle.store(idle, Ordering::Relaxed);
+        if ctx.is_warmup() {
+            self.warmup_iterations.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        if idle {
+            self.idle_iterations.fetch_add(1, Ordering::Relaxed);
+        }
+        if ctx.budget().map(|b| took > b).unwrap_or(false) {
+            self.over_budget_iterations.fetch_add(1, Ordering::Relaxed);
+        }
+        let mut slowest = self.slowest.lock().unwrap();
+        if slowest.as_ref().map(|s| took > s.took).unwrap_or(true) {
+            *slowest = Some(SlowIteration {
+                took,
+                tag: ctx.tag().map(String::from),
+            });
+        }
+        drop(slowest);
+        let mut rates = self.rates.lock().unwrap();
+        rates.advance(Instant::now());
+        rates.iterations += 1.0;
+        if failed {
+            rates.errors += 1.0;
+        }
+        drop(rates);
+        let nanos = u64::try_from(took.as_nanos()).unwrap_or(u64::MAX);
+        self.iteration_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.iterations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns `true` if the most recent iteration was idle with nothing left in the queue.
+    pub(crate) fn is_quiescent(&self) -> bool {
+        self.idle.load(Ordering::Relaxed) && self.queued.load(Ordering::Relaxed) == 0
+    }
+
+    pub(crate) fn activity(&self) -> Activity {
+        if self.idle.load(Ordering::Relaxed) {
+            Activity::Idle
+        } else {
+            Activity::Busy
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> Stats {
+        let now = Instant::now();
+        let rates = self.rates.lock().unwrap();
+        Stats {
+            iterations: self.iterations.load(Ordering::Relaxed),
+            idle_iterations: self.idle_iterations.load(Ordering::Relaxed),
+            over_budget_iterations: self.over_budget_iterations.load(Ordering::Relaxed),
+            warmup_iterations: self.warmup_iterations.load(Ordering::Relaxed),
+            iteration_time: Duration::from_nanos(self.iteration_nanos.load(Ordering::Relaxed)),
+            activity: self.activity(),
+            queued: self.queued.load(Ordering::Relaxed),
+            slowest: self.slowest.lock().unwrap().clone(),
+            last_iteration: *self.last_iteration.lock().unwrap(),
+            iteration_rate: rates.per_second(rates.iterations, now),
+            error_rate: rates.per_second(rates.errors, now),
+        }
+    }
+}
@@ -0,0 +1,468 @@
+use super::{
+    Backoff, Cancellable, Context, ErrorDisposition, ExitReason, ExitStatus, Finish, Handle,
+    LoopState, MemberStatus, Resources,
+};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// When a [`Supervisor`] should restart a service that has exited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartPolicy {
+    /// Never restart the service.
+    Never,
+    /// Restart the service if it returned an error.
+    OnError,
+    /// Restart the service if it panicked.
+    OnPanic,
+    /// Restart the service whenever it exits, even if it exited cleanly.
+    Always,
+}
+
+impl RestartPolicy {
+    fn restarts<E>(self, status: &ExitStatus<E>) -> bool {
+        matches!(
+            (self, status),
+            (RestartPolicy::Always, _)
+                | (RestartPolicy::OnError, ExitStatus::Failed(_))
+                | (RestartPolicy::OnPanic, ExitStatus::Panicked(_))
+        )
+    }
+}
+
+/// The error a [`Supervisor`] reports when it gives up on a service that keeps exiting.
+///
+/// See [`Supervisor::restart_intensity`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestartsExceeded {
+    /// The service that was given up on.
+    pub service: String,
+    /// How many restarts the service was allowed.
+    pub restarts: usize,
+    /// The window in which the service was allowed that many restarts.
+    pub within: Duration,
+}
+
+impl fmt::Display for RestartsExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "service {} exceeded {} restarts within {:?}",
+            self.service, self.restarts, self.within
+        )
+    }
+}
+
+impl Error for RestartsExceeded {}
+
+/// A set of services that are restarted according to their [`RestartPolicy`] when they exit.
+///
+/// Each service is built by a factory, which is called again whenever the service needs to be
+/// restarted. The supervisor is itself a service: once spawned, it waits for its services to
+/// exit, restarts the ones whose policy says so, and finishes once none of them are left
+/// running. Cancelling the supervisor cancels all of its services, and waits for them to exit.
+/// The supervisor's [output](Finish) is the name and final exit status of every service, in the
+/// order they stopped for good.
+///
+/// ```
+/// # use minion::*;
+/// # use std::time::Duration;
+/// # struct Poller;
+/// # impl Cancellable for Poller {
+/// #     type Error = ();
+/// #     fn for_each(&mut self) -> Result<LoopState, ()> { Ok(LoopState::Continue) }
+/// # }
+/// # struct Flaky;
+/// # impl Cancellable for Flaky {
+/// #     type Error = ();
+/// #     fn for_each(&mut self) -> Result<LoopState, ()> { Err(()) }
+/// # }
+/// let backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(30));
+/// let mut sup = Supervisor::new().restart_backoff(backoff.jitter(Jitter::Full));
+/// sup.supervise("poller", RestartPolicy::OnPanic, || Poller);
+/// sup.supervise("flaky", RestartPolicy::Never, || Flaky);
+/// let h = sup.builder().name("supervisor").spawn_finish();
+///
+/// // ... until it is time to shut down
+/// h.cancel();
+/// for (name, status) in h.wait().unwrap() {
+///     println!("{} exited: {:?}", name, status.is_success());
+/// }
+/// ```
+pub struct Supervisor<E> {
+    services: Vec<Supervised<E>>,
+    backoff: Option<Backoff>,
+    intensity: Option<(usize, Duration)>,
+    escalate: bool,
+    exits: mpsc::Receiver<Option<usize>>,
+    notify: mpsc::Sender<Option<usize>>,
+    stopped: Vec<MemberStatus<E>>,
+    resources: Resources,
+}
+
+/// Spawns a fresh instance of a supervised service, with the given start delay.
+type Factory<E> = Box<dyn FnMut(&str, Option<Duration>) -> Handle<E> + Send>;
+
+struct Supervised<E> {
+    name: String,
+    policy: RestartPolicy,
+    factory: Factory<E>,
+    handle: Option<Handle<E>>,
+    backoff: Option<Backoff>,
+    started: Instant,
+    restarts: VecDeque<Instant>,
+}
+
+impl<E> Default for Supervisor<E> {
+    fn default() -> Self {
+        let (notify, exits) = mpsc::channel();
+        Supervisor {
+            services: Vec::new(),
+            backoff: None,
+            intensity: None,
+            escalate: false,
+            exits,
+            notify,
+            stopped: Vec::new(),
+            resources: Resources::new(),
+        }
+    }
+}
+
+impl<E: Send + 'static> Supervisor<E> {
+    /// Create a supervisor with no services.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wait according to `backoff` before each restarted service starts its first iteration.
+    ///
+    /// Without a backoff, services are restarted right away, so one that keeps failing as soon
+    /// as it starts ends up in a hot crash loop, hammering whatever it talks to. With one, each
+    /// consecutive restart of the same service waits longer (and with jitter, by a random
+    /// amount), and once the backoff [gives up](Backoff::max_retries), so does the supervisor,
+    /// and the service stays down. A service that stays up for at least the backoff's maximum
+    /// delay is considered healthy again, and its next restart starts over from the base delay.
+    ///
+    /// Every service gets its own copy of `backoff`, so this must be set before any services are
+    /// [supervised](Supervisor::supervise). The delay is cut short if the supervisor is
+    /// cancelled. See [`SpawnOptions::start_after`](crate::SpawnOptions::start_after).
+    pub fn restart_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = Some(backoff);
+        self
+    }
+
+    /// Give up on a service once it would need more than `restarts` restarts within `within`.
+    ///
+    /// A service that is restarted over and over usually points at a fatal problem, such as a
+    /// misconfiguration, that restarting will never fix, and that a supervisor restarting it
+    /// indefinitely would only hide. Once a service exceeds this budget, it stays down, and the
+    /// supervisor reports a [`RestartsExceeded`] error, which is kept among its
+    /// [recent errors](crate::ServiceBuilder::keep_errors). The supervisor's other services keep
+    /// running, unless the supervisor [escalates](Supervisor::escalate).
+    pub fn restart_intensity(mut self, restarts: usize, within: Duration) -> Self {
+        self.intensity = Some((restarts, within));
+        self
+    }
+
+    /// Cancel every service, and fail the supervisor with [`RestartsExceeded`], once any service
+    /// exceeds the [restart intensity](Supervisor::restart_intensity).
+    ///
+    /// This lets a supervisor that is itself supervised hand the problem up the tree.
+    pub fn escalate(mut self) -> Self {
+        self.escalate = true;
+        self
+    }
+
+    /// The resources that every supervised service is [given](crate::ServiceBuilder::resources).
+    ///
+    /// Resources can be added at any time, and are seen by services that are already running.
+    pub fn resources(&self) -> &Resources {
+        &self.resources
+    }
+
+    /// Spawn the service produced by `factory` under the given name, and restart it according
+    /// to `policy` whenever it exits.
+    pub fn supervise<N, F, S>(&mut self, name: N, policy: RestartPolicy, mut factory: F)
+    where
+        N: Into<String>,
+        F: FnMut() -> S + Send + 'static,
+        S: Cancellable<Error = E> + Send + 'static,
+    {
+        let resources = self.resources.clone();
+        let factory: Factory<E> = Box::new(move |name, delay| {
+            let builder = factory().builder().name(name).resources(&resources);
+            match delay {
+                Some(delay) => builder.start_after(delay).spawn(),
+                None => builder.spawn(),
+            }
+        });
+        self.push(name.into(), policy, factory);
+    }
+
+    /// Spawn the service produced by `factory` with the settings in `spec`, and restart it
+    /// according to the spec's restart policy whenever it exits.
+    ///
+    /// The service is named after the spec, or `"unnamed"` if the spec has no name, and is
+    /// never restarted if the spec has no restart policy. Every instance of the service gets the
+    /// spec's settings. See [`ServiceBuilder::spec`](crate::ServiceBuilder::spec).
+    #[cfg(feature = "serde")]
+    pub fn supervise_spec<F, S>(&mut self, spec: &crate::ServiceSpec, mut factory: F)
+    where
+        F: FnMut() -> S + Send + 'static,
+        S: Cancellable<Error = E> + Send + 'static,
+    {
+        let name = spec.name.clone().unwrap_or_else(|| "unnamed".to_string());
+        let policy = spec.restart.unwrap_or(RestartPolicy::Never);
+        let spec = spec.clone();
+        let resources = self.resources.clone();
+        let factory: Factory<E> = Box::new(move |name, delay| {
+            let builder = factory().builder().spec(&spec).name(name).resources(&resources);
+            match delay {
+                Some(delay) => builder.start_after(delay).spawn(),
+                None => builder.spawn(),
+            }
+        });
+        self.push(name, policy, factory);
+    }
+
+    fn push(&mut self, name: String, policy: RestartPolicy, factory: Factory<E>) {
+        self.services.push(Supervised {
+            name,
+            policy,
+            factory,
+            handle: None,
+            backoff: self.backoff.clone(),
+            started: Instant::now(),
+            restarts: VecDeque::new(),
+        });
+        self.start(self.services.len() - 1, None);
+    }
+
+    /// Spawn a fresh instance of the `i`th service.
+    fn start(&mut self, i: usize, delay: Option<Duration>) {
+        let s = &mut self.services[i];
+        let handle = (s.factory)(&s.name, delay);
+        s.started = Instant::now();
+        let notify = self.notify.clone();
+        handle.on_exit(move |_| {
+            let _ = notify.send(Some(i));
+        });
+        s.handle = Some(handle);
+    }
+
+    fn running(&self) -> usize {
+        self.services.iter().filter(|s| s.handle.is_some()).count()
+    }
+}
+
+impl<E: Send + 'static> Cancellable for Supervisor<E> {
+    type Error = RestartsExceeded;
+
+    fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+        self.for_each_ctx(&mut Context::new())
+    }
+
+    fn for_each_ctx(&mut self, ctx: &mut Context) -> Result<LoopState, Self::Error> {
+        if self.running() == 0 {
+            return Ok(LoopState::Break);
+        }
+        let i = match self.exits.recv() {
+            Ok(Some(i)) => i,
+            // woken up because we were cancelled
+            Ok(None) | Err(_) => return Ok(LoopState::Continue),
+        };
+        let s = &mut self.services[i];
+        let handle = match s.handle.take() {
+            Some(handle) => handle,
+            None => return Ok(LoopState::Continue),
+        };
+        let status = handle.join();
+        let mut restart = s.policy.restarts(&status) && !ctx.canceller().is_cancelled();
+        if let (true, Some((restarts, within))) = (restart, self.intensity) {
+            while s.restarts.front().is_some_and(|t| t.elapsed() > within) {
+                s.restarts.pop_front();
+            }
+            if s.restarts.len() >= restarts {
+                self.stopped.push((s.name.clone(), status));
+                return Err(RestartsExceeded {
+                    service: s.name.clone(),
+                    restarts,
+                    within,
+                });
+            }
+            s.restarts.push_back(Instant::now());
+        }
+        let mut delay = None;
+        if let (true, Some(backoff)) = (restart, s.backoff.as_mut()) {
+            if s.started.elapsed() >= backoff.max() {
+                backoff.reset();
+            }
+            delay = backoff.next_delay();
+            restart = delay.is_some();
+        }
+        if restart {
+            self.start(i, delay);
+        } else {
+            self.stopped.push((s.name.clone(), status));
+        }
+        Ok(LoopState::Continue)
+    }
+
+    fn on_error(&mut self, err: Self::Error) -> ErrorDisposition<Self::Error> {
+        if self.escalate {
+            ErrorDisposition::Abort(err)
+        } else {
+            ErrorDisposition::Continue
+        }
+    }
+
+    fn release_on_cancel(&self) -> Option<Box<dyn FnOnce() + Send>> {
+        let notify = self.notify.clone();
+        Some(Box::new(move || {
+            let _ = notify.send(None);
+        }))
+    }
+
+    fn on_exit(&mut self, _: ExitReason) {
+        for s in &self.services {
+            if let Some(ref h) = s.handle {
+                h.cancel();
+            }
+        }
+        for s in &mut self.services {
+            if let Some(h) = s.handle.take() {
+                self.stopped.push((s.name.clone(), h.join()));
+            }
+        }
+    }
+}
+
+impl<E: Send + 'static> Finish for Supervisor<E> {
+    type Output = Vec<MemberStatus<E>>;
+
+    fn finish(self) -> Self::Output {
+        self.stopped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    struct Failing(usize);
+    impl Cancellable for Failing {
+        type Error = usize;
+        fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+            Err(self.0)
+        }
+    }
+
+    struct Stuck;
+    impl Cancellable for Stuck {
+        type Error = usize;
+        fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+            std::thread::sleep(Duration::from_millis(1));
+            Ok(LoopState::Continue)
+        }
+    }
+
+    #[test]
+    fn it_restarts_by_policy() {
+        let spawned = Arc::new(AtomicUsize::new(0));
+        let n = spawned.clone();
+        let mut sup = Supervisor::new();
+        sup.supervise("retried", RestartPolicy::OnError, move || {
+            Failing(n.fetch_add(1, Ordering::SeqCst))
+        });
+        sup.supervise("given up", RestartPolicy::OnPanic, || Failing(42));
+        let h = sup.builder().spawn_finish();
+
+        while spawned.load(Ordering::SeqCst) < 5 {
+            std::thread::yield_now();
+        }
+        h.cancel();
+        let stopped = h.wait().unwrap();
+        assert_eq!(stopped.len(), 2);
+        let given_up = stopped.iter().find(|(n, _)| n == "given up").unwrap();
+        assert!(matches!(given_up.1, ExitStatus::Failed(42)));
+    }
+
+    #[test]
+    fn services_get_the_supervisors_resources() {
+        struct Counting;
+        impl Cancellable for Counting {
+            type Error = ();
+            fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+                self.for_each_ctx(&mut Context::new())
+            }
+            fn for_each_ctx(&mut self, ctx: &mut Context) -> Result<LoopState, Self::Error> {
+                ctx.resource::<AtomicUsize>().ok_or(())?.fetch_add(1, Ordering::SeqCst);
+                Ok(LoopState::Break)
+            }
+        }
+
+        let mut sup = Supervisor::new();
+        sup.resources().insert(AtomicUsize::new(0));
+        let shared = sup.resources().get::<AtomicUsize>().unwrap();
+        sup.supervise("a", RestartPolicy::Never, || Counting);
+        sup.supervise("b", RestartPolicy::Never, || Counting);
+        let stopped = sup.builder().spawn_finish().wait().unwrap();
+        assert!(stopped.iter().all(|(_, s)| s.is_success()));
+        assert_eq!(shared.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn it_finishes_once_nothing_is_left() {
+        let mut sup = Supervisor::new();
+        sup.supervise("once", RestartPolicy::Never, || Failing(1));
+        sup.supervise("twice", RestartPolicy::OnPanic, || Failing(2));
+        let stopped = sup.builder().spawn_finish().wait().unwrap();
+        let mut names: Vec<_> = stopped.iter().map(|(n, _)| &**n).collect();
+        names.sort_unstable();
+        assert_eq!(names, ["once", "twice"]);
+    }
+
+    #[test]
+    fn restarts_back_off() {
+        let spawned = Arc::new(AtomicUsize::new(0));
+        let n = spawned.clone();
+        let ms = Duration::from_millis;
+        let backoff = Backoff::new(ms(20), ms(1000)).max_retries(3);
+        let mut sup = Supervisor::new().restart_backoff(backoff);
+        sup.supervise("crashy", RestartPolicy::OnError, move || {
+            Failing(n.fetch_add(1, Ordering::SeqCst))
+        });
+
+        let start = std::time::Instant::now();
+        let stopped = sup.builder().spawn_finish().wait().unwrap();
+        // 20ms, 40ms, and 80ms between the four attempts, and then the backoff gives up
+        assert!(start.elapsed() >= ms(140));
+        assert_eq!(spawned.load(Ordering::SeqCst), 4);
+        assert!(matches!(stopped[0].1, ExitStatus::Failed(3)));
+    }
+
+    #[test]
+    fn it_gives_up_on_crash_loops() {
+        let hour = Duration::from_secs(3600);
+        let mut sup = Supervisor::new().restart_intensity(3, hour);
+        sup.supervise("crashy", RestartPolicy::OnError, || Failing(1));
+        sup.supervise("fine", RestartPolicy::Never, || Failing(2));
+        let h = sup.builder().keep_errors(1).spawn_finish();
+        assert!(h.status.wait_exit(Duration::from_secs(5)));
+        let errors = h.recent_errors();
+        assert!(errors[0].message.contains("\"crashy\""), "{:?}", errors);
+        assert_eq!(h.wait().unwrap().len(), 2);
+
+        let mut sup = Supervisor::new().restart_intensity(3, hour).escalate();
+        sup.supervise("crashy", RestartPolicy::OnError, || Failing(1));
+        sup.supervise("stuck", RestartPolicy::Never, || Stuck);
+        let err = sup.builder().spawn_finish().wait().unwrap_err();
+        assert_eq!(err.service, "crashy");
+        assert_eq!(err.restarts, 3);
+    }
+}
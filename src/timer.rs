@@ -0,0 +1,83 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::thread;
+use std::time::Instant;
+
+/// A single background thread that runs deferred tasks once they are due.
+///
+/// The thread is started the first time a task is scheduled, and then lives for the rest of the
+/// process, so that any number of deferred tasks cost one thread between them.
+#[derive(Default)]
+struct Timer {
+    queue: Mutex<BinaryHeap<Entry>>,
+    cvar: Condvar,
+}
+
+struct Entry {
+    at: Instant,
+    task: Box<dyn FnOnce() + Send>,
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.at == other.at
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // reversed, so that the heap yields the earliest entry first
+        other.at.cmp(&self.at)
+    }
+}
+
+impl Timer {
+    fn run(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            match queue.peek().map(|e| e.at) {
+                Some(at) if at <= now => {
+                    let entry = queue.pop().expect("just peeked");
+                    drop(queue);
+                    // a panicking task must not take every other deferred task down with it
+                    let _ = panic::catch_unwind(AssertUnwindSafe(entry.task));
+                    queue = self.queue.lock().unwrap();
+                }
+                Some(at) => queue = self.cvar.wait_timeout(queue, at - now).unwrap().0,
+                None => queue = self.cvar.wait(queue).unwrap(),
+            }
+        }
+    }
+}
+
+/// Run `task` on the shared timer thread once `at` has passed.
+pub(crate) fn schedule<F: FnOnce() + Send + 'static>(at: Instant, task: F) {
+    static TIMER: OnceLock<Timer> = OnceLock::new();
+    let mut started = false;
+    let timer = TIMER.get_or_init(|| {
+        started = true;
+        Timer::default()
+    });
+    if started {
+        thread::Builder::new()
+            .name(String::from("minion-timer"))
+            .spawn(move || timer.run())
+            .expect("failed to spawn the timer thread");
+    }
+    timer.queue.lock().unwrap().push(Entry {
+        at,
+        task: Box::new(task),
+    });
+    timer.cvar.notify_one();
+}
@@ -0,0 +1,166 @@
+use super::Canceller;
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// A source of cancellation that a service loop can check between iterations.
+///
+/// This is implemented by [`Canceller`], and by [`StaticCanceller`] for when allocating a
+/// [`Canceller`] is not an option. See [`Cancellable::run_with`](crate::Cancellable::run_with).
+pub trait CancelToken {
+    /// Returns `true` if the service loop should stop accepting new work.
+    fn is_cancelled(&self) -> bool;
+
+    /// Returns an error if the service loop has been cancelled.
+    ///
+    /// This makes it easy to abort long-running work inside a single iteration early, using
+    /// `?` or anything that stops at the first error. In particular, it fits data-parallel work
+    /// with rayon's `try_for_each`, which stops handing out items to its worker threads once one
+    /// of them returns an error:
+    ///
+    /// ```ignore
+    /// use rayon::prelude::*;
+    ///
+    /// let exit = h.canceller();
+    /// rows.par_chunks(1024).try_for_each(|chunk| {
+    ///     exit.check()?;
+    ///     process(chunk);
+    ///     Ok::<_, Cancelled>(())
+    /// })?;
+    /// ```
+    fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The error returned by [`CancelToken::check`] once the service loop has been cancelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("service loop was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+impl From<Cancelled> for io::Error {
+    fn from(c: Cancelled) -> Self {
+        io::Error::new(io::ErrorKind::Interrupted, c)
+    }
+}
+
+impl CancelToken for Canceller {
+    fn is_cancelled(&self) -> bool {
+        !self.keep_running()
+    }
+}
+
+/// A cancellation flag backed by a `static` [`AtomicBool`].
+///
+/// Unlike [`Canceller`], creating a `StaticCanceller` does not allocate, which makes it suitable
+/// for embedded and real-time contexts. The flag is `true` once cancelled.
+///
+/// ```
+/// # use minion::*;
+/// # use std::sync::atomic::AtomicBool;
+/// # struct Service;
+/// # impl Cancellable for Service {
+/// #     type Error = ();
+/// #     fn for_each(&mut self) -> Result<LoopState, ()> { Ok(LoopState::Continue) }
+/// # }
+/// static STOP: AtomicBool = AtomicBool::new(false);
+///
+/// let exit = StaticCanceller::new(&STOP);
+/// exit.cancel();
+/// Service.run_with(&exit).unwrap();
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct StaticCanceller {
+    cancelled: &'static AtomicBool,
+}
+
+impl StaticCanceller {
+    /// Create a canceller backed by `flag`.
+    pub const fn new(flag: &'static AtomicBool) -> Self {
+        StaticCanceller { cancelled: flag }
+    }
+
+    /// Cancel any service loop running with this canceller.
+    ///
+    /// Returns `true` if this call is what cancelled it. See [`Canceller::cancel`].
+    pub fn cancel(&self) -> bool {
+        !self.cancelled.swap(true, Ordering::Relaxed)
+    }
+}
+
+impl CancelToken for StaticCanceller {
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    static STOP: AtomicBool = AtomicBool::new(false);
+
+    struct CancelAfter(usize, StaticCanceller);
+
+    impl Cancellable for CancelAfter {
+        type Error = ();
+        fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+            self.0 -= 1;
+            if self.0 == 0 {
+                self.1.cancel();
+            }
+            Ok(LoopState::Continue)
+        }
+    }
+
+    #[test]
+    fn it_runs_until_cancelled() {
+        let exit = StaticCanceller::new(&STOP);
+        let mut s = CancelAfter(3, exit);
+        s.run_with(&exit).unwrap();
+        assert_eq!(s.0, 0);
+        assert!(STOP.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn check_aborts_chunked_work() {
+        use std::sync::atomic::AtomicUsize;
+
+        let exit = Canceller::new();
+        let processed = AtomicUsize::new(0);
+        let data: Vec<usize> = (0..1000).collect();
+        // stand-in for a parallel iterator: a few threads working through chunks
+        let r: Result<Vec<()>, Cancelled> = std::thread::scope(|s| {
+            let workers: Vec<_> = data
+                .chunks(250)
+                .map(|part| {
+                    let (exit, processed) = (&exit, &processed);
+                    s.spawn(move || {
+                        part.chunks(10).try_for_each(|_| {
+                            exit.check()?;
+                            if processed.fetch_add(1, Ordering::SeqCst) == 5 {
+                                exit.cancel();
+                            }
+                            Ok(())
+                        })
+                    })
+                })
+                .collect();
+            workers.into_iter().map(|w| w.join().unwrap()).collect()
+        });
+        assert_eq!(r, Err(Cancelled));
+        assert!(processed.load(Ordering::SeqCst) < 100);
+    }
+}
@@ -0,0 +1,128 @@
+use super::{Cancellable, LoopState};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A service whose iterations each either commit or roll back a unit of work.
+///
+/// Every iteration calls `work` to begin a transaction, which produces some staged state (or
+/// `None` if there was nothing to do). If the service loop was not cancelled while `work` ran, the
+/// staged state is passed to `commit`. If it was, the state is passed to `rollback` instead, and
+/// the loop exits. This gives the common delivery patterns a fixed shape: acknowledging a message
+/// in `commit` only after it has been processed gives at-least-once delivery, while
+/// acknowledging it in `work` and undoing any partial effects in `rollback` gives at-most-once.
+///
+/// ```
+/// # use minion::*;
+/// use std::collections::VecDeque;
+/// use std::sync::{Arc, Mutex};
+///
+/// let queue = Arc::new(Mutex::new(VecDeque::from(vec![1, 2, 3])));
+/// let (q, r) = (queue.clone(), queue.clone());
+/// let h = Transactional::new(
+///     move || Ok::<_, ()>(q.lock().unwrap().pop_front()),
+///     |n| {
+///         println!("processed {}", n);
+///         Ok(())
+///     },
+///     move |n| {
+///         // put the item back so it is not lost
+///         r.lock().unwrap().push_front(n);
+///         Ok(())
+///     },
+/// )
+/// .builder()
+/// .spawn();
+/// h.cancel();
+/// h.wait().unwrap();
+/// ```
+///
+/// Cancellation is only observed if the service is spawned through
+/// [`Cancellable::spawn`] or a [`ServiceBuilder`](crate::ServiceBuilder), since it is tracked
+/// through [`Cancellable::release_on_cancel`]. A [drain](crate::Canceller::drain) lets the
+/// current transaction commit as usual.
+pub struct Transactional<W, C, R> {
+    work: W,
+    commit: C,
+    rollback: R,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl<W, C, R, T, E> Transactional<W, C, R>
+where
+    W: FnMut() -> Result<Option<T>, E>,
+    C: FnMut(T) -> Result<(), E>,
+    R: FnMut(T) -> Result<(), E>,
+{
+    /// Create a service that begins a transaction with `work`, and then ends it with either
+    /// `commit` or `rollback`.
+    pub fn new(work: W, commit: C, rollback: R) -> Self {
+        Transactional {
+            work,
+            commit,
+            rollback,
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+impl<W, C, R, T, E> Cancellable for Transactional<W, C, R>
+where
+    W: FnMut() -> Result<Option<T>, E>,
+    C: FnMut(T) -> Result<(), E>,
+    R: FnMut(T) -> Result<(), E>,
+{
+    type Error = E;
+
+    fn for_each(&mut self) -> Result<LoopState, Self::Error> {
+        let staged = match (self.work)()? {
+            Some(staged) => staged,
+            None => return Ok(LoopState::Idle),
+        };
+        if self.cancelled.load(Ordering::SeqCst) {
+            (self.rollback)(staged)?;
+            return Ok(LoopState::Break);
+        }
+        (self.commit)(staged)?;
+        Ok(LoopState::Continue)
+    }
+
+    fn release_on_cancel(&self) -> Option<Box<dyn FnOnce() + Send>> {
+        let cancelled = self.cancelled.clone();
+        Some(Box::new(move || cancelled.store(true, Ordering::SeqCst)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn cancellation_mid_transaction_rolls_back() {
+        let exit = Canceller::new();
+        let (committed_tx, committed) = mpsc::channel();
+        let (rolled_back_tx, rolled_back) = mpsc::channel();
+
+        let mut next = 0;
+        let c = exit.clone();
+        let h = Transactional::new(
+            move || {
+                next += 1;
+                if next == 3 {
+                    // cancelled after the transaction has begun
+                    c.cancel();
+                }
+                Ok::<_, ()>(Some(next))
+            },
+            move |n| committed_tx.send(n).map_err(|_| ()),
+            move |n| rolled_back_tx.send(n).map_err(|_| ()),
+        )
+        .builder()
+        .canceller(exit)
+        .spawn();
+        h.wait().unwrap();
+
+        assert_eq!(committed.try_iter().collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(rolled_back.try_iter().collect::<Vec<_>>(), [3]);
+    }
+}
@@ -0,0 +1,250 @@
+//! Seamless self-upgrades by handing listening sockets and state to a freshly `exec`ed binary.
+//!
+//! A daemon that wants to upgrade itself in place stops its services, describes what the new
+//! binary should take over in an [`Upgrade`], and then [`exec`](Upgrade::exec)s the new binary.
+//! The process keeps its pid, and the listening sockets stay open throughout, so connections that
+//! arrive during the upgrade queue up in the listen backlog rather than being refused. On startup,
+//! the new binary picks everything back up through [`Inherited`]:
+//!
+//! ```no_run
+//! use minion::upgrade::{Inherited, Upgrade};
+//! use minion::*;
+//! use std::{io, net, process::Command};
+//!
+//! # fn serve(l: &net::TcpListener, store: &MemoryCheckpoints) -> Registry { Registry::new() }
+//! # fn upgrade_requested() {}
+//! fn main() -> io::Result<()> {
+//!     let store = MemoryCheckpoints::new();
+//!     let mut inherited = Inherited::from_env();
+//!     inherited.restore_checkpoints(&store)?;
+//!     let listener = match inherited.listener("http") {
+//!         Some(l) => l,
+//!         None => net::TcpListener::bind("0.0.0.0:8080")?,
+//!     };
+//!
+//!     let services = serve(&listener, &store);
+//!     upgrade_requested();
+//!     services.cancel_all();
+//!     services.quiesce(std::time::Duration::from_secs(30));
+//!
+//!     let err = Upgrade::new()
+//!         .fd("http", &listener)
+//!         .checkpoint(&store, "ingest")?
+//!         .exec(Command::new(std::env::current_exe()?).args(std::env::args_os().skip(1)));
+//!     // exec only ever returns if it failed
+//!     Err(err)
+//! }
+//! ```
+//!
+//! Everything is passed to the new binary through its environment, so this only works with
+//! `exec`, and not with spawning the new binary as a child process.
+
+use crate::CheckpointStore;
+use std::collections::HashMap;
+use std::env;
+use std::io;
+use std::net::TcpListener;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+/// The environment variable that names the file descriptors handed over by an [`Upgrade`].
+const FDS_VAR: &str = "MINION_UPGRADE_FDS";
+
+/// The environment variable that holds the state handed over by an [`Upgrade`].
+const STATE_VAR: &str = "MINION_UPGRADE_STATE";
+
+/// The prefix of state entries that hold [checkpoints](Upgrade::checkpoint).
+const CHECKPOINT_PREFIX: &str = "checkpoint:";
+
+/// What to hand over to the next binary when upgrading in place.
+///
+/// See the [module-level documentation](self) for details.
+#[derive(Debug, Default)]
+pub struct Upgrade {
+    fds: Vec<(String, RawFd)>,
+    state: Vec<(String, String)>,
+}
+
+impl Upgrade {
+    /// Describe an upgrade that hands nothing over yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hand over the file descriptor of `socket` (typically a [`TcpListener`]) under `name`.
+    ///
+    /// The descriptor must stay open until [`Upgrade::exec`] is called.
+    pub fn fd<N: Into<String>, F: AsRawFd + ?Sized>(mut self, name: N, socket: &F) -> Self {
+        self.fds.push((name.into(), socket.as_raw_fd()));
+        self
+    }
+
+    /// Hand over an arbitrary string `value` under `name`.
+    pub fn state<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.state.push((name.into(), value.into()));
+        self
+    }
+
+    /// Hand over the checkpoint that `store` holds under `key`, if any.
+    ///
+    /// This is mostly useful for [`MemoryCheckpoints`](crate::MemoryCheckpoints), which would
+    /// otherwise be lost in the upgrade. See [`Inherited::restore_checkpoints`].
+    pub fn checkpoint<S: CheckpointStore + ?Sized>(self, store: &S, key: &str) -> io::Result<Self> {
+        Ok(match store.load(key)? {
+            Some(seq) => self.state(format!("{}{}", CHECKPOINT_PREFIX, key), seq.to_string()),
+            None => self,
+        })
+    }
+
+    /// Replace the current process with `cmd`, handing everything over to it.
+    ///
+    /// This should be called once every service has been cancelled and has exited, since none of
+    /// the process's threads survive the `exec`. It only returns if the handoff or the `exec`
+    /// itself fails, in which case the handed-over descriptors are left open.
+    pub fn exec(self, cmd: &mut Command) -> io::Error {
+        for &(_, fd) in &self.fds {
+            if let Err(e) = inheritable(fd) {
+                return e;
+            }
+        }
+        let fds = self.fds.iter().map(|(name, fd)| (name.clone(), fd.to_string()));
+        cmd.env(FDS_VAR, encode(fds)).env(STATE_VAR, encode(self.state)).exec()
+    }
+}
+
+/// Clear `FD_CLOEXEC` on `fd`, so that it stays open across `exec`.
+fn inheritable(fd: RawFd) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if flags == -1 || unsafe { libc::fcntl(fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Whatever the previous binary handed over through an [`Upgrade`].
+///
+/// See the [module-level documentation](self) for details.
+#[derive(Debug, Default)]
+pub struct Inherited {
+    fds: HashMap<String, RawFd>,
+    state: HashMap<String, String>,
+}
+
+impl Inherited {
+    /// Pick up what the previous binary handed over, if this process was started by an
+    /// [`Upgrade`].
+    ///
+    /// The handoff is removed from the environment, so that it is not passed on to any child
+    /// processes. Call this once, early on; otherwise, this behaves as if nothing was handed over.
+    pub fn from_env() -> Self {
+        let fds = env::var(FDS_VAR).unwrap_or_default();
+        let state = env::var(STATE_VAR).unwrap_or_default();
+        env::remove_var(FDS_VAR);
+        env::remove_var(STATE_VAR);
+        Self::parse(&fds, &state)
+    }
+
+    fn parse(fds: &str, state: &str) -> Self {
+        Inherited {
+            fds: decode(fds)
+                .filter_map(|(name, fd)| Some((name, fd.parse().ok()?)))
+                .collect(),
+            state: decode(state).collect(),
+        }
+    }
+
+    /// Returns `true` if the previous binary handed anything over.
+    pub fn is_upgrade(&self) -> bool {
+        !self.fds.is_empty() || !self.state.is_empty()
+    }
+
+    /// Take ownership of the file descriptor handed over under `name`, if any.
+    ///
+    /// Each descriptor can only be taken once.
+    pub fn take_fd(&mut self, name: &str) -> Option<RawFd> {
+        self.fds.remove(name)
+    }
+
+    /// Take the listener handed over under `name`, if any.
+    pub fn listener(&mut self, name: &str) -> Option<TcpListener> {
+        // the descriptor was handed over to this process, and is owned by no one else
+        self.take_fd(name).map(|fd| unsafe { TcpListener::from_raw_fd(fd) })
+    }
+
+    /// The string handed over under `name`, if any.
+    pub fn state(&self, name: &str) -> Option<&str> {
+        self.state.get(name).map(|s| &**s)
+    }
+
+    /// Save every [checkpoint](Upgrade::checkpoint) that was handed over into `store`.
+    pub fn restore_checkpoints<S: CheckpointStore + ?Sized>(&self, store: &S) -> io::Result<()> {
+        for (name, seq) in &self.state {
+            if let (Some(key), Ok(seq)) = (name.strip_prefix(CHECKPOINT_PREFIX), seq.parse()) {
+                store.save(key, seq)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Encode name-value pairs so that any string survives a trip through the environment.
+fn encode<I: IntoIterator<Item = (String, String)>>(pairs: I) -> String {
+    let hex = |s: &str| s.bytes().map(|b| format!("{:02x}", b)).collect::<String>();
+    let pairs: Vec<_> = pairs
+        .into_iter()
+        .map(|(name, value)| format!("{}={}", hex(&name), hex(&value)))
+        .collect();
+    pairs.join(",")
+}
+
+fn decode(s: &str) -> impl Iterator<Item = (String, String)> + '_ {
+    let unhex = |s: &str| {
+        let bytes: Option<Vec<u8>> = (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+            .collect();
+        String::from_utf8(bytes?).ok()
+    };
+    s.split(',').filter_map(move |pair| {
+        let mut parts = pair.splitn(2, '=');
+        Some((unhex(parts.next()?)?, unhex(parts.next()?)?))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryCheckpoints;
+    use std::os::unix::io::IntoRawFd;
+
+    #[test]
+    fn it_hands_over_listeners_and_state() {
+        let l = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = l.local_addr().unwrap();
+        let store = MemoryCheckpoints::new();
+        store.save("ingest", 42).unwrap();
+
+        let up = Upgrade::new()
+            .fd("http", &l)
+            .state("odd=name,", "a value, with = signs")
+            .checkpoint(&store, "ingest")
+            .unwrap();
+        inheritable(l.as_raw_fd()).unwrap();
+        let fds = up.fds.iter().map(|(n, fd)| (n.clone(), fd.to_string()));
+        let mut inherited = Inherited::parse(&encode(fds), &encode(up.state));
+        // the listener is now owned by the inherited side
+        let _ = l.into_raw_fd();
+
+        assert!(inherited.is_upgrade());
+        assert_eq!(inherited.state("odd=name,"), Some("a value, with = signs"));
+        let restored = MemoryCheckpoints::new();
+        inherited.restore_checkpoints(&restored).unwrap();
+        assert_eq!(restored.load("ingest").unwrap(), Some(42));
+
+        let l = inherited.listener("http").unwrap();
+        assert_eq!(l.local_addr().unwrap(), addr);
+        assert!(inherited.listener("http").is_none());
+        assert!(!Inherited::parse("", "").is_upgrade());
+    }
+}
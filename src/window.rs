@@ -0,0 +1,106 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A daily stretch of time during which a service should not run.
+///
+/// A service spawned with a
+/// [`ServiceBuilder::maintenance_window`](crate::ServiceBuilder::maintenance_window) does not start
+/// any iterations while one of its windows is open, just as if it had been
+/// [paused](crate::Canceller::pause), and picks up again once the window closes. This suits loops
+/// that must stay out of the way during business hours, or during a nightly backup:
+///
+/// ```
+/// # use minion::*;
+/// # use std::time::Duration;
+/// # struct Service;
+/// # impl Cancellable for Service {
+/// #     type Error = ();
+/// #     fn for_each(&mut self) -> Result<LoopState, ()> { Ok(LoopState::Continue) }
+/// # }
+/// let hour = |h: u64| Duration::from_secs(h * 60 * 60);
+/// // backups run from 23:00 to 01:00, US Eastern Standard Time
+/// let backups = MaintenanceWindow::daily(hour(23), hour(1)).utc_offset(-5 * 60 * 60);
+/// let h = Service.builder().maintenance_window(backups).spawn();
+/// # h.cancel();
+/// # h.wait().unwrap();
+/// ```
+///
+/// Times of day are given as the time since midnight, in UTC unless an
+/// [offset](MaintenanceWindow::utc_offset) is given. Note that the offset is fixed, so it does not
+/// follow daylight saving time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaintenanceWindow {
+    from: Duration,
+    to: Duration,
+    offset: i32,
+}
+
+impl MaintenanceWindow {
+    /// A window that opens at time of day `from` and closes at time of day `to` every day.
+    ///
+    /// If `to` is earlier than `from`, the window spans midnight. If the two are the same, the
+    /// window never opens.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either time is a day or more past midnight.
+    pub fn daily(from: Duration, to: Duration) -> Self {
+        assert!(from < DAY && to < DAY, "time of day must be before midnight");
+        MaintenanceWindow { from, to, offset: 0 }
+    }
+
+    /// Interpret the window's times of day in a time zone `secs` seconds ahead of UTC.
+    ///
+    /// Use a negative offset for time zones behind UTC.
+    pub fn utc_offset(mut self, secs: i32) -> Self {
+        self.offset = secs;
+        self
+    }
+
+    /// How much longer the window stays open at `now`, or `None` if it is closed.
+    pub(crate) fn remaining(&self, now: SystemTime) -> Option<Duration> {
+        let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let secs = (since_epoch.as_secs() as i64 + i64::from(self.offset)).rem_euclid(86_400);
+        let tod = Duration::new(secs as u64, since_epoch.subsec_nanos());
+        if self.from <= self.to {
+            if tod >= self.from && tod < self.to {
+                return Some(self.to - tod);
+            }
+        } else if tod >= self.from {
+            return Some(DAY - tod + self.to);
+        } else if tod < self.to {
+            return Some(self.to - tod);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(h: u64, m: u64) -> SystemTime {
+        // some day at midnight UTC
+        UNIX_EPOCH + DAY * 19_000 + Duration::from_secs(h * 3600 + m * 60)
+    }
+
+    #[test]
+    fn windows_open_and_close() {
+        let hour = |h: u64| Duration::from_secs(h * 3600);
+        let business = MaintenanceWindow::daily(hour(9), hour(17));
+        assert_eq!(business.remaining(at(8, 59)), None);
+        assert_eq!(business.remaining(at(16, 0)), Some(hour(1)));
+        assert_eq!(business.remaining(at(17, 0)), None);
+
+        let nightly = MaintenanceWindow::daily(hour(23), hour(1));
+        assert_eq!(nightly.remaining(at(23, 0)), Some(hour(2)));
+        assert_eq!(nightly.remaining(at(0, 30)), Some(hour(1) / 2));
+        assert_eq!(nightly.remaining(at(12, 0)), None);
+
+        // 9-to-5 two hours behind UTC is 11-to-19 UTC
+        let behind = business.utc_offset(-2 * 3600);
+        assert_eq!(behind.remaining(at(10, 0)), None);
+        assert_eq!(behind.remaining(at(18, 0)), Some(hour(1)));
+    }
+}